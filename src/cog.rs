@@ -0,0 +1,578 @@
+//! Cloud Optimized GeoTIFF files served as raster tilesets. Each tile is rendered on request
+//! from the internal tiles of the overview closest to its resolution, reprojected to Web
+//! Mercator and resampled to 256 pixels.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use image::RgbaImage;
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::tags::Tag;
+use tiff::ColorType;
+use tilejson::{tilejson, Bounds, Center};
+
+use crate::errors::{Error, Result};
+use crate::raster::encode_image;
+use crate::tiler::Crs;
+use crate::tiles::{TileMeta, TileSource};
+use crate::utils::{DataFormat, WEB_MERCATOR_EXTENT};
+
+/// Width and height of rendered tiles
+const TILE_SIZE: u32 = 256;
+/// Deepest zoom level tiles are rendered at
+const MAX_ZOOM: u8 = 24;
+
+// GeoTIFF keys
+const GT_MODEL_TYPE: u16 = 1024;
+const GT_RASTER_TYPE: u16 = 1025;
+const GEOGRAPHIC_TYPE: u16 = 2048;
+const PROJECTED_CS_TYPE: u16 = 3072;
+const MODEL_TYPE_GEOGRAPHIC: u16 = 2;
+const RASTER_PIXEL_IS_POINT: u16 = 2;
+
+type TiffDecoder = Decoder<BufReader<File>>;
+
+fn invalid(path: &Path, message: &str) -> Error {
+    Error::InvalidDataFormat(format!("{}: {message}", path.display()))
+}
+
+/// Find the reference system of the file in its GeoTIFF keys
+fn parse_crs(path: &Path, geo_keys: &[u16]) -> Result<(Crs, bool)> {
+    let keys: HashMap<u16, u16> = geo_keys
+        .get(4..)
+        .unwrap_or_default()
+        .chunks_exact(4)
+        // Keys stored in another tag are not numbers this needs
+        .filter(|key| key[1] == 0)
+        .map(|key| (key[0], key[3]))
+        .collect();
+    let pixel_is_point = keys.get(&GT_RASTER_TYPE) == Some(&RASTER_PIXEL_IS_POINT);
+    let crs = match (keys.get(&PROJECTED_CS_TYPE), keys.get(&GEOGRAPHIC_TYPE)) {
+        (Some(3857 | 3785), _) => Crs::WebMercator,
+        (Some(code), _) => return Err(invalid(path, &format!("unsupported CRS EPSG:{code}"))),
+        (None, Some(4326)) => Crs::Wgs84,
+        (None, None) if keys.get(&GT_MODEL_TYPE) == Some(&MODEL_TYPE_GEOGRAPHIC) => Crs::Wgs84,
+        (None, Some(code)) => return Err(invalid(path, &format!("unsupported CRS EPSG:{code}"))),
+        (None, None) => return Err(invalid(path, "missing CRS")),
+    };
+    Ok((crs, pixel_is_point))
+}
+
+/// Read the position of the top-left corner of the image and the size of its pixels
+fn parse_transform(path: &Path, decoder: &mut TiffDecoder) -> Result<([f64; 2], [f64; 2])> {
+    let tag = |decoder: &mut TiffDecoder, tag: Tag| {
+        decoder
+            .find_tag(tag)
+            .ok()
+            .flatten()
+            .and_then(|value| value.into_f64_vec().ok())
+    };
+    if let Some(transform) = tag(decoder, Tag::ModelTransformationTag) {
+        return match transform.as_slice() {
+            [sx, 0.0, _, x, 0.0, sy, _, y, ..] => Ok(([*x, *y], [*sx, -sy])),
+            _ => Err(invalid(path, "rotated images are not supported")),
+        };
+    }
+    match (
+        tag(decoder, Tag::ModelPixelScaleTag),
+        tag(decoder, Tag::ModelTiepointTag),
+    ) {
+        (Some(scale), Some(tiepoint)) if scale.len() >= 2 && tiepoint.len() >= 6 => Ok((
+            [
+                tiepoint[3] - tiepoint[0] * scale[0],
+                tiepoint[4] + tiepoint[1] * scale[1],
+            ],
+            [scale[0], scale[1]],
+        )),
+        _ => Err(invalid(path, "missing georeferencing")),
+    }
+}
+
+/// Number of samples of each pixel for the color types tiles can be rendered from
+fn samples_per_pixel(color_type: ColorType) -> Option<usize> {
+    match color_type {
+        ColorType::Gray(_) | ColorType::Palette(_) => Some(1),
+        ColorType::GrayA(_) => Some(2),
+        ColorType::RGB(_) | ColorType::YCbCr(_) => Some(3),
+        ColorType::RGBA(_) => Some(4),
+        ColorType::Multiband { num_samples, .. } => Some(num_samples as usize),
+        _ => None,
+    }
+}
+
+/// Samples of a decoded chunk, and whether they are bytes that need no stretching
+fn samples(result: DecodingResult) -> (Vec<f64>, bool) {
+    match result {
+        DecodingResult::U8(data) => (data.into_iter().map(f64::from).collect(), true),
+        DecodingResult::U16(data) => (data.into_iter().map(f64::from).collect(), false),
+        DecodingResult::U32(data) => (data.into_iter().map(f64::from).collect(), false),
+        DecodingResult::U64(data) => (data.into_iter().map(|v| v as f64).collect(), false),
+        DecodingResult::F16(data) => (data.into_iter().map(f64::from).collect(), false),
+        DecodingResult::F32(data) => (data.into_iter().map(f64::from).collect(), false),
+        DecodingResult::F64(data) => (data, false),
+        DecodingResult::I8(data) => (data.into_iter().map(f64::from).collect(), false),
+        DecodingResult::I16(data) => (data.into_iter().map(f64::from).collect(), false),
+        DecodingResult::I32(data) => (data.into_iter().map(f64::from).collect(), false),
+        DecodingResult::I64(data) => (data.into_iter().map(|v| v as f64).collect(), false),
+    }
+}
+
+/// An image of the file, the full resolution one or one of its overviews
+#[derive(Clone, Debug)]
+struct Level {
+    /// Index of the image in the file
+    index: usize,
+    width: u32,
+    height: u32,
+    chunk_width: u32,
+    chunk_height: u32,
+    /// Size of a pixel in the units of the reference system
+    resolution: [f64; 2],
+}
+
+/// Pixels of a decoded chunk, converted to RGBA
+struct Chunk {
+    width: u32,
+    pixels: Vec<[u8; 4]>,
+}
+
+/// A Cloud Optimized GeoTIFF file and its overviews
+#[derive(Debug)]
+pub struct CogTiles {
+    path: PathBuf,
+    decoder: Mutex<(usize, TiffDecoder)>,
+    crs: Crs,
+    origin: [f64; 2],
+    levels: Vec<Level>,
+    color_type: ColorType,
+    palette: Vec<[u8; 3]>,
+    nodata: Option<f64>,
+    /// Range of the values mapped to grey levels, for images whose samples are not bytes
+    range: (f64, f64),
+}
+
+impl CogTiles {
+    pub fn open(path: &Path) -> Result<Self> {
+        let tiff_error = |err: tiff::TiffError| invalid(path, &err.to_string());
+        let file = File::open(path).map_err(Error::IO)?;
+        let mut decoder = Decoder::new(BufReader::new(file)).map_err(tiff_error)?;
+
+        let geo_keys = decoder
+            .find_tag_unsigned_vec::<u16>(Tag::GeoKeyDirectoryTag)
+            .map_err(tiff_error)?
+            .ok_or_else(|| invalid(path, "not a GeoTIFF file"))?;
+        let (crs, pixel_is_point) = parse_crs(path, &geo_keys)?;
+        let (mut origin, resolution) = parse_transform(path, &mut decoder)?;
+        if pixel_is_point {
+            origin = [
+                origin[0] - resolution[0] / 2.0,
+                origin[1] + resolution[1] / 2.0,
+            ];
+        }
+        let nodata = decoder
+            .get_tag_ascii_string(Tag::GdalNodata)
+            .ok()
+            .and_then(|nodata| nodata.trim_matches(char::from(0)).trim().parse().ok());
+        let color_type = decoder.colortype().map_err(tiff_error)?;
+        if samples_per_pixel(color_type).is_none() {
+            return Err(invalid(
+                path,
+                &format!("unsupported color type {color_type:?}"),
+            ));
+        }
+        let palette = match color_type {
+            ColorType::Palette(_) => {
+                let colors = decoder.get_tag_u16_vec(Tag::ColorMap).map_err(tiff_error)?;
+                let count = colors.len() / 3;
+                (0..count)
+                    .map(|i| [0, 1, 2].map(|band| (colors[band * count + i] >> 8) as u8))
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
+
+        // Overviews are the following reduced resolution images, masks being skipped
+        let (full_width, full_height) = decoder.dimensions().map_err(tiff_error)?;
+        let mut levels = Vec::new();
+        let mut index = 0;
+        loop {
+            let subfile_type = decoder
+                .find_tag_unsigned::<u32>(Tag::NewSubfileType)
+                .map_err(tiff_error)?
+                .unwrap_or_default();
+            if index == 0 || subfile_type & 4 == 0 {
+                let (width, height) = decoder.dimensions().map_err(tiff_error)?;
+                let (chunk_width, chunk_height) = decoder.chunk_dimensions();
+                levels.push(Level {
+                    index,
+                    width,
+                    height,
+                    chunk_width,
+                    chunk_height,
+                    resolution: [
+                        resolution[0] * f64::from(full_width) / f64::from(width),
+                        resolution[1] * f64::from(full_height) / f64::from(height),
+                    ],
+                });
+            }
+            if !decoder.more_images() {
+                break;
+            }
+            decoder.next_image().map_err(tiff_error)?;
+            index += 1;
+        }
+        levels.sort_by_key(|level| std::cmp::Reverse(level.width));
+
+        let mut cog = CogTiles {
+            path: path.to_path_buf(),
+            decoder: Mutex::new((index, decoder)),
+            crs,
+            origin,
+            levels,
+            color_type,
+            palette,
+            nodata,
+            range: (0.0, 255.0),
+        };
+        cog.range = cog.value_range()?;
+        Ok(cog)
+    }
+
+    /// Range of the values of the smallest overview, for images whose samples are not bytes
+    fn value_range(&self) -> Result<(f64, f64)> {
+        let level = self.levels.last().unwrap();
+        let (mut min, mut max) = (f64::INFINITY, f64::NEG_INFINITY);
+        let chunks =
+            level.width.div_ceil(level.chunk_width) * level.height.div_ceil(level.chunk_height);
+        for chunk in 0..chunks {
+            let (values, bytes) = samples(self.read_chunk(level, chunk)?);
+            if bytes {
+                return Ok((0.0, 255.0));
+            }
+            for value in values {
+                if value.is_finite() && Some(value) != self.nodata {
+                    min = min.min(value);
+                    max = max.max(value);
+                }
+            }
+        }
+        Ok(if min < max {
+            (min, max)
+        } else {
+            (min, min + 1.0)
+        })
+    }
+
+    fn read_chunk(&self, level: &Level, chunk: u32) -> Result<DecodingResult> {
+        let tiff_error = |err: tiff::TiffError| invalid(&self.path, &err.to_string());
+        let mut decoder = self.decoder.lock().unwrap();
+        if decoder.0 != level.index {
+            decoder.1.seek_to_image(level.index).map_err(tiff_error)?;
+            decoder.0 = level.index;
+        }
+        decoder.1.read_chunk(chunk).map_err(tiff_error)
+    }
+
+    /// Decode a chunk into RGBA pixels, nodata being transparent
+    fn decode_chunk(&self, level: &Level, chunk: u32) -> Result<Chunk> {
+        let columns = level.width.div_ceil(level.chunk_width);
+        let column = chunk % columns;
+        let width = level
+            .chunk_width
+            .min(level.width - column * level.chunk_width);
+        let (values, bytes) = samples(self.read_chunk(level, chunk)?);
+        let stretch = |value: f64| {
+            if bytes {
+                value as u8
+            } else {
+                let (min, max) = self.range;
+                ((value - min) / (max - min) * 255.0)
+                    .round()
+                    .clamp(0.0, 255.0) as u8
+            }
+        };
+        let samples = samples_per_pixel(self.color_type).unwrap_or(1);
+        let pixels = values
+            .chunks_exact(samples)
+            .map(|pixel| {
+                let nodata = match self.nodata {
+                    Some(nodata) => pixel.iter().take(3).all(|value| *value == nodata),
+                    None => false,
+                };
+                if nodata || pixel.iter().any(|value| value.is_nan()) {
+                    return [0, 0, 0, 0];
+                }
+                match (self.color_type, pixel) {
+                    (ColorType::Palette(_), [value, ..]) => {
+                        let [r, g, b] = self
+                            .palette
+                            .get(*value as usize)
+                            .copied()
+                            .unwrap_or_default();
+                        [r, g, b, 255]
+                    }
+                    (ColorType::YCbCr(_), [y, cb, cr]) => {
+                        let r = y + 1.402 * (cr - 128.0);
+                        let g = y - 0.344136 * (cb - 128.0) - 0.714136 * (cr - 128.0);
+                        let b = y + 1.772 * (cb - 128.0);
+                        let [r, g, b] =
+                            [r, g, b].map(|value| value.round().clamp(0.0, 255.0) as u8);
+                        [r, g, b, 255]
+                    }
+                    (ColorType::RGB(_), [r, g, b]) => [stretch(*r), stretch(*g), stretch(*b), 255],
+                    (ColorType::RGBA(_), [r, g, b, a, ..]) => {
+                        [stretch(*r), stretch(*g), stretch(*b), stretch(*a)]
+                    }
+                    (ColorType::GrayA(_), [value, alpha])
+                    | (ColorType::Multiband { .. }, [value, alpha]) => {
+                        let value = stretch(*value);
+                        [value, value, value, stretch(*alpha)]
+                    }
+                    (_, [value, ..]) => {
+                        let value = stretch(*value);
+                        [value, value, value, 255]
+                    }
+                    (_, []) => [0, 0, 0, 0],
+                }
+            })
+            .collect();
+        Ok(Chunk { width, pixels })
+    }
+
+    /// Bounds of the image in the Web Mercator unit square, as left, top, right and bottom
+    fn unit_bounds(&self) -> [f64; 4] {
+        let level = &self.levels[0];
+        let right = self.origin[0] + f64::from(level.width) * level.resolution[0];
+        let bottom = self.origin[1] - f64::from(level.height) * level.resolution[1];
+        let [left, top, _] = self.crs.project(self.origin[0], self.origin[1]);
+        let [right, bottom, _] = self.crs.project(right, bottom);
+        [left, top, right, bottom]
+    }
+
+    /// Select the smallest image whose pixels are not larger than the pixels of a tile at the
+    /// given zoom level
+    fn select_level(&self, z: u32) -> &Level {
+        let resolution = match self.crs {
+            Crs::Wgs84 => 360.0,
+            Crs::WebMercator => 2.0 * WEB_MERCATOR_EXTENT,
+        } / f64::from(TILE_SIZE)
+            / f64::from(1u32 << z);
+        self.levels
+            .iter()
+            .rev()
+            .find(|level| level.resolution[0] <= resolution * 1.01)
+            .unwrap_or(&self.levels[0])
+    }
+
+    /// Render the tile at the given XYZ position as an image, `None` when it is empty
+    fn render(&self, z: u32, x: u32, y: u32) -> Result<Option<RgbaImage>> {
+        let scale = f64::from(1u32 << z);
+        let [left, top, right, bottom] = self.unit_bounds();
+        if f64::from(x + 1) / scale <= left
+            || f64::from(x) / scale >= right
+            || f64::from(y + 1) / scale <= top
+            || f64::from(y) / scale >= bottom
+        {
+            return Ok(None);
+        }
+
+        let level = self.select_level(z);
+        let columns = level.width.div_ceil(level.chunk_width);
+        let mut chunks: HashMap<u32, Chunk> = HashMap::new();
+        let mut pixel = |column: i64, row: i64| -> Result<[u8; 4]> {
+            if column < 0 || row < 0 || column >= level.width.into() || row >= level.height.into() {
+                return Ok([0, 0, 0, 0]);
+            }
+            let (column, row) = (column as u32, row as u32);
+            let chunk = (row / level.chunk_height) * columns + column / level.chunk_width;
+            let data = match chunks.entry(chunk) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(self.decode_chunk(level, chunk)?),
+            };
+            let index = (row % level.chunk_height) * data.width + column % level.chunk_width;
+            Ok(data.pixels.get(index as usize).copied().unwrap_or_default())
+        };
+
+        let mut image = RgbaImage::new(TILE_SIZE, TILE_SIZE);
+        let mut empty = true;
+        for py in 0..TILE_SIZE {
+            for px in 0..TILE_SIZE {
+                let unit_x = (f64::from(x) + (f64::from(px) + 0.5) / f64::from(TILE_SIZE)) / scale;
+                let unit_y = (f64::from(y) + (f64::from(py) + 0.5) / f64::from(TILE_SIZE)) / scale;
+                let (crs_x, crs_y) = self.crs.unproject(unit_x, unit_y);
+                // Position in pixels of the image, relative to the center of its pixels
+                let column = (crs_x - self.origin[0]) / level.resolution[0] - 0.5;
+                let row = (self.origin[1] - crs_y) / level.resolution[1] - 0.5;
+                let (column0, row0) = (column.floor() as i64, row.floor() as i64);
+                let (dx, dy) = (column - column.floor(), row - row.floor());
+
+                // Bilinear interpolation, or the nearest pixel next to transparent ones
+                let corners = [
+                    pixel(column0, row0)?,
+                    pixel(column0 + 1, row0)?,
+                    pixel(column0, row0 + 1)?,
+                    pixel(column0 + 1, row0 + 1)?,
+                ];
+                let color = if corners.iter().all(|corner| corner[3] == 255) {
+                    let weights = [
+                        (1.0 - dx) * (1.0 - dy),
+                        dx * (1.0 - dy),
+                        (1.0 - dx) * dy,
+                        dx * dy,
+                    ];
+                    [0, 1, 2, 3].map(|band| {
+                        corners
+                            .iter()
+                            .zip(weights)
+                            .map(|(corner, weight)| f64::from(corner[band]) * weight)
+                            .sum::<f64>()
+                            .round() as u8
+                    })
+                } else {
+                    corners[usize::from(dx >= 0.5) + 2 * usize::from(dy >= 0.5)]
+                };
+                if color[3] > 0 {
+                    empty = false;
+                }
+                image.put_pixel(px, py, image::Rgba(color));
+            }
+        }
+        Ok((!empty).then_some(image))
+    }
+
+    /// Render the tile at the given XYZ position and encode it in the given image format
+    pub fn get_tile(&self, z: u32, x: u32, y: u32, data_format: DataFormat) -> Result<Vec<u8>> {
+        if z > u32::from(MAX_ZOOM) || x >= 1 << z || y >= 1 << z {
+            return Err(Error::TileNotFound);
+        }
+        match self.render(z, x, y)? {
+            Some(image) => encode_image(image, data_format),
+            None => Err(Error::TileNotFound),
+        }
+    }
+}
+
+/// Open a GeoTIFF file and build its TileJSON, tiles being rendered as PNG unless another
+/// image format is requested
+pub fn get_tile_details(path: &Path, tile_name: &str) -> Result<TileMeta> {
+    let cog = CogTiles::open(path)?;
+
+    let level = &cog.levels[0];
+    let (left, top) = cog.crs.lonlat(cog.origin[0], cog.origin[1]);
+    let (right, bottom) = cog.crs.lonlat(
+        cog.origin[0] + f64::from(level.width) * level.resolution[0],
+        cog.origin[1] - f64::from(level.height) * level.resolution[1],
+    );
+    // Zoom level whose tile pixels match the full resolution
+    let world = match cog.crs {
+        Crs::Wgs84 => 360.0,
+        Crs::WebMercator => 2.0 * WEB_MERCATOR_EXTENT,
+    };
+    let maxzoom = (world / f64::from(TILE_SIZE) / level.resolution[0])
+        .log2()
+        .ceil()
+        .clamp(0.0, f64::from(MAX_ZOOM)) as u8;
+
+    let mut tilejson = tilejson! {
+        tilejson: "3.0.0".to_string(),
+        tiles: vec!["".to_string()],
+    };
+    tilejson.name = Some(tile_name.to_string());
+    tilejson.minzoom = Some(0);
+    tilejson.maxzoom = Some(maxzoom);
+    tilejson.bounds = Some(Bounds::new(left, bottom, right, top));
+    tilejson.center = Some(Center::new(
+        (left + right) / 2.0,
+        (bottom + top) / 2.0,
+        maxzoom.saturating_sub(2),
+    ));
+
+    Ok(TileMeta {
+        source: TileSource::Cog(Arc::new(cog)),
+        path: PathBuf::from(path),
+        tilejson,
+        id: tile_name.to_string(),
+        tile_format: DataFormat::Png,
+        grid_format: None,
+        layer_type: None,
+        json: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raster::decode_image;
+
+    #[test]
+    fn get_cog_details() {
+        let tile_meta = get_tile_details(&PathBuf::from("./tiles/europe.tif"), "europe").unwrap();
+        assert_eq!(tile_meta.tile_format, DataFormat::Png);
+        let bounds = tile_meta.tilejson.bounds.unwrap();
+        assert!((bounds.left + 20.0).abs() < 1e-9 && (bounds.right - 40.0).abs() < 1e-9);
+        assert!((bounds.bottom - 30.0).abs() < 1e-9 && (bounds.top - 70.0).abs() < 1e-9);
+        // 0.125 degree pixels are between the resolutions of zoom levels 3 and 4
+        assert_eq!(tile_meta.tilejson.maxzoom, Some(4));
+        match tile_meta.source {
+            TileSource::Cog(cog) => {
+                assert_eq!(cog.levels.len(), 3);
+                assert_eq!(cog.select_level(0).width, 120);
+                assert_eq!(cog.select_level(2).width, 240);
+                assert_eq!(cog.select_level(3).width, 480);
+            }
+            _ => panic!("not a COG"),
+        }
+    }
+
+    #[test]
+    fn render_rgb_tile() {
+        let cog = CogTiles::open(&PathBuf::from("./tiles/europe.tif")).unwrap();
+        // Tile 4/8/5 spans 0 to 22.5 degrees of longitude, and about 40.98 to 55.78 of latitude
+        let tile = cog.get_tile(4, 8, 5, DataFormat::Png).unwrap();
+        let image = decode_image(&tile, DataFormat::Png).unwrap();
+        let pixel = image.get_pixel(128, 128);
+        // 11.25 degrees east is column 250 of 480, 48.99 degrees north is row 168 of 320
+        assert!((i32::from(pixel[0]) - 250 * 255 / 479).abs() <= 2);
+        assert!((i32::from(pixel[1]) - 168 * 255 / 319).abs() <= 2);
+        assert_eq!(pixel[2], 128);
+        assert_eq!(pixel[3], 255);
+        assert!(matches!(
+            cog.get_tile(4, 0, 0, DataFormat::Png),
+            Err(Error::TileNotFound)
+        ));
+        assert!(cog.get_tile(4, 8, 5, DataFormat::Webp).is_ok());
+        assert!(cog.get_tile(4, 8, 5, DataFormat::Jpg).is_ok());
+
+        // The nodata corner at the top-left of the image is transparent, and tiles only
+        // covering it are empty
+        let tile = cog.get_tile(6, 29, 15, DataFormat::Png).unwrap();
+        let image = decode_image(&tile, DataFormat::Png).unwrap();
+        assert_eq!(image.get_pixel(10, 128)[3], 0);
+        assert_eq!(image.get_pixel(100, 128)[3], 255);
+        assert!(matches!(
+            cog.get_tile(6, 28, 15, DataFormat::Png),
+            Err(Error::TileNotFound)
+        ));
+    }
+
+    #[test]
+    fn render_elevation_tile() {
+        let cog = CogTiles::open(&PathBuf::from("./tiles/elevation.tif")).unwrap();
+        assert_eq!(cog.crs, Crs::WebMercator);
+        assert_eq!(cog.nodata, Some(-9999.0));
+        let tile = cog.get_tile(1, 1, 0, DataFormat::Png).unwrap();
+        let image = decode_image(&tile, DataFormat::Png).unwrap();
+        // Values grow eastward, and the last columns are nodata
+        assert!(image.get_pixel(10, 128)[0] < image.get_pixel(200, 128)[0]);
+        assert_eq!(image.get_pixel(10, 128)[3], 255);
+        assert_eq!(image.get_pixel(250, 128)[3], 0);
+        assert!(matches!(
+            cog.get_tile(1, 0, 1, DataFormat::Png),
+            Err(Error::TileNotFound)
+        ));
+    }
+}
@@ -1,20 +1,58 @@
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::fs::read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use log::warn;
+use serde_json::{Map, Value as JSONValue};
 
+use crate::discovery::{self, Filter, SkippedFile, Source};
 use crate::errors::{Error, Result};
-use crate::tiles;
+use crate::glob::Glob;
+use crate::s3::{self, Credentials, S3Bucket};
+use crate::{pool, sprites, styles, tiles};
 
 #[derive(Parser, Default, Debug)]
 #[clap(about = "A simple mbtiles server")]
+#[clap(subcommand_precedence_over_arg = true)]
 #[clap(version)]
 pub struct Args {
-    #[clap(long, short, default_value = "./tiles", help = "Tiles directory")]
-    pub directory: PathBuf,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+    #[clap(
+        long,
+        short,
+        number_of_values = 1,
+        help = "Tiles directory, as <path> or <prefix>=<path> to serve its tilesets under a prefix. Can be used multiple times. [default: ./tiles]"
+    )]
+    pub directory: Vec<String>,
+    #[clap(help = "Tileset files or directories to serve, as <path> or <prefix>=<path>")]
+    pub files: Vec<String>,
+    #[clap(skip)]
+    pub sources: Vec<Source>,
+    #[clap(
+        long,
+        number_of_values = 1,
+        help = "Only serve the files matching a glob pattern. Can be used multiple times."
+    )]
+    pub include: Vec<String>,
+    #[clap(
+        long,
+        number_of_values = 1,
+        help = "Leave out the files and directories matching a glob pattern. Can be used multiple times."
+    )]
+    pub exclude: Vec<String>,
+    #[clap(
+        long,
+        help = "JSON file of TileJSON fields overriding the ones of tilesets, by tileset name"
+    )]
+    pub metadata: Option<PathBuf>,
     #[clap(skip)]
     pub tilesets: HashMap<String, tiles::TileMeta>,
+    #[clap(skip)]
+    pub skipped: Vec<SkippedFile>,
     #[clap(short, long, default_value_t = 3000, help = "Server port")]
     pub port: u16,
     #[clap(
@@ -34,18 +72,203 @@ pub struct Args {
     pub headers: Vec<(String, String)>,
     #[clap(long, help = "Disable preview map")]
     pub disable_preview: bool,
+    #[clap(long, help = "Enable OSGeo TMS endpoint")]
+    pub enable_tms: bool,
+    #[clap(long, help = "Enable admin endpoints")]
+    pub enable_admin: bool,
+    #[clap(
+        long,
+        help = "Fonts directory, with one subdirectory of glyph ranges per font"
+    )]
+    pub fonts: Option<PathBuf>,
+    #[clap(
+        long,
+        help = "Sprites directory, with prebuilt sprites and subdirectories of SVG icons"
+    )]
+    pub sprites: Option<PathBuf>,
+    #[clap(skip)]
+    pub sprite_files: HashMap<String, Vec<u8>>,
+    #[clap(long, help = "Styles directory")]
+    pub styles: Option<PathBuf>,
+    #[clap(skip)]
+    pub style_files: HashMap<String, JSONValue>,
+    #[clap(
+        long,
+        help = "S3 bucket and prefix to discover tilesets from, e.g. s3://tiles/world"
+    )]
+    pub s3_url: Option<String>,
+    #[clap(
+        long,
+        help = "Endpoint of the S3-compatible object storage [default: https://s3.<region>.amazonaws.com]"
+    )]
+    pub s3_endpoint: Option<String>,
+    #[clap(long, default_value = "us-east-1", help = "Region of the S3 bucket")]
+    pub s3_region: String,
+    #[clap(
+        long,
+        help = "Directory mbtiles downloaded from S3 are cached in [default: <temp dir>/mbtileserver]"
+    )]
+    pub s3_cache: Option<PathBuf>,
+    #[clap(
+        long,
+        default_value_t = pool::DEFAULT_IDLE_TIMEOUT,
+        help = "Seconds after which the connections of an unused mbtiles file are closed"
+    )]
+    pub pool_idle_timeout: u64,
+    #[clap(
+        long,
+        default_value_t = pool::DEFAULT_MAX_CONNECTIONS,
+        help = "Maximum number of mbtiles connections open at once"
+    )]
+    pub max_connections: u32,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Discover the tilesets and print the files skipped, without starting the server
+    Discover,
 }
 
 impl Args {
     /// Update args after the initially parsing them with Clap
     pub fn post_parse(mut self) -> Result<Self> {
-        if !self.directory.is_dir() {
-            return Err(Error::Config(format!(
-                "Directory does not exists: {}",
-                self.directory.display()
-            )));
+        for directory in &self.directory {
+            let source = Source::parse(directory);
+            if !source.path.is_dir() {
+                return Err(Error::Config(format!(
+                    "Directory does not exists: {}",
+                    source.path.display()
+                )));
+            }
+            self.sources.push(source);
+        }
+        for file in &self.files {
+            let source = Source::parse(file);
+            if !source.path.exists() {
+                return Err(Error::Config(format!(
+                    "File does not exists: {}",
+                    source.path.display()
+                )));
+            }
+            self.sources.push(source);
+        }
+        if self.sources.is_empty() {
+            let source = Source::new(Path::new("./tiles"));
+            // The default tiles directory is optional when tilesets are served from a bucket
+            if source.path.is_dir() {
+                self.sources.push(source);
+            } else if self.s3_url.is_none() {
+                return Err(Error::Config(format!(
+                    "Directory does not exists: {}",
+                    source.path.display()
+                )));
+            }
+        }
+        let filter = Filter {
+            include: self
+                .include
+                .iter()
+                .map(|pattern| Glob::new(pattern))
+                .collect::<Result<_>>()?,
+            exclude: self
+                .exclude
+                .iter()
+                .map(|pattern| Glob::new(pattern))
+                .collect::<Result<_>>()?,
+        };
+        if let Some(fonts) = &self.fonts {
+            if !fonts.is_dir() {
+                return Err(Error::Config(format!(
+                    "Fonts directory does not exists: {}",
+                    fonts.display()
+                )));
+            }
+        }
+        if let Some(sprites) = &self.sprites {
+            if !sprites.is_dir() {
+                return Err(Error::Config(format!(
+                    "Sprites directory does not exists: {}",
+                    sprites.display()
+                )));
+            }
+            self.sprite_files = sprites::load_sprites(sprites);
+        }
+        if self.max_connections == 0 {
+            return Err(Error::Config(
+                "Maximum number of connections must be at least 1".to_string(),
+            ));
+        }
+        pool::configure(
+            Duration::from_secs(self.pool_idle_timeout),
+            self.max_connections,
+        );
+        let discovery = discovery::discover_sources(&self.sources, &filter);
+        self.tilesets = discovery.tilesets;
+        self.skipped = discovery.skipped;
+        if let Some(s3_url) = &self.s3_url {
+            let (bucket, prefix) = s3_url
+                .strip_prefix("s3://")
+                .map(|path| path.split_once('/').unwrap_or((path, "")))
+                .filter(|(bucket, _)| !bucket.is_empty())
+                .ok_or_else(|| Error::Config(format!("Invalid S3 URL: {s3_url}")))?;
+            let endpoint = self
+                .s3_endpoint
+                .clone()
+                .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", self.s3_region));
+            let bucket = Arc::new(S3Bucket::new(
+                &endpoint,
+                bucket,
+                &self.s3_region,
+                Credentials::from_env(),
+            ));
+            let cache_dir = self
+                .s3_cache
+                .clone()
+                .unwrap_or_else(|| std::env::temp_dir().join("mbtileserver"));
+            for (name, tile_meta) in s3::discover_tilesets(&bucket, prefix, &cache_dir)? {
+                if self.tilesets.contains_key(&name) {
+                    warn!("Skipping {s3_url}: another tileset is already named {name}");
+                    continue;
+                }
+                self.tilesets.insert(name, tile_meta);
+            }
+        }
+        if let Some(metadata) = &self.metadata {
+            let entries: Map<String, JSONValue> = read(metadata)
+                .map_err(Error::IO)
+                .and_then(|data| {
+                    serde_json::from_slice(&data).map_err(|err| Error::Config(err.to_string()))
+                })
+                .map_err(|err| {
+                    Error::Config(format!(
+                        "Invalid metadata file {}: {err}",
+                        metadata.display()
+                    ))
+                })?;
+            for (name, overrides) in entries {
+                match (self.tilesets.get_mut(&name), overrides) {
+                    (Some(tile_meta), JSONValue::Object(overrides)) => {
+                        tiles::override_tilejson(tile_meta, overrides)
+                    }
+                    (Some(_), _) => warn!("Invalid metadata of {name}: expected a JSON object"),
+                    (None, _) => warn!("Metadata given for a tileset that does not exist: {name}"),
+                }
+            }
+        }
+        if let Some(styles) = &self.styles {
+            if !styles.is_dir() {
+                return Err(Error::Config(format!(
+                    "Styles directory does not exists: {}",
+                    styles.display()
+                )));
+            }
+            self.style_files = styles::load_styles(styles);
+            for (name, style) in &self.style_files {
+                for tileset in styles::get_missing_tilesets(style, &self.tilesets) {
+                    warn!("Style {name} uses a tileset that does not exist: {tileset}");
+                }
+            }
         }
-        self.tilesets = tiles::discover_tilesets(String::new(), &self.directory);
         self.allowed_hosts
             .iter_mut()
             .for_each(|v| *v = v.trim().to_string());
@@ -70,6 +293,7 @@ impl Args {
 }
 
 #[cfg(test)]
+#[allow(clippy::needless_borrows_for_generic_args)]
 mod tests {
     use super::*;
     use tempdir::TempDir;
@@ -90,6 +314,87 @@ mod tests {
         };
     }
 
+    #[test]
+    fn test_multiple_sources() {
+        let args = Args::try_parse_from([
+            "",
+            "-d",
+            "./tiles/pmtiles",
+            "--directory",
+            "local=./tiles/openstreetmap",
+            "./tiles/regions.fgb",
+            "base=./tiles/world_cities.mbtiles",
+            "--exclude",
+            "regions.*",
+        ])
+        .unwrap()
+        .post_parse()
+        .unwrap();
+        assert_eq!(args.sources.len(), 4);
+        assert_eq!(args.sources[1].prefix, "local");
+        let mut names: Vec<&String> = args.tilesets.keys().collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec!["base/world_cities", "local/open-streets-dc", "world_cities"]
+        );
+
+        let args = Args::try_parse_from(["", "./tiles/missing.mbtiles"])
+            .unwrap()
+            .post_parse();
+        assert!(format!("{}", args.unwrap_err()).starts_with("File does not exists"));
+    }
+
+    #[test]
+    fn test_metadata_file() {
+        let dir = TempDir::new("metadata").unwrap();
+        let metadata = dir.path().join("metadata.json");
+        std::fs::write(
+            &metadata,
+            br#"{"world_cities": {"name": "Cities", "maxzoom": 4}, "missing": {}}"#,
+        )
+        .unwrap();
+        let args = Args::try_parse_from([
+            "",
+            "./tiles/pmtiles",
+            "--metadata",
+            metadata.to_str().unwrap(),
+        ])
+        .unwrap()
+        .post_parse()
+        .unwrap();
+        let tilejson = &args.tilesets["world_cities"].tilejson;
+        assert_eq!(tilejson.name.as_deref(), Some("Cities"));
+        assert_eq!(tilejson.maxzoom, Some(4));
+
+        std::fs::write(&metadata, b"[").unwrap();
+        let args = Args::try_parse_from([
+            "",
+            "./tiles/pmtiles",
+            "--metadata",
+            metadata.to_str().unwrap(),
+        ])
+        .unwrap()
+        .post_parse();
+        assert!(format!("{}", args.unwrap_err()).starts_with("Invalid metadata file"));
+    }
+
+    #[test]
+    fn test_invalid_s3_url() {
+        let args = Args::try_parse_from(["", "--s3-url", "tiles/world"])
+            .unwrap()
+            .post_parse();
+        assert!(format!("{}", args.unwrap_err()).starts_with("Invalid S3 URL"));
+    }
+
+    #[test]
+    fn test_invalid_max_connections() {
+        let args = Args::try_parse_from(["", "--max-connections", "0"])
+            .unwrap()
+            .post_parse();
+        assert!(format!("{}", args.unwrap_err()).starts_with("Maximum number of connections"));
+    }
+
     #[test]
     fn test_valid_headers() {
         let args = Args::try_parse_from(&[
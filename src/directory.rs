@@ -0,0 +1,351 @@
+//! Tilesets stored as a `{z}/{x}/{y}.<ext>` tree of tile files
+
+use std::ffi::OsStr;
+use std::fs::{read, read_dir};
+use std::path::{Path, PathBuf};
+
+use log::warn;
+use serde_json::{Map, Value as JSONValue};
+use tilejson::{tilejson, Bounds, Center};
+
+use crate::errors::{Error, Result};
+use crate::tiles::{promote_json_metadata, set_metadata, TileMeta, TileSource};
+use crate::utils::{encode, get_data_format, tile_to_lonlat, DataFormat};
+
+const METADATA_FILE: &str = "metadata.json";
+/// TileJSON field listing the fields inferred from the tiles of a tileset
+pub const INFERRED_FIELD: &str = "inferred";
+
+/// A directory of tile files, named after their XYZ position or, when `tms` is set, their
+/// TMS position
+#[derive(Clone, Debug)]
+pub struct TileDirectory {
+    path: PathBuf,
+    extension: String,
+    tms: bool,
+}
+
+impl TileDirectory {
+    /// Read the tile at the given XYZ position. Vector tiles are returned gzipped, as they
+    /// are stored in mbtiles.
+    pub fn get_tile(&self, z: u32, x: u32, y: u32) -> Result<Vec<u8>> {
+        if z > 31 || y >= 1 << z {
+            return Err(Error::TileNotFound);
+        }
+        let y = if self.tms { (1 << z) - 1 - y } else { y };
+        let path = self
+            .path
+            .join(z.to_string())
+            .join(x.to_string())
+            .join(format!("{y}.{}", self.extension));
+        read(path)
+            .map(compress_vector_tile)
+            .map_err(|_| Error::TileNotFound)
+    }
+}
+
+/// Gzip tiles that are neither images nor already gzipped, as vector tiles are stored in
+/// mbtiles
+pub fn compress_vector_tile(data: Vec<u8>) -> Vec<u8> {
+    match get_data_format(&data) {
+        DataFormat::Gzip | DataFormat::Png | DataFormat::Jpg | DataFormat::Webp => data,
+        _ => encode(&data),
+    }
+}
+
+/// Return the numeric subdirectories or files of a directory, along with their number
+fn numeric_entries(path: &Path) -> Vec<(u32, PathBuf)> {
+    let mut entries: Vec<(u32, PathBuf)> = match read_dir(path) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let path = entry.path();
+                let number = path.file_stem()?.to_str()?.parse().ok()?;
+                Some((number, path))
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    entries.sort();
+    entries
+}
+
+/// Whether a directory holds a tree of tile files rather than tilesets: it either has a
+/// `metadata.json` file or only zoom level subdirectories
+pub fn is_tile_directory(path: &Path) -> bool {
+    if path.join(METADATA_FILE).is_file() {
+        return true;
+    }
+    let entries: Vec<PathBuf> = match read_dir(path) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .collect(),
+        Err(_) => return false,
+    };
+    !entries.is_empty()
+        && entries.iter().all(|entry| {
+            entry.is_dir()
+                && entry
+                    .file_name()
+                    .and_then(OsStr::to_str)
+                    .is_some_and(|name| name.parse::<u32>().is_ok())
+        })
+}
+
+/// Return the first tile file of the tree, at the lowest zoom level
+fn find_first_tile(path: &Path) -> Option<PathBuf> {
+    numeric_entries(path)
+        .into_iter()
+        .filter(|(_, zoom_dir)| zoom_dir.is_dir())
+        .flat_map(|(_, zoom_dir)| numeric_entries(&zoom_dir))
+        .filter(|(_, column_dir)| column_dir.is_dir())
+        .flat_map(|(_, column_dir)| numeric_entries(&column_dir))
+        .map(|(_, tile)| tile)
+        .find(|tile| tile.is_file())
+}
+
+/// Scan the tree for its zoom levels and for the tiles of its highest zoom level
+fn scan_tiles(path: &Path, tms: bool, metadata: &mut TileMeta) {
+    let zoom_levels: Vec<(u32, PathBuf)> = numeric_entries(path)
+        .into_iter()
+        .filter(|(z, zoom_dir)| *z < 32 && zoom_dir.is_dir())
+        .collect();
+    let (minzoom, (maxzoom, maxzoom_dir)) = match (zoom_levels.first(), zoom_levels.last()) {
+        (Some((minzoom, _)), Some(last)) => (*minzoom, last),
+        _ => return,
+    };
+    let tiles = numeric_entries(maxzoom_dir)
+        .into_iter()
+        .flat_map(|(x, column_dir)| {
+            numeric_entries(&column_dir)
+                .into_iter()
+                .map(move |(y, _)| (x, y))
+        });
+    set_tile_extents(metadata, tms, minzoom, *maxzoom, tiles);
+}
+
+/// Complete missing zoom levels, bounds and center from the zoom levels of a tileset and the
+/// `(x, y)` positions of the tiles of its highest zoom level, in the row order of `tms`. The
+/// fields completed are listed in the `inferred` field of the TileJSON.
+pub fn set_tile_extents(
+    metadata: &mut TileMeta,
+    tms: bool,
+    minzoom: u32,
+    maxzoom: u32,
+    tiles: impl IntoIterator<Item = (u32, u32)>,
+) {
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (u32::MAX, u32::MAX, 0, 0);
+    for (x, y) in tiles {
+        let y = if tms {
+            (1_u32 << maxzoom).saturating_sub(1 + y)
+        } else {
+            y
+        };
+        min_x = min_x.min(x);
+        max_x = max_x.max(x);
+        min_y = min_y.min(y);
+        max_y = max_y.max(y);
+    }
+
+    let tilejson = &mut metadata.tilejson;
+    let mut inferred = Vec::new();
+    if tilejson.minzoom.is_none() {
+        tilejson.minzoom = Some(minzoom as u8);
+        inferred.push("minzoom");
+    }
+    if tilejson.maxzoom.is_none() {
+        tilejson.maxzoom = Some(maxzoom as u8);
+        inferred.push("maxzoom");
+    }
+    if tilejson.bounds.is_none() && min_x <= max_x {
+        let (left, top) = tile_to_lonlat(maxzoom, min_x, min_y);
+        let (right, bottom) = tile_to_lonlat(maxzoom, max_x + 1, max_y + 1);
+        tilejson.bounds = Some(Bounds::new(left, bottom, right, top));
+        inferred.push("bounds");
+    }
+    if tilejson.center.is_none() {
+        if let Some(bounds) = tilejson.bounds {
+            tilejson.center = Some(Center::new(
+                (bounds.left + bounds.right) / 2.0,
+                (bounds.bottom + bounds.top) / 2.0,
+                minzoom as u8,
+            ));
+            inferred.push("center");
+        }
+    }
+    // Lists the fields computed from the tiles rather than read from the metadata
+    if !inferred.is_empty() {
+        tilejson
+            .other
+            .insert(INFERRED_FIELD.to_string(), JSONValue::from(inferred));
+    }
+}
+
+/// Parse a `metadata.json` file, read from `source`, into its entries
+pub fn parse_metadata(data: &[u8], source: &str) -> Result<Map<String, JSONValue>> {
+    match serde_json::from_slice(data) {
+        Ok(JSONValue::Object(entries)) => Ok(entries),
+        _ => Err(Error::InvalidMetadata(format!(
+            "{source}: expected a JSON object"
+        ))),
+    }
+}
+
+/// Remove the `scheme` entry of a `metadata.json` file and return whether rows are numbered
+/// from the bottom, as in TMS
+pub fn take_scheme(entries: &mut Map<String, JSONValue>, tile_name: &str) -> bool {
+    match entries.remove("scheme") {
+        Some(JSONValue::String(scheme)) if scheme == "tms" => true,
+        Some(JSONValue::String(scheme)) if scheme == "xyz" => false,
+        None => false,
+        Some(scheme) => {
+            warn!("Invalid scheme in {tile_name}: {scheme}, using xyz");
+            false
+        }
+    }
+}
+
+/// Detect the format of the tiles of a tileset from one of its tiles and its file extension
+pub fn get_tile_format(data: &[u8], extension: &str, tile_name: &str) -> Result<DataFormat> {
+    match get_data_format(data) {
+        DataFormat::Gzip => Ok(DataFormat::Pbf),
+        DataFormat::Unknown if matches!(extension, "pbf" | "mvt") => Ok(DataFormat::Pbf),
+        DataFormat::Unknown => Err(Error::UnknownTileFormat(tile_name.to_string())),
+        tile_format => Ok(tile_format),
+    }
+}
+
+/// Apply the entries of a `metadata.json` file as if they were rows of the mbtiles
+/// `metadata` table
+pub fn set_json_metadata(metadata: &mut TileMeta, entries: Map<String, JSONValue>) {
+    let mut json = Map::new();
+    for (label, value) in entries {
+        match (label.as_str(), value) {
+            ("vector_layers" | "tilestats", value) => {
+                json.insert(label, value);
+            }
+            (_, JSONValue::String(value)) => set_metadata(metadata, &label, value),
+            (_, JSONValue::Array(values)) => {
+                let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+                set_metadata(metadata, &label, values.join(","));
+            }
+            (_, value) => set_metadata(metadata, &label, value.to_string()),
+        }
+    }
+    if !json.is_empty() {
+        match metadata.json.as_mut() {
+            Some(JSONValue::Object(metadata_json)) => metadata_json.extend(json),
+            _ => metadata.json = Some(JSONValue::Object(json)),
+        }
+    }
+    promote_json_metadata(metadata);
+}
+
+/// Build the details of a directory of tile files from its `metadata.json`, completed by a
+/// scan of the tree. Tiles are named after their XYZ position unless the `scheme` of the
+/// metadata is `tms`.
+pub fn get_tile_details(path: &Path, tile_name: &str) -> Result<TileMeta> {
+    let metadata_path = path.join(METADATA_FILE);
+    let mut entries = if metadata_path.is_file() {
+        let data = read(&metadata_path).map_err(Error::IO)?;
+        parse_metadata(&data, &metadata_path.display().to_string())?
+    } else {
+        Map::new()
+    };
+    let tms = take_scheme(&mut entries, tile_name);
+
+    let first_tile = find_first_tile(path)
+        .ok_or_else(|| Error::MissingTable(format!("{}: no tiles", path.display())))?;
+    let extension = first_tile
+        .extension()
+        .and_then(OsStr::to_str)
+        .unwrap_or_default()
+        .to_string();
+    let tile_format = get_tile_format(
+        &read(&first_tile).map_err(Error::IO)?,
+        &extension,
+        tile_name,
+    )?;
+
+    let mut metadata = TileMeta {
+        source: TileSource::Directory(TileDirectory {
+            path: path.to_path_buf(),
+            extension,
+            tms,
+        }),
+        path: PathBuf::from(path),
+        tilejson: tilejson! {
+            tilejson: "3.0.0".to_string(),
+            tiles: vec!["".to_string()],
+        },
+        id: tile_name.to_string(),
+        tile_format,
+        grid_format: None,
+        layer_type: None,
+        json: None,
+    };
+    metadata.tilejson.name = Some(tile_name.to_string());
+    set_json_metadata(&mut metadata, entries);
+    scan_tiles(path, tms, &mut metadata);
+
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, write};
+
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn get_tms_directory_details() {
+        let path = PathBuf::from("./tiles/geography-class-tms");
+        assert!(is_tile_directory(&path));
+        assert!(!is_tile_directory(&PathBuf::from("./tiles/openstreetmap")));
+
+        let tile_meta = get_tile_details(&path, "geography-class-tms").unwrap();
+        assert_eq!(tile_meta.tile_format, DataFormat::Png);
+        assert_eq!(tile_meta.tilejson.name.as_deref(), Some("Geography Class"));
+        assert_eq!(tile_meta.tilejson.maxzoom, Some(1));
+        assert_eq!(tile_meta.tilejson.center, Some(Center::new(0.0, 20.0, 0)));
+
+        let source = match &tile_meta.source {
+            TileSource::Directory(source) => source,
+            _ => unreachable!(),
+        };
+        // XYZ row 0 is TMS row 1 at zoom 1
+        assert_eq!(
+            source.get_tile(1, 0, 0).unwrap(),
+            read(path.join("1/0/1.png")).unwrap()
+        );
+    }
+
+    #[test]
+    fn scan_xyz_directory() {
+        let dir = TempDir::new("tiles").unwrap();
+        for (z, x, y) in [(2, 1, 1), (3, 2, 3), (3, 3, 2)] {
+            create_dir_all(dir.path().join(format!("{z}/{x}"))).unwrap();
+            write(dir.path().join(format!("{z}/{x}/{y}.pbf")), b"\x1a\x00").unwrap();
+        }
+        assert!(is_tile_directory(dir.path()));
+
+        let tile_meta = get_tile_details(dir.path(), "scanned").unwrap();
+        assert_eq!(tile_meta.tile_format, DataFormat::Pbf);
+        assert_eq!(tile_meta.tilejson.name.as_deref(), Some("scanned"));
+        assert_eq!(tile_meta.tilejson.minzoom, Some(2));
+        assert_eq!(tile_meta.tilejson.maxzoom, Some(3));
+        let bounds = tile_meta.tilejson.bounds.unwrap();
+        assert_eq!((bounds.left, bounds.right), (-90.0, 0.0));
+
+        let source = match &tile_meta.source {
+            TileSource::Directory(source) => source,
+            _ => unreachable!(),
+        };
+        let tile = source.get_tile(3, 2, 3).unwrap();
+        assert_eq!(get_data_format(&tile), DataFormat::Gzip);
+        assert!(matches!(source.get_tile(3, 0, 0), Err(Error::TileNotFound)));
+    }
+}
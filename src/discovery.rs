@@ -0,0 +1,605 @@
+//! Discovery of the tilesets of a directory tree. The tree is walked first, following symbolic
+//! links, then the files found are opened in parallel. Files that cannot be served are skipped
+//! and reported with the reason why, instead of failing the discovery as a whole.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::{canonicalize, read_dir, File};
+use std::io::ErrorKind;
+use std::num::NonZeroUsize;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use log::warn;
+use rusqlite::{Error::SqliteFailure, ErrorCode};
+use serde::Serialize;
+use serde_json::{json, Value as JSONValue};
+
+use crate::errors::{Error, Result};
+use crate::glob::Glob;
+use crate::tiles::{self, TileMeta};
+use crate::{cog, directory, fgb, geojson, gpkg, pmtiles, zip};
+
+/// Why a file was left out of the tilesets
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    MissingTables,
+    UnknownFormat,
+    CorruptMetadata,
+    InvalidData,
+    PermissionDenied,
+    Unreadable,
+    InvalidName,
+    NameCollision,
+    SymlinkCycle,
+    Panicked,
+}
+
+/// A file or directory skipped while discovering tilesets
+#[derive(Clone, Debug, Serialize)]
+pub struct SkippedFile {
+    pub path: String,
+    pub reason: SkipReason,
+    pub message: String,
+}
+
+impl SkippedFile {
+    fn new(path: &Path, reason: SkipReason, message: String) -> Self {
+        SkippedFile {
+            path: path.display().to_string(),
+            reason,
+            message,
+        }
+    }
+
+    fn from_error(path: &Path, err: &Error) -> Self {
+        let reason = match err {
+            Error::MissingTable(_) => SkipReason::MissingTables,
+            Error::UnknownTileFormat(_) => SkipReason::UnknownFormat,
+            Error::InvalidMetadata(_) => SkipReason::CorruptMetadata,
+            Error::InvalidDataFormat(_) | Error::Image(_) => SkipReason::InvalidData,
+            Error::IO(err) if err.kind() == ErrorKind::PermissionDenied => {
+                SkipReason::PermissionDenied
+            }
+            Error::DBConnection(SqliteFailure(err, _))
+                if matches!(
+                    err.code,
+                    ErrorCode::NotADatabase | ErrorCode::DatabaseCorrupt
+                ) =>
+            {
+                SkipReason::InvalidData
+            }
+            _ => SkipReason::Unreadable,
+        };
+        let message = match &err {
+            Error::DBConnection(source) => format!("{err}: {source}"),
+            Error::Pool(source) => format!("{err}: {source}"),
+            Error::Image(source) => format!("{err}: {source}"),
+            _ => err.to_string(),
+        };
+        SkippedFile::new(path, reason, message)
+    }
+
+    fn from_panic(path: &Path, payload: Box<dyn Any + Send>) -> Self {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast::<&str>() {
+                Ok(message) => message.to_string(),
+                Err(_) => "Reading the file panicked".to_string(),
+            },
+        };
+        SkippedFile::new(path, SkipReason::Panicked, message)
+    }
+}
+
+/// The tilesets found, by name, and the files skipped on the way
+#[derive(Debug, Default)]
+pub struct Discovery {
+    pub tilesets: HashMap<String, TileMeta>,
+    pub skipped: Vec<SkippedFile>,
+}
+
+/// A directory, or a single file, to serve the tilesets of
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Source {
+    pub path: PathBuf,
+    /// Prepended to the names of the tilesets, separated with `/`
+    pub prefix: String,
+}
+
+impl Source {
+    pub fn new(path: &Path) -> Self {
+        Source {
+            path: path.to_path_buf(),
+            prefix: String::new(),
+        }
+    }
+
+    /// Parse a path optionally preceded by a prefix, as in `<prefix>=<path>`. A path that exists
+    /// is taken as is, even when it contains `=`.
+    pub fn parse(value: &str) -> Self {
+        match value.split_once('=') {
+            Some((prefix, path)) if !Path::new(value).exists() => Source {
+                path: PathBuf::from(path),
+                prefix: prefix.trim_matches('/').to_string(),
+            },
+            _ => Source::new(Path::new(value)),
+        }
+    }
+
+    fn name_prefix(&self) -> String {
+        match self.prefix.as_str() {
+            "" => String::new(),
+            prefix => format!("{prefix}/"),
+        }
+    }
+}
+
+/// Glob patterns selecting the files served out of the ones discovered
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    /// Files served when any matches, or all files when empty
+    pub include: Vec<Glob>,
+    /// Files and directories left out, even when included
+    pub exclude: Vec<Glob>,
+}
+
+impl Filter {
+    fn excludes(&self, path: &str) -> bool {
+        self.exclude.iter().any(|glob| glob.matches(path))
+    }
+
+    fn includes(&self, path: &str) -> bool {
+        !self.excludes(path)
+            && (self.include.is_empty() || self.include.iter().any(|glob| glob.matches(path)))
+    }
+}
+
+type GetDetails = fn(&Path, &str) -> Result<TileMeta>;
+/// Tilesets of a candidate by name, or the panic of its reader
+type Opened = thread::Result<Result<Vec<(String, TileMeta)>>>;
+
+#[derive(Debug)]
+enum Kind {
+    /// A tree of z/x/y tile files
+    Directory,
+    /// A GeoPackage, with a tileset for each of its tile pyramids
+    GeoPackage,
+    File(GetDetails),
+}
+
+impl Kind {
+    /// The kind of a tileset file from its extension
+    fn of_file(path: &Path) -> Option<Self> {
+        match path.extension().and_then(OsStr::to_str) {
+            Some("mbtiles") => Some(Kind::File(tiles::get_tile_details)),
+            Some("pmtiles") => Some(Kind::File(pmtiles::get_tile_details)),
+            Some("zip") => Some(Kind::File(zip::get_tile_details)),
+            Some("geojson") => Some(Kind::File(geojson::get_tile_details)),
+            Some("fgb") => Some(Kind::File(fgb::get_tile_details)),
+            Some("tif" | "tiff") => Some(Kind::File(cog::get_tile_details)),
+            Some("gpkg") => Some(Kind::GeoPackage),
+            _ => None,
+        }
+    }
+}
+
+/// A file found by the walk, to be opened as a tileset
+#[derive(Debug)]
+struct Candidate {
+    path: PathBuf,
+    /// Name of the tileset, prefixed with the directories it is nested in
+    name: String,
+    /// Name of the file, identifying the tileset in its details
+    file_name: String,
+    kind: Kind,
+}
+
+/// Walk through the given path and its subfolders, following symbolic links, and open all valid
+/// tilesets found
+#[cfg(test)]
+pub fn discover_tilesets(path: &Path) -> Discovery {
+    discover_sources(&[Source::new(path)], &Filter::default())
+}
+
+/// Discover the tilesets of several sources, in order, keeping the files selected by the filter
+pub fn discover_sources(sources: &[Source], filter: &Filter) -> Discovery {
+    let mut walker = Walker {
+        filter,
+        ancestors: Vec::new(),
+        candidates: Vec::new(),
+        skipped: Vec::new(),
+    };
+    for source in sources {
+        walker.add_source(source);
+    }
+    let Walker {
+        candidates,
+        mut skipped,
+        ..
+    } = walker;
+
+    let mut tilesets = HashMap::new();
+    for (candidate, opened) in candidates.iter().zip(open_all(&candidates)) {
+        match opened {
+            Ok(Ok(members)) => {
+                for (name, tile_meta) in members {
+                    if tilesets.contains_key(&name) {
+                        skipped.push(SkippedFile::new(
+                            &candidate.path,
+                            SkipReason::NameCollision,
+                            format!("another tileset is already named {name}"),
+                        ));
+                        continue;
+                    }
+                    tilesets.insert(name, tile_meta);
+                }
+            }
+            Ok(Err(err)) => skipped.push(SkippedFile::from_error(&candidate.path, &err)),
+            Err(payload) => skipped.push(SkippedFile::from_panic(&candidate.path, payload)),
+        }
+    }
+    for skipped_file in &skipped {
+        warn!("Skipping {}: {}", skipped_file.path, skipped_file.message);
+    }
+    Discovery { tilesets, skipped }
+}
+
+/// Report of the tilesets served and the files skipped
+pub fn report(tilesets: &HashMap<String, TileMeta>, skipped: &[SkippedFile]) -> JSONValue {
+    let mut names: Vec<&String> = tilesets.keys().collect();
+    names.sort();
+    json!({
+        "tilesets": names,
+        "skipped": skipped,
+    })
+}
+
+/// Collects the files to open as tilesets
+struct Walker<'a> {
+    filter: &'a Filter,
+    /// Canonical paths of the directories walked into, a link back to one of them being a cycle
+    ancestors: Vec<PathBuf>,
+    candidates: Vec<Candidate>,
+    skipped: Vec<SkippedFile>,
+}
+
+impl Walker<'_> {
+    fn add_source(&mut self, source: &Source) {
+        let path = &source.path;
+        if path.is_dir() && !directory::is_tile_directory(path) {
+            match canonicalize(path) {
+                Ok(root) => {
+                    self.ancestors = vec![root];
+                    self.walk(path, "", &source.name_prefix());
+                }
+                Err(err) => self.skip_error(path, Error::IO(err)),
+            }
+            return;
+        }
+        let Some((stem, file_name)) = self.names(path) else {
+            return;
+        };
+        let name = format!("{}{stem}", source.name_prefix());
+        if !self.filter.includes(&file_name) {
+            return;
+        }
+        let kind = if path.is_dir() {
+            Some(Kind::Directory)
+        } else {
+            Kind::of_file(path)
+        };
+        match kind {
+            Some(kind) => self.candidates.push(Candidate {
+                path: path.clone(),
+                name,
+                file_name: stem,
+                kind,
+            }),
+            None if path.exists() => self.skipped.push(SkippedFile::new(
+                path,
+                SkipReason::UnknownFormat,
+                "Unsupported file extension".to_string(),
+            )),
+            None => self.skip_error(path, Error::IO(ErrorKind::NotFound.into())),
+        }
+    }
+
+    /// Collect the tilesets of a directory, in file name order. `relative` is the path of the
+    /// directory within its source, matched by the filter, and `prefix` the one of its tilesets.
+    fn walk(&mut self, dir: &Path, relative: &str, prefix: &str) {
+        let entries = match read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) => return self.skip_error(dir, Error::IO(err)),
+        };
+        let mut paths = Vec::new();
+        for entry in entries {
+            match entry {
+                Ok(entry) => paths.push(entry.path()),
+                Err(err) => self.skip_error(dir, Error::IO(err)),
+            }
+        }
+        paths.sort();
+
+        for path in paths {
+            let Some((stem, file_name)) = self.names(&path) else {
+                continue;
+            };
+            let relative = format!("{relative}{file_name}");
+            if path.is_dir() && !directory::is_tile_directory(&path) {
+                if self.filter.excludes(&relative) {
+                    continue;
+                }
+                let canonical = match canonicalize(&path) {
+                    Ok(canonical) => canonical,
+                    Err(err) => {
+                        self.skip_error(&path, Error::IO(err));
+                        continue;
+                    }
+                };
+                if self.ancestors.contains(&canonical) {
+                    self.skipped.push(SkippedFile::new(
+                        &path,
+                        SkipReason::SymlinkCycle,
+                        format!("links back to {}", canonical.display()),
+                    ));
+                    continue;
+                }
+                self.ancestors.push(canonical);
+                self.walk(&path, &format!("{relative}/"), &format!("{prefix}{stem}/"));
+                self.ancestors.pop();
+                continue;
+            }
+            let kind = if path.is_dir() {
+                // A tree of z/x/y tile files is a tileset of its own
+                Kind::Directory
+            } else {
+                match Kind::of_file(&path) {
+                    Some(kind) => kind,
+                    None => continue,
+                }
+            };
+            if self.filter.includes(&relative) {
+                self.candidates.push(Candidate {
+                    name: format!("{prefix}{stem}"),
+                    file_name: stem,
+                    path,
+                    kind,
+                });
+            }
+        }
+    }
+
+    /// The name of a file without and with its extension, reported as skipped when it is not
+    /// valid UTF-8
+    fn names(&mut self, path: &Path) -> Option<(String, String)> {
+        let names = path
+            .file_stem()
+            .and_then(OsStr::to_str)
+            .zip(path.file_name().and_then(OsStr::to_str));
+        if names.is_none() {
+            self.skipped.push(SkippedFile::new(
+                path,
+                SkipReason::InvalidName,
+                "File name is not valid UTF-8".to_string(),
+            ));
+        }
+        names.map(|(stem, file_name)| (stem.to_string(), file_name.to_string()))
+    }
+
+    fn skip_error(&mut self, path: &Path, err: Error) {
+        self.skipped.push(SkippedFile::from_error(path, &err));
+    }
+}
+
+/// Open the candidates on as many threads as there are cores, catching the panics of their
+/// readers so that a single file cannot bring down the discovery
+fn open_all(candidates: &[Candidate]) -> Vec<Opened> {
+    let next = AtomicUsize::new(0);
+    let workers = thread::available_parallelism()
+        .map_or(1, NonZeroUsize::get)
+        .min(candidates.len());
+    let mut results: Vec<_> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(candidate) = candidates.get(index) else {
+                            break results;
+                        };
+                        results.push((index, catch_unwind(AssertUnwindSafe(|| open(candidate)))));
+                    }
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap_or_default())
+            .collect()
+    });
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, opened)| opened).collect()
+}
+
+fn open(candidate: &Candidate) -> Result<Vec<(String, TileMeta)>> {
+    match candidate.kind {
+        Kind::Directory => {
+            let tile_meta = directory::get_tile_details(&candidate.path, &candidate.file_name)?;
+            Ok(vec![(candidate.name.clone(), tile_meta)])
+        }
+        Kind::GeoPackage => {
+            check_readable(&candidate.path)?;
+            // Each tile pyramid of a GeoPackage is a tileset of its own
+            Ok(gpkg::get_tilesets(&candidate.path)?
+                .into_iter()
+                .map(|(table, tile_meta)| (format!("{}/{table}", candidate.name), tile_meta))
+                .collect())
+        }
+        Kind::File(get_details) => {
+            check_readable(&candidate.path)?;
+            let mut tile_meta = get_details(&candidate.path, &candidate.file_name)?;
+            if let Some(overrides) = tiles::read_sidecar(&candidate.path)? {
+                tiles::override_tilejson(&mut tile_meta, overrides);
+            }
+            Ok(vec![(candidate.name.clone(), tile_meta)])
+        }
+    }
+}
+
+/// Fail with the IO error of a file that cannot be opened, which readers such as SQLite would
+/// otherwise report in less detail
+fn check_readable(path: &Path) -> Result<()> {
+    File::open(path).map_err(Error::IO)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{copy, create_dir, write};
+    use tempdir::TempDir;
+
+    fn reasons(skipped: &[SkippedFile]) -> Vec<(String, SkipReason)> {
+        skipped
+            .iter()
+            .map(|skipped_file| (skipped_file.path.clone(), skipped_file.reason))
+            .collect()
+    }
+
+    #[test]
+    fn get_list_of_valid_tilesets() {
+        let discovery = discover_tilesets(&PathBuf::from("./tiles"));
+        let tilesets = discovery.tilesets;
+        // 2 out of 15 tilesets in ./tiles directory are invalid
+        assert_eq!(tilesets.len(), 13);
+        assert!(tilesets.contains_key("pmtiles/world_cities"));
+        assert!(tilesets.contains_key("geography-class/geography_class"));
+        assert!(tilesets.contains_key("geography-class-tms"));
+        assert!(tilesets.contains_key("geography-class-zip"));
+        assert!(tilesets.contains_key("incidents"));
+        assert!(tilesets.contains_key("regions"));
+        assert!(tilesets.contains_key("europe"));
+        assert!(tilesets.contains_key("elevation"));
+
+        assert!(!tilesets.contains_key("invalid"));
+        assert!(!tilesets.contains_key("invalid-tile-format"));
+        assert_eq!(
+            reasons(&discovery.skipped),
+            vec![
+                (
+                    "./tiles/invalid-tile-format.mbtiles".to_string(),
+                    SkipReason::UnknownFormat
+                ),
+                (
+                    "./tiles/invalid.mbtiles".to_string(),
+                    SkipReason::MissingTables
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn filter_sources() {
+        let sources = [
+            Source::parse("local=./tiles"),
+            Source::parse("./tiles/regions.fgb"),
+            Source::parse("cog/=./tiles/europe.tif"),
+        ];
+        let filter = Filter {
+            include: vec![
+                Glob::new("*.mbtiles").unwrap(),
+                Glob::new("*.fgb").unwrap(),
+                Glob::new("*.tif").unwrap(),
+            ],
+            exclude: vec![
+                Glob::new("invalid*").unwrap(),
+                Glob::new("openstreetmap").unwrap(),
+            ],
+        };
+        let discovery = discover_sources(&sources, &filter);
+        let mut names: Vec<&String> = discovery.tilesets.keys().collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                "cog/europe",
+                "local/elevation",
+                "local/europe",
+                "local/geography-class-jpg",
+                "local/geography-class-png",
+                "local/geography-class-png-no-bounds",
+                "local/regions",
+                "local/world_cities",
+                "regions",
+            ]
+        );
+        assert!(discovery.skipped.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn report_skipped_files() {
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::fs::symlink;
+
+        let dir = TempDir::new("discovery").unwrap();
+        let root = dir.path();
+        copy("./tiles/world_cities.mbtiles", root.join("cities.mbtiles")).unwrap();
+        copy(
+            "./tiles/pmtiles/world_cities.pmtiles",
+            root.join("cities.pmtiles"),
+        )
+        .unwrap();
+        copy("./tiles/world_cities.mbtiles", root.join("corrupt.mbtiles")).unwrap();
+        write(root.join("corrupt.mbtiles.json"), b"[]").unwrap();
+        write(root.join("garbage.mbtiles"), b"not a database").unwrap();
+        write(root.join(OsStr::from_bytes(b"\xff.mbtiles")), b"").unwrap();
+        create_dir(root.join("nested")).unwrap();
+        symlink(root, root.join("nested/loop")).unwrap();
+        symlink(
+            canonicalize("./tiles/pmtiles").unwrap(),
+            root.join("nested/linked"),
+        )
+        .unwrap();
+
+        let discovery = discover_tilesets(root);
+        let mut names: Vec<&String> = discovery.tilesets.keys().collect();
+        names.sort();
+        assert_eq!(names, vec!["cities", "nested/linked/world_cities"]);
+
+        let path = |name: &str| root.join(name).display().to_string();
+        let mut skipped = reasons(&discovery.skipped);
+        skipped.sort_by_key(|(path, _)| path.clone());
+        assert_eq!(
+            skipped,
+            vec![
+                (path("cities.pmtiles"), SkipReason::NameCollision),
+                (path("corrupt.mbtiles"), SkipReason::CorruptMetadata),
+                (path("garbage.mbtiles"), SkipReason::InvalidData),
+                (path("nested/loop"), SkipReason::SymlinkCycle),
+                (
+                    root.join(OsStr::from_bytes(b"\xff.mbtiles"))
+                        .display()
+                        .to_string(),
+                    SkipReason::InvalidName
+                ),
+            ]
+        );
+        let report = report(&discovery.tilesets, &discovery.skipped);
+        assert_eq!(
+            report["tilesets"],
+            json!(["cities", "nested/linked/world_cities"])
+        );
+        assert_eq!(report["skipped"].as_array().unwrap().len(), 5);
+        assert_eq!(
+            serde_json::to_value(SkipReason::NameCollision).unwrap(),
+            "name_collision"
+        );
+    }
+}
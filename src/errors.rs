@@ -1,7 +1,9 @@
 use std::error::Error as StdError;
 use std::fmt;
+use std::io::Error as IOError;
 use std::result::Result as StdResult;
 
+use image::ImageError;
 use r2d2::Error as R2D2Error;
 use rusqlite::Error as DBError;
 
@@ -11,11 +13,16 @@ pub type Result<T> = StdResult<T, Error>;
 pub enum Error {
     DBConnection(DBError),
     Pool(R2D2Error),
+    Image(ImageError),
+    IO(IOError),
     Config(String),
     MissingTable(String),
     InvalidDataFormat(String),
     InvalidDataFormatQueryCategory(String),
+    InvalidMetadata(String),
     UnknownTileFormat(String),
+    Remote(String),
+    TileNotFound,
 }
 
 impl fmt::Display for Error {
@@ -31,9 +38,14 @@ impl fmt::Display for Error {
             Error::InvalidDataFormatQueryCategory(tile_name) => {
                 write!(f, "Invalid query category: {tile_name}")
             }
+            Error::InvalidMetadata(entry) => write!(f, "Invalid metadata: {entry}"),
             Error::UnknownTileFormat(tile_name) => write!(f, "Unknown tile format: {tile_name}"),
+            Error::Remote(message) => write!(f, "Object storage error: {message}"),
+            Error::TileNotFound => write!(f, "Tile not found"),
             Error::DBConnection(_) => write!(f, "Database connection error"),
             Error::Pool(_) => write!(f, "Database pool connection error"),
+            Error::Image(_) => write!(f, "Image processing error"),
+            Error::IO(err) => write!(f, "{err}"),
         }
     }
 }
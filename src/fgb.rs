@@ -0,0 +1,761 @@
+//! FlatGeobuf files served as vector tilesets, as described by
+//! <https://github.com/flatgeobuf/flatgeobuf/tree/master/src/fbs>. Features crossing a tile
+//! are found with the packed Hilbert R-tree of the file and cut into tiles on request.
+
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use serde_json::{json, Value as JSONValue};
+use tilejson::{tilejson, Bounds, Center, VectorLayer};
+
+use crate::errors::{Error, Result};
+use crate::s3::Location;
+use crate::tiler::{tile_bbox, Crs, Feature, Geometry, Point, TileIndex, MAX_ZOOM};
+use crate::tiles::{TileMeta, TileSource};
+use crate::utils::{encode, DataFormat};
+
+const MAGIC_BYTES: &[u8] = b"fgb\x03fgb";
+/// Size of a node of the R-tree: its bounding box and an offset
+const NODE_SIZE: u64 = 40;
+const DEFAULT_INDEX_NODE_SIZE: u16 = 16;
+/// Bytes of encoded tiles kept in memory for each file
+const TILE_CACHE_SIZE: usize = 32 * 1024 * 1024;
+/// Bytes accounted for each cached tile on top of its data, so that empty tiles count too
+const TILE_CACHE_OVERHEAD: usize = 64;
+
+// Geometry types
+const POINT: u8 = 1;
+const LINE_STRING: u8 = 2;
+const POLYGON: u8 = 3;
+const MULTI_POINT: u8 = 4;
+const MULTI_LINE_STRING: u8 = 5;
+const MULTI_POLYGON: u8 = 6;
+const GEOMETRY_COLLECTION: u8 = 7;
+
+fn invalid(location: &Location, message: &str) -> Error {
+    Error::InvalidDataFormat(format!("{location}: {message}"))
+}
+
+fn read_u16(data: &[u8], position: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(position..position + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(data: &[u8], position: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(position..position + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u64(data: &[u8], position: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(position..position + 8)?.try_into().ok()?,
+    ))
+}
+
+fn read_f64(data: &[u8], position: usize) -> Option<f64> {
+    read_u64(data, position).map(f64::from_bits)
+}
+
+/// A table of a FlatBuffers buffer, whose fields are all bound checked
+#[derive(Clone, Copy)]
+struct Table<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Table<'a> {
+    fn root(data: &'a [u8]) -> Option<Self> {
+        Some(Table {
+            data,
+            position: read_u32(data, 0)? as usize,
+        })
+    }
+
+    /// Position of a field, `None` when it is absent
+    fn field(&self, index: usize) -> Option<usize> {
+        let vtable_offset = read_u32(self.data, self.position)? as i32;
+        let vtable = usize::try_from(self.position as i64 - i64::from(vtable_offset)).ok()?;
+        let vtable_length = read_u16(self.data, vtable)? as usize;
+        let entry = 4 + 2 * index;
+        if entry + 2 > vtable_length {
+            return None;
+        }
+        match read_u16(self.data, vtable + entry)? {
+            0 => None,
+            offset => Some(self.position + offset as usize),
+        }
+    }
+
+    fn u8(&self, index: usize) -> Option<u8> {
+        self.data.get(self.field(index)?).copied()
+    }
+
+    fn u16(&self, index: usize) -> Option<u16> {
+        read_u16(self.data, self.field(index)?)
+    }
+
+    fn i32(&self, index: usize) -> Option<i32> {
+        read_u32(self.data, self.field(index)?).map(|value| value as i32)
+    }
+
+    fn u64(&self, index: usize) -> Option<u64> {
+        read_u64(self.data, self.field(index)?)
+    }
+
+    /// Position of the string, vector or table a field points to
+    fn indirect(&self, index: usize) -> Option<usize> {
+        let position = self.field(index)?;
+        position.checked_add(read_u32(self.data, position)? as usize)
+    }
+
+    /// Start and length of a vector of elements of the given size
+    fn vector(&self, index: usize, element_size: usize) -> Option<(usize, usize)> {
+        let position = self.indirect(index)?;
+        let length = read_u32(self.data, position)? as usize;
+        let start = position + 4;
+        (start + length * element_size <= self.data.len()).then_some((start, length))
+    }
+
+    fn bytes(&self, index: usize) -> Option<&'a [u8]> {
+        let (start, length) = self.vector(index, 1)?;
+        self.data.get(start..start + length)
+    }
+
+    fn string(&self, index: usize) -> Option<&'a str> {
+        std::str::from_utf8(self.bytes(index)?).ok()
+    }
+
+    fn f64s(&self, index: usize) -> Vec<f64> {
+        match self.vector(index, 8) {
+            Some((start, length)) => (0..length)
+                .filter_map(|i| read_f64(self.data, start + 8 * i))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn u32s(&self, index: usize) -> Vec<u32> {
+        match self.vector(index, 4) {
+            Some((start, length)) => (0..length)
+                .filter_map(|i| read_u32(self.data, start + 4 * i))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn table(&self, index: usize) -> Option<Table<'a>> {
+        Some(Table {
+            data: self.data,
+            position: self.indirect(index)?,
+        })
+    }
+
+    fn tables(&self, index: usize) -> Vec<Table<'a>> {
+        match self.vector(index, 4) {
+            Some((start, length)) => (0..length)
+                .filter_map(|i| {
+                    let element = start + 4 * i;
+                    Some(Table {
+                        data: self.data,
+                        position: element + read_u32(self.data, element)? as usize,
+                    })
+                })
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Attribute column of a FlatGeobuf file
+#[derive(Clone, Debug)]
+struct Column {
+    name: String,
+    column_type: u8,
+}
+
+impl Column {
+    /// Type of the column, as named in the `fields` of a TileJSON vector layer
+    fn field_type(&self) -> Option<&'static str> {
+        match self.column_type {
+            2 => Some("Boolean"),
+            0..=10 => Some("Number"),
+            11..=13 => Some("String"),
+            _ => None,
+        }
+    }
+}
+
+fn parse_columns(table: &Table, index: usize) -> Vec<Column> {
+    table
+        .tables(index)
+        .iter()
+        .map(|column| Column {
+            name: column.string(0).unwrap_or_default().to_string(),
+            column_type: column.u8(1).unwrap_or_default(),
+        })
+        .collect()
+}
+
+/// Decode the properties of a feature into JSON values
+fn parse_properties(data: &[u8], columns: &[Column]) -> Vec<(String, JSONValue)> {
+    let mut properties = Vec::new();
+    let mut position = 0;
+    while let Some(index) = read_u16(data, position) {
+        position += 2;
+        let column = match columns.get(index as usize) {
+            Some(column) => column,
+            None => break,
+        };
+        let (length, value) = match column.column_type {
+            0 => (1, data.get(position).map(|v| json!(*v as i8))),
+            1 => (1, data.get(position).map(|v| json!(*v))),
+            2 => (1, data.get(position).map(|v| json!(*v != 0))),
+            3 => (2, read_u16(data, position).map(|v| json!(v as i16))),
+            4 => (2, read_u16(data, position).map(|v| json!(v))),
+            5 => (4, read_u32(data, position).map(|v| json!(v as i32))),
+            6 => (4, read_u32(data, position).map(|v| json!(v))),
+            7 => (8, read_u64(data, position).map(|v| json!(v as i64))),
+            8 => (8, read_u64(data, position).map(|v| json!(v))),
+            9 => (
+                4,
+                read_u32(data, position).map(|v| json!(f32::from_bits(v))),
+            ),
+            10 => (8, read_f64(data, position).map(|v| json!(v))),
+            _ => {
+                let length = match read_u32(data, position) {
+                    Some(length) => length as usize,
+                    None => break,
+                };
+                position += 4;
+                let value = data
+                    .get(position..position + length)
+                    .filter(|_| column.column_type != 14)
+                    .map(|bytes| json!(String::from_utf8_lossy(bytes)));
+                (length, value)
+            }
+        };
+        position += length;
+        match value {
+            Some(value) => properties.push((column.name.clone(), value)),
+            None if column.column_type == 14 => continue,
+            None => break,
+        }
+    }
+    properties
+}
+
+/// Split the positions of a geometry into its parts, as delimited by `ends`
+fn split_parts(points: Vec<Point>, ends: &[u32]) -> Vec<Vec<Point>> {
+    if ends.is_empty() {
+        return vec![points];
+    }
+    let mut start = 0;
+    ends.iter()
+        .filter_map(|&end| {
+            let part = points.get(start..end as usize)?.to_vec();
+            start = end as usize;
+            Some(part)
+        })
+        .collect()
+}
+
+/// Parse a FlatGeobuf geometry into the geometries of MVT features, projecting its positions
+fn parse_geometry(
+    geometry: &Table,
+    geometry_type: u8,
+    project: &impl Fn(f64, f64) -> Point,
+) -> Vec<Geometry> {
+    let points = || -> Vec<Point> {
+        geometry
+            .f64s(1)
+            .chunks_exact(2)
+            .map(|xy| project(xy[0], xy[1]))
+            .collect()
+    };
+    let geometry = match geometry_type {
+        POINT | MULTI_POINT => Geometry::Points(points()),
+        LINE_STRING => Geometry::Lines(vec![points()]),
+        MULTI_LINE_STRING => Geometry::Lines(split_parts(points(), &geometry.u32s(0))),
+        POLYGON => Geometry::Polygons(vec![split_parts(points(), &geometry.u32s(0))]),
+        MULTI_POLYGON => Geometry::Polygons(
+            geometry
+                .tables(7)
+                .iter()
+                .map(|part| {
+                    split_parts(
+                        part.f64s(1)
+                            .chunks_exact(2)
+                            .map(|xy| project(xy[0], xy[1]))
+                            .collect(),
+                        &part.u32s(0),
+                    )
+                })
+                .collect(),
+        ),
+        GEOMETRY_COLLECTION => {
+            return geometry
+                .tables(7)
+                .iter()
+                .flat_map(|part| parse_geometry(part, part.u8(6).unwrap_or_default(), project))
+                .collect()
+        }
+        // Curves and surfaces have no MVT counterpart
+        _ => return Vec::new(),
+    };
+    vec![geometry]
+}
+
+/// Return the start and end of each level of a packed R-tree, from the leaves to the root
+fn level_bounds(num_items: u64, node_size: u64) -> Vec<(u64, u64)> {
+    let mut level_num_nodes = vec![num_items];
+    let mut n = num_items;
+    let mut num_nodes = n;
+    loop {
+        n = n.div_ceil(node_size);
+        num_nodes += n;
+        level_num_nodes.push(n);
+        if n <= 1 {
+            break;
+        }
+    }
+    let mut bounds = Vec::with_capacity(level_num_nodes.len());
+    let mut end = num_nodes;
+    for size in level_num_nodes {
+        bounds.push((end - size, end));
+        end -= size;
+    }
+    bounds
+}
+
+type TileKey = (u32, u32, u32);
+
+/// Tiles cut so far, `None` for tiles without features, dropped in the order they were cut
+/// once they take more than `capacity` bytes
+#[derive(Debug)]
+struct TileCache {
+    capacity: usize,
+    size: usize,
+    tiles: HashMap<TileKey, Option<Vec<u8>>>,
+    order: VecDeque<TileKey>,
+}
+
+impl TileCache {
+    fn new(capacity: usize) -> Self {
+        TileCache {
+            capacity,
+            size: 0,
+            tiles: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn entry_size(tile: &Option<Vec<u8>>) -> usize {
+        TILE_CACHE_OVERHEAD + tile.as_ref().map_or(0, |tile| tile.len())
+    }
+
+    fn get(&self, key: &TileKey) -> Option<Option<Vec<u8>>> {
+        self.tiles.get(key).cloned()
+    }
+
+    fn insert(&mut self, key: TileKey, tile: Option<Vec<u8>>) {
+        let size = Self::entry_size(&tile);
+        if size > self.capacity || self.tiles.contains_key(&key) {
+            return;
+        }
+        while self.size + size > self.capacity {
+            match self.order.pop_front().and_then(|key| self.tiles.remove(&key)) {
+                Some(evicted) => self.size -= Self::entry_size(&evicted),
+                None => break,
+            }
+        }
+        self.size += size;
+        self.tiles.insert(key, tile);
+        self.order.push_back(key);
+    }
+}
+
+/// A FlatGeobuf file with a spatial index
+#[derive(Debug)]
+pub struct FlatGeobufTiles {
+    location: Location,
+    layer_name: String,
+    crs: Crs,
+    geometry_type: u8,
+    columns: Vec<Column>,
+    features_count: u64,
+    index_node_size: u64,
+    index_offset: u64,
+    features_offset: u64,
+    tiles: Mutex<TileCache>,
+}
+
+impl FlatGeobufTiles {
+    fn open(location: Location, layer_name: &str) -> Result<(Self, Option<Bounds>, JSONValue)> {
+        let start = location.read(0, 12)?;
+        if !start.starts_with(MAGIC_BYTES) {
+            return Err(invalid(&location, "not a FlatGeobuf file"));
+        }
+        let header_length = u64::from(read_u32(&start, 8).unwrap_or_default());
+        let header_data = location.read(12, header_length)?;
+        let header =
+            Table::root(&header_data).ok_or_else(|| invalid(&location, "invalid header"))?;
+
+        let crs = match header.table(10) {
+            None => Crs::Wgs84,
+            Some(crs) => match (crs.string(0), crs.i32(1).unwrap_or_default()) {
+                (None, 4326) | (None, 0) => Crs::Wgs84,
+                (Some(org), 4326) if org.eq_ignore_ascii_case("EPSG") => Crs::Wgs84,
+                (None, 3857) => Crs::WebMercator,
+                (Some(org), 3857) if org.eq_ignore_ascii_case("EPSG") => Crs::WebMercator,
+                (org, code) => {
+                    return Err(invalid(
+                        &location,
+                        &format!("unsupported CRS {}:{code}", org.unwrap_or("EPSG")),
+                    ))
+                }
+            },
+        };
+        let features_count = header.u64(8).unwrap_or_default();
+        let index_node_size = u64::from(header.u16(9).unwrap_or(DEFAULT_INDEX_NODE_SIZE));
+        if index_node_size < 2 || features_count == 0 {
+            return Err(invalid(&location, "missing spatial index"));
+        }
+        let num_nodes = level_bounds(features_count, index_node_size)
+            .first()
+            .map_or(0, |(_, end)| *end);
+        let index_offset = 12 + header_length;
+
+        let bounds = match header.f64s(1).as_slice() {
+            [min_x, min_y, max_x, max_y, ..] => {
+                let (left, bottom) = crs.lonlat(*min_x, *min_y);
+                let (right, top) = crs.lonlat(*max_x, *max_y);
+                Some(Bounds::new(left, bottom, right, top))
+            }
+            _ => None,
+        };
+        let details = json!({
+            "name": header.string(0),
+            "title": header.string(11),
+            "description": header.string(12),
+        });
+
+        let fgb = FlatGeobufTiles {
+            location,
+            layer_name: layer_name.to_string(),
+            crs,
+            geometry_type: header.u8(2).unwrap_or_default(),
+            columns: parse_columns(&header, 7),
+            features_count,
+            index_node_size,
+            index_offset,
+            features_offset: index_offset + num_nodes * NODE_SIZE,
+            tiles: Mutex::new(TileCache::new(TILE_CACHE_SIZE)),
+        };
+        Ok((fgb, bounds, details))
+    }
+
+    /// Search the R-tree for the features whose bounding box intersects `bbox`, given in the
+    /// reference system of the file. Returns their offsets along with the offset of the
+    /// feature that follows them, when known.
+    fn search(&self, [min_x, min_y, max_x, max_y]: [f64; 4]) -> Result<Vec<(u64, Option<u64>)>> {
+        let levels = level_bounds(self.features_count, self.index_node_size);
+        let leaves_start = levels[0].0;
+        let mut results = Vec::new();
+        let mut queue = vec![(0, levels.len() - 1)];
+        while let Some((node_index, level)) = queue.pop() {
+            let level_end = levels[level].1;
+            let end = level_end.min(node_index + self.index_node_size);
+            if node_index >= end {
+                return Err(invalid(&self.location, "invalid spatial index"));
+            }
+            let nodes = self.location.read(
+                self.index_offset + node_index * NODE_SIZE,
+                (end - node_index) * NODE_SIZE,
+            )?;
+            let nodes: Vec<&[u8]> = nodes.chunks_exact(NODE_SIZE as usize).collect();
+            for (i, node) in nodes.iter().enumerate() {
+                let [node_min_x, node_min_y, node_max_x, node_max_y] =
+                    [0, 8, 16, 24].map(|position| read_f64(node, position).unwrap_or_default());
+                if node_max_x < min_x
+                    || node_min_x > max_x
+                    || node_max_y < min_y
+                    || node_min_y > max_y
+                {
+                    continue;
+                }
+                let offset = read_u64(node, 32).unwrap_or_default();
+                if node_index >= leaves_start {
+                    let next = nodes.get(i + 1).and_then(|next| read_u64(next, 32));
+                    results.push((offset, next));
+                } else if level > 0 && offset >= levels[level - 1].0 && offset < levels[level - 1].1
+                {
+                    queue.push((offset, level - 1));
+                } else {
+                    return Err(invalid(&self.location, "invalid spatial index"));
+                }
+            }
+        }
+        results.sort();
+        Ok(results)
+    }
+
+    /// Read the features whose bounding box intersects `bbox`, reading consecutive features
+    /// at once
+    fn read_features(&self, bbox: [f64; 4]) -> Result<Vec<Feature>> {
+        let items = self.search(bbox)?;
+        let project = |x, y| self.crs.project(x, y);
+        let mut features = Vec::new();
+        let mut i = 0;
+        while i < items.len() {
+            let start = items[i].0;
+            let mut j = i;
+            while let (Some(end), Some(next)) = (items[j].1, items.get(j + 1)) {
+                if next.0 != end {
+                    break;
+                }
+                j += 1;
+            }
+            let end = match items[j].1 {
+                Some(end) => end,
+                None => {
+                    let offset = self.features_offset + items[j].0;
+                    let length = read_u32(&self.location.read(offset, 4)?, 0).unwrap_or_default();
+                    items[j].0 + 4 + u64::from(length)
+                }
+            };
+            i = j + 1;
+
+            let data = self
+                .location
+                .read(self.features_offset + start, end.saturating_sub(start))?;
+            let mut position = 0;
+            while let Some(length) = read_u32(&data, position) {
+                let feature_data = match data.get(position + 4..position + 4 + length as usize) {
+                    Some(feature_data) => feature_data,
+                    None => return Err(invalid(&self.location, "truncated feature")),
+                };
+                position += 4 + length as usize;
+                let feature = match Table::root(feature_data) {
+                    Some(feature) => feature,
+                    None => continue,
+                };
+                let properties = feature
+                    .bytes(1)
+                    .map(|data| parse_properties(data, &self.columns))
+                    .unwrap_or_default();
+                let geometry = match feature.table(0) {
+                    Some(geometry) => geometry,
+                    None => continue,
+                };
+                let geometry_type = match self.geometry_type {
+                    0 => geometry.u8(6).unwrap_or_default(),
+                    geometry_type => geometry_type,
+                };
+                for geometry in parse_geometry(&geometry, geometry_type, &project) {
+                    features.push(Feature::new(None, geometry, properties.clone()));
+                }
+            }
+        }
+        Ok(features)
+    }
+
+    /// Return the tile at the given XYZ position, gzipped as vector tiles are stored in
+    /// mbtiles. Tiles are cut once from the features crossing them and then kept in memory.
+    pub fn get_tile(&self, z: u32, x: u32, y: u32) -> Result<Vec<u8>> {
+        if z > MAX_ZOOM || x >= 1 << z || y >= 1 << z {
+            return Err(Error::TileNotFound);
+        }
+        let cached = self.tiles.lock().unwrap().get(&(z, x, y));
+        let tile = match cached {
+            Some(tile) => tile,
+            None => {
+                let tile = self.cut_tile(z, x, y)?;
+                self.tiles.lock().unwrap().insert((z, x, y), tile.clone());
+                tile
+            }
+        };
+        tile.ok_or(Error::TileNotFound)
+    }
+
+    /// Read the features crossing the tile at the given XYZ position and encode them as a
+    /// gzipped vector tile, or `None` when there are none
+    fn cut_tile(&self, z: u32, x: u32, y: u32) -> Result<Option<Vec<u8>>> {
+        let [left, top, right, bottom] = tile_bbox(z, x, y);
+        let (min_x, max_y) = self.crs.unproject(left, top.max(0.0));
+        let (max_x, min_y) = self.crs.unproject(right, bottom.min(1.0));
+        let features = self.read_features([min_x, min_y, max_x, max_y])?;
+        Ok(TileIndex::new(features)
+            .get_tile(&self.layer_name, z, x, y)
+            .map(|tile| encode(&tile)))
+    }
+}
+
+/// Name of a FlatGeobuf geometry type in tilestats
+fn geometry_name(geometry_type: u8) -> Option<&'static str> {
+    match geometry_type {
+        POINT | MULTI_POINT => Some("Point"),
+        LINE_STRING | MULTI_LINE_STRING => Some("LineString"),
+        POLYGON | MULTI_POLYGON => Some("Polygon"),
+        _ => None,
+    }
+}
+
+/// Open a local FlatGeobuf file and build its TileJSON from its header, with a single vector
+/// layer named after the file
+pub fn get_tile_details(path: &Path, tile_name: &str) -> Result<TileMeta> {
+    let layer_name = path
+        .file_stem()
+        .and_then(|name| name.to_str())
+        .unwrap_or(tile_name);
+    let (fgb, bounds, details) =
+        FlatGeobufTiles::open(Location::File(path.to_path_buf()), layer_name)?;
+
+    let mut tilejson = tilejson! {
+        tilejson: "3.0.0".to_string(),
+        tiles: vec!["".to_string()],
+    };
+    tilejson.name = details["title"]
+        .as_str()
+        .or(details["name"].as_str())
+        .map(String::from)
+        .or_else(|| Some(tile_name.to_string()));
+    tilejson.description = details["description"].as_str().map(String::from);
+    tilejson.minzoom = Some(0);
+    tilejson.maxzoom = Some(MAX_ZOOM as u8);
+    tilejson.bounds = bounds;
+    tilejson.center = bounds.map(|bounds| {
+        Center::new(
+            (bounds.left + bounds.right) / 2.0,
+            (bounds.bottom + bounds.top) / 2.0,
+            0,
+        )
+    });
+    let fields: HashMap<String, String> = fgb
+        .columns
+        .iter()
+        .filter_map(|column| Some((column.name.clone(), column.field_type()?.to_string())))
+        .collect();
+    tilejson.vector_layers = Some(vec![VectorLayer::new(layer_name.to_string(), fields)]);
+    if let Some(geometry) = geometry_name(fgb.geometry_type) {
+        tilejson.other.insert(
+            "tilestats".to_string(),
+            json!({
+                "layerCount": 1,
+                "layers": [{
+                    "layer": layer_name,
+                    "count": fgb.features_count,
+                    "geometry": geometry,
+                }],
+            }),
+        );
+    }
+
+    Ok(TileMeta {
+        source: TileSource::FlatGeobuf(Arc::new(fgb)),
+        path: PathBuf::from(path),
+        tilejson,
+        id: tile_name.to_string(),
+        tile_format: DataFormat::Pbf,
+        grid_format: None,
+        layer_type: Some("overlay".to_string()),
+        json: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+
+    use super::*;
+    use crate::mvt::Tile;
+    use crate::utils::decompress;
+
+    #[test]
+    fn packed_rtree_levels() {
+        assert_eq!(
+            level_bounds(576, 16),
+            vec![(40, 616), (4, 40), (1, 4), (0, 1)]
+        );
+        assert_eq!(level_bounds(1, 16), vec![(1, 2), (0, 1)]);
+    }
+
+    #[test]
+    fn get_flatgeobuf_details() {
+        let tile_meta = get_tile_details(&PathBuf::from("./tiles/regions.fgb"), "regions").unwrap();
+        assert_eq!(tile_meta.tile_format, DataFormat::Pbf);
+        assert_eq!(tile_meta.tilejson.name.as_deref(), Some("Grid regions"));
+        let bounds = tile_meta.tilejson.bounds.unwrap();
+        assert!((bounds.left + 180.0).abs() < 1e-9 && (bounds.top - 80.0).abs() < 1e-9);
+        let vector_layers = tile_meta.tilejson.vector_layers.unwrap();
+        assert_eq!(vector_layers[0].id, "regions");
+        assert_eq!(vector_layers[0].fields["name"], "String");
+        assert_eq!(vector_layers[0].fields["area"], "Number");
+        assert_eq!(vector_layers[0].fields["even"], "Boolean");
+    }
+
+    #[test]
+    fn get_flatgeobuf_tile() {
+        let (fgb, _, _) = FlatGeobufTiles::open(
+            Location::File(PathBuf::from("./tiles/regions.fgb")),
+            "regions",
+        )
+        .unwrap();
+        // The 10 degree cells touching the box of 20 to 30 degrees of longitude and latitude
+        let features = fgb.read_features([20.5, 20.5, 29.5, 29.5]).unwrap();
+        assert_eq!(features.len(), 1);
+        let property = |name: &str| {
+            features[0]
+                .properties
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+        };
+        assert_eq!(property("name"), Some(json!("cell 10-20")));
+        assert_eq!(property("row"), Some(json!(10)));
+        assert_eq!(property("area"), Some(json!(105)));
+        assert_eq!(property("even"), Some(json!(true)));
+        assert_eq!(
+            fgb.read_features([-180.0, -90.0, 180.0, 90.0])
+                .unwrap()
+                .len(),
+            576
+        );
+
+        let tile = decompress(fgb.get_tile(1, 1, 0).unwrap()).unwrap();
+        let tile = Tile::decode(&tile[..]).unwrap();
+        assert_eq!(tile.layers[0].name, "regions");
+        // 18 columns of cells from 0 to 80 degrees of latitude, and the cells of the buffer
+        // around the tile
+        assert_eq!(tile.layers[0].features.len(), 19 * 9);
+        assert!(matches!(fgb.get_tile(15, 0, 0), Err(Error::TileNotFound)));
+
+        // Tiles are cut once and then read from the cache, tiles without features included
+        let cached = fgb.tiles.lock().unwrap().get(&(1, 1, 0));
+        assert_eq!(cached, Some(Some(fgb.get_tile(1, 1, 0).unwrap())));
+        assert!(matches!(fgb.get_tile(14, 0, 0), Err(Error::TileNotFound)));
+        assert_eq!(fgb.tiles.lock().unwrap().get(&(14, 0, 0)), Some(None));
+    }
+
+    #[test]
+    fn evict_cached_tiles() {
+        let mut cache = TileCache::new(3 * TILE_CACHE_OVERHEAD + 20);
+        cache.insert((0, 0, 0), Some(vec![0; 10]));
+        cache.insert((1, 0, 0), None);
+        cache.insert((1, 1, 0), Some(vec![0; 10]));
+        assert_eq!(cache.get(&(0, 0, 0)), Some(Some(vec![0; 10])));
+        assert_eq!(cache.get(&(1, 0, 0)), Some(None));
+
+        cache.insert((1, 1, 1), None);
+        assert_eq!(cache.get(&(0, 0, 0)), None);
+        assert_eq!(cache.get(&(1, 1, 1)), Some(None));
+        assert!(cache.size <= cache.capacity);
+
+        // Tiles larger than the whole cache are not kept
+        cache.insert((2, 0, 0), Some(vec![0; 1000]));
+        assert_eq!(cache.get(&(2, 0, 0)), None);
+        assert_eq!(cache.get(&(1, 0, 0)), Some(None));
+    }
+}
@@ -0,0 +1,168 @@
+//! Glyph range protobuf messages as described by
+//! <https://github.com/mapbox/node-fontnik/blob/master/proto/glyphs.proto>
+
+use std::collections::HashSet;
+use std::fs::{read, read_dir};
+use std::path::Path;
+
+use prost::Message;
+
+use crate::errors::{Error, Result};
+use crate::utils::decompress;
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Glyph {
+    #[prost(uint32, required, tag = "1")]
+    pub id: u32,
+    #[prost(bytes = "vec", optional, tag = "2")]
+    pub bitmap: Option<Vec<u8>>,
+    #[prost(uint32, required, tag = "3")]
+    pub width: u32,
+    #[prost(uint32, required, tag = "4")]
+    pub height: u32,
+    #[prost(sint32, required, tag = "5")]
+    pub left: i32,
+    #[prost(sint32, required, tag = "6")]
+    pub top: i32,
+    #[prost(uint32, required, tag = "7")]
+    pub advance: u32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Fontstack {
+    #[prost(string, required, tag = "1")]
+    pub name: String,
+    #[prost(string, required, tag = "2")]
+    pub range: String,
+    #[prost(message, repeated, tag = "3")]
+    pub glyphs: Vec<Glyph>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Glyphs {
+    #[prost(message, repeated, tag = "1")]
+    pub stacks: Vec<Fontstack>,
+}
+
+/// Return the names of the fonts in the fonts directory, i.e. its subdirectories
+pub fn get_font_list(fonts_dir: &Path) -> Vec<String> {
+    let mut fonts: Vec<String> = match read_dir(fonts_dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    fonts.sort();
+    fonts
+}
+
+/// Read the glyphs of the given range for a comma-separated list of fonts.
+///
+/// Glyphs are taken from the first font of the list that has them. Fonts missing from the
+/// fonts directory, or whose name is not a plain directory name, are skipped, and `None` is
+/// returned when none of them exist.
+pub fn get_glyphs(fonts_dir: &Path, fontstack: &str, range: &str) -> Result<Option<Vec<u8>>> {
+    let mut merged = Fontstack {
+        name: String::new(),
+        range: range.to_string(),
+        glyphs: Vec::new(),
+    };
+    let mut names = Vec::new();
+    let mut ids = HashSet::new();
+    for font in fontstack.split(',').map(str::trim) {
+        if font.is_empty() || font.starts_with('.') || font.contains(['/', '\\']) {
+            continue;
+        }
+        let data = match read(fonts_dir.join(font).join(format!("{range}.pbf"))) {
+            Ok(data) => decompress(data)?,
+            Err(_) => continue,
+        };
+        let glyphs = match Glyphs::decode(&data[..]) {
+            Ok(glyphs) => glyphs,
+            Err(err) => return Err(Error::InvalidDataFormat(format!("{font}: {err}"))),
+        };
+        names.push(font);
+        for stack in glyphs.stacks {
+            for glyph in stack.glyphs {
+                if ids.insert(glyph.id) {
+                    merged.glyphs.push(glyph);
+                }
+            }
+        }
+    }
+    if names.is_empty() {
+        return Ok(None);
+    }
+    merged.name = names.join(", ");
+    merged.glyphs.sort_by_key(|glyph| glyph.id);
+    Ok(Some(
+        Glyphs {
+            stacks: vec![merged],
+        }
+        .encode_to_vec(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir, write};
+
+    use tempdir::TempDir;
+
+    use super::*;
+
+    fn write_font(dir: &Path, name: &str, ids: &[u32], advance: u32) {
+        let font_dir = dir.join(name);
+        create_dir(&font_dir).unwrap();
+        let glyphs = Glyphs {
+            stacks: vec![Fontstack {
+                name: name.to_string(),
+                range: "0-255".to_string(),
+                glyphs: ids
+                    .iter()
+                    .map(|&id| Glyph {
+                        id,
+                        advance,
+                        ..Default::default()
+                    })
+                    .collect(),
+            }],
+        };
+        write(font_dir.join("0-255.pbf"), glyphs.encode_to_vec()).unwrap();
+    }
+
+    #[test]
+    fn compose_fontstack() {
+        let dir = TempDir::new("fonts").unwrap();
+        write_font(dir.path(), "Noto Sans Regular", &[65, 66], 10);
+        write_font(dir.path(), "Open Sans Regular", &[66, 67], 20);
+
+        assert_eq!(
+            get_font_list(dir.path()),
+            vec!["Noto Sans Regular", "Open Sans Regular"]
+        );
+
+        let data = get_glyphs(
+            dir.path(),
+            "Noto Sans Regular,Missing Font,Open Sans Regular",
+            "0-255",
+        )
+        .unwrap()
+        .unwrap();
+        let glyphs = Glyphs::decode(&data[..]).unwrap();
+        assert_eq!(glyphs.stacks.len(), 1);
+        let stack = &glyphs.stacks[0];
+        assert_eq!(stack.name, "Noto Sans Regular, Open Sans Regular");
+        let advances: Vec<(u32, u32)> = stack.glyphs.iter().map(|g| (g.id, g.advance)).collect();
+        assert_eq!(advances, vec![(65, 10), (66, 10), (67, 20)]);
+
+        assert!(get_glyphs(dir.path(), "Missing Font", "0-255")
+            .unwrap()
+            .is_none());
+        assert!(get_glyphs(dir.path(), "../Noto Sans Regular", "0-255")
+            .unwrap()
+            .is_none());
+    }
+}
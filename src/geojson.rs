@@ -0,0 +1,353 @@
+//! GeoJSON files served as vector tilesets, cut into tiles on the fly
+
+use std::collections::HashMap;
+use std::fs::read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+use log::{info, warn};
+use serde_json::{json, Value as JSONValue};
+use tilejson::{tilejson, Bounds, Center, TileJSON, VectorLayer};
+
+use crate::errors::{Error, Result};
+use crate::tiler::{project, Feature, Geometry, Point, TileIndex, MAX_ZOOM};
+use crate::tiles::{TileMeta, TileSource};
+use crate::utils::{encode, DataFormat};
+
+fn invalid(message: &str) -> Error {
+    Error::InvalidDataFormat(format!("invalid GeoJSON: {message}"))
+}
+
+fn parse_position(position: &JSONValue) -> Result<Point> {
+    match position.as_array().map(|position| position.as_slice()) {
+        Some([lon, lat, ..]) => match (lon.as_f64(), lat.as_f64()) {
+            (Some(lon), Some(lat)) => Ok(project(lon, lat)),
+            _ => Err(invalid("position is not a pair of numbers")),
+        },
+        _ => Err(invalid("position is not a pair of numbers")),
+    }
+}
+
+fn parse_positions(positions: &JSONValue) -> Result<Vec<Point>> {
+    positions
+        .as_array()
+        .ok_or_else(|| invalid("expected an array of positions"))?
+        .iter()
+        .map(parse_position)
+        .collect()
+}
+
+fn parse_rings(rings: &JSONValue) -> Result<Vec<Vec<Point>>> {
+    rings
+        .as_array()
+        .ok_or_else(|| invalid("expected an array of rings"))?
+        .iter()
+        .map(parse_positions)
+        .collect()
+}
+
+/// Parse a GeoJSON geometry into the geometries of MVT features, a geometry collection
+/// giving one geometry per member
+fn parse_geometry(geometry: &JSONValue) -> Result<Vec<Geometry>> {
+    let coordinates = || {
+        geometry
+            .get("coordinates")
+            .ok_or_else(|| invalid("missing coordinates"))
+    };
+    let geometry = match geometry.get("type").and_then(JSONValue::as_str) {
+        Some("Point") => Geometry::Points(vec![parse_position(coordinates()?)?]),
+        Some("MultiPoint") => Geometry::Points(parse_positions(coordinates()?)?),
+        Some("LineString") => Geometry::Lines(vec![parse_positions(coordinates()?)?]),
+        Some("MultiLineString") => Geometry::Lines(parse_rings(coordinates()?)?),
+        Some("Polygon") => Geometry::Polygons(vec![parse_rings(coordinates()?)?]),
+        Some("MultiPolygon") => Geometry::Polygons(
+            coordinates()?
+                .as_array()
+                .ok_or_else(|| invalid("expected an array of polygons"))?
+                .iter()
+                .map(parse_rings)
+                .collect::<Result<_>>()?,
+        ),
+        Some("GeometryCollection") => {
+            let mut geometries = Vec::new();
+            for member in geometry
+                .get("geometries")
+                .and_then(JSONValue::as_array)
+                .ok_or_else(|| invalid("missing geometries"))?
+            {
+                geometries.extend(parse_geometry(member)?);
+            }
+            return Ok(geometries);
+        }
+        Some(geometry_type) => return Err(invalid(&format!("unknown type {geometry_type}"))),
+        None => return Err(invalid("missing geometry type")),
+    };
+    Ok(vec![geometry])
+}
+
+/// Parse a GeoJSON feature collection, feature or geometry into features
+fn parse_features(geojson: &JSONValue) -> Result<Vec<Feature>> {
+    let features = match geojson.get("type").and_then(JSONValue::as_str) {
+        Some("FeatureCollection") => geojson
+            .get("features")
+            .and_then(JSONValue::as_array)
+            .ok_or_else(|| invalid("missing features"))?
+            .iter()
+            .collect(),
+        Some("Feature") => vec![geojson],
+        _ => {
+            return parse_geometry(geojson).map(|geometries| {
+                geometries
+                    .into_iter()
+                    .map(|geometry| Feature::new(None, geometry, Vec::new()))
+                    .collect()
+            })
+        }
+    };
+
+    let mut parsed = Vec::new();
+    for feature in features {
+        let geometry = match feature.get("geometry") {
+            Some(JSONValue::Null) | None => continue,
+            Some(geometry) => geometry,
+        };
+        let id = feature.get("id").and_then(JSONValue::as_u64);
+        let properties: Vec<(String, JSONValue)> = match feature.get("properties") {
+            Some(JSONValue::Object(properties)) => properties
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+            _ => Vec::new(),
+        };
+        for geometry in parse_geometry(geometry)? {
+            parsed.push(Feature::new(id, geometry, properties.clone()));
+        }
+    }
+    Ok(parsed)
+}
+
+/// Version of a file, as its modification time and size
+type FileVersion = Option<(SystemTime, u64)>;
+
+fn file_version(path: &Path) -> FileVersion {
+    let metadata = path.metadata().ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// The features of a GeoJSON file indexed for cutting tiles, with the fields of the TileJSON
+/// derived from them
+#[derive(Debug)]
+struct Index {
+    tiles: TileIndex,
+    bounds: Option<Bounds>,
+    fields: HashMap<String, String>,
+    geometry_type: Option<&'static str>,
+    feature_count: usize,
+}
+
+fn load_index(path: &Path) -> Result<Index> {
+    let data = read(path).map_err(Error::IO)?;
+    let geojson: JSONValue =
+        serde_json::from_slice(&data).map_err(|err| invalid(&err.to_string()))?;
+    let tiles = TileIndex::new(parse_features(&geojson)?);
+    Ok(Index {
+        bounds: tiles.bounds(),
+        fields: tiles.fields(),
+        geometry_type: tiles.geometry_type(),
+        feature_count: tiles.feature_count(),
+        tiles,
+    })
+}
+
+/// A GeoJSON file and the tile index of its features, built again when the file changes
+#[derive(Debug)]
+pub struct GeoJsonTiles {
+    path: PathBuf,
+    layer_name: String,
+    index: RwLock<(FileVersion, Arc<Index>)>,
+}
+
+impl GeoJsonTiles {
+    pub fn open(path: &Path, layer_name: &str) -> Result<Self> {
+        let version = file_version(path);
+        Ok(GeoJsonTiles {
+            path: path.to_path_buf(),
+            layer_name: layer_name.to_string(),
+            index: RwLock::new((version, Arc::new(load_index(path)?))),
+        })
+    }
+
+    /// Return the tile index, reloading the file first when it changed since it was indexed
+    fn index(&self) -> Arc<Index> {
+        let version = file_version(&self.path);
+        {
+            let index = self.index.read().unwrap();
+            if index.0 == version {
+                return index.1.clone();
+            }
+        }
+        let mut index = self.index.write().unwrap();
+        if index.0 != version {
+            match load_index(&self.path) {
+                Ok(reloaded) => {
+                    info!("Reloaded {}", self.path.display());
+                    *index = (version, Arc::new(reloaded));
+                }
+                Err(err) => {
+                    // Keep serving the last valid version, e.g. while the file is being written
+                    warn!("{}: {err}", self.path.display());
+                    index.0 = version;
+                }
+            }
+        }
+        index.1.clone()
+    }
+
+    /// Cut the tile at the given XYZ position, gzipped as vector tiles are stored in mbtiles
+    pub fn get_tile(&self, z: u32, x: u32, y: u32) -> Result<Vec<u8>> {
+        self.index()
+            .tiles
+            .get_tile(&self.layer_name, z, x, y)
+            .map(|tile| encode(&tile))
+            .ok_or(Error::TileNotFound)
+    }
+
+    /// Set the bounds, center, vector layer and tilestats of a TileJSON from the features of
+    /// the file, reloading it first when it changed
+    pub fn update_tilejson(&self, tilejson: &mut TileJSON) {
+        let index = self.index();
+        tilejson.bounds = index.bounds;
+        tilejson.center = index.bounds.map(|bounds| {
+            Center::new(
+                (bounds.left + bounds.right) / 2.0,
+                (bounds.bottom + bounds.top) / 2.0,
+                0,
+            )
+        });
+        tilejson.vector_layers = Some(vec![VectorLayer::new(
+            self.layer_name.clone(),
+            index.fields.clone(),
+        )]);
+        match index.geometry_type {
+            Some(geometry) => {
+                tilejson.other.insert(
+                    "tilestats".to_string(),
+                    json!({
+                        "layerCount": 1,
+                        "layers": [{
+                            "layer": self.layer_name,
+                            "count": index.feature_count,
+                            "geometry": geometry,
+                        }],
+                    }),
+                );
+            }
+            None => {
+                tilejson.other.remove("tilestats");
+            }
+        }
+    }
+}
+
+/// Index the features of a GeoJSON file and build its TileJSON, with a single vector layer
+/// named after the file
+pub fn get_tile_details(path: &Path, tile_name: &str) -> Result<TileMeta> {
+    let layer_name = path
+        .file_stem()
+        .and_then(|name| name.to_str())
+        .unwrap_or(tile_name);
+    let geojson = GeoJsonTiles::open(path, layer_name)?;
+
+    let mut tilejson = tilejson! {
+        tilejson: "3.0.0".to_string(),
+        tiles: vec!["".to_string()],
+    };
+    tilejson.name = Some(tile_name.to_string());
+    tilejson.minzoom = Some(0);
+    tilejson.maxzoom = Some(MAX_ZOOM as u8);
+    geojson.update_tilejson(&mut tilejson);
+
+    Ok(TileMeta {
+        source: TileSource::GeoJson(Arc::new(geojson)),
+        path: PathBuf::from(path),
+        tilejson,
+        id: tile_name.to_string(),
+        tile_format: DataFormat::Pbf,
+        grid_format: None,
+        layer_type: Some("overlay".to_string()),
+        json: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+
+    use prost::Message;
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::mvt::Tile;
+    use crate::utils::decompress;
+
+    #[test]
+    fn get_geojson_details() {
+        let tile_meta =
+            get_tile_details(&PathBuf::from("./tiles/incidents.geojson"), "incidents").unwrap();
+        assert_eq!(tile_meta.tile_format, DataFormat::Pbf);
+        let vector_layers = tile_meta.tilejson.vector_layers.unwrap();
+        assert_eq!(vector_layers[0].id, "incidents");
+        assert_eq!(vector_layers[0].fields["severity"], "Number");
+        assert_eq!(vector_layers[0].fields["kind"], "String");
+        assert_eq!(
+            tile_meta.tilejson.other["tilestats"]["layers"][0]["geometry"],
+            "Point"
+        );
+        let bounds = tile_meta.tilejson.bounds.unwrap();
+        assert!((bounds.left + 122.4194).abs() < 1e-6);
+    }
+
+    #[test]
+    fn reload_changed_file() {
+        let dir = TempDir::new("geojson").unwrap();
+        let path = dir.path().join("points.geojson");
+        let point = |lon: f64| {
+            json!({"type": "Feature", "geometry": {"type": "Point", "coordinates": [lon, 10.0]}, "properties": {}})
+                .to_string()
+        };
+        write(&path, point(10.0)).unwrap();
+        let geojson = GeoJsonTiles::open(&path, "points").unwrap();
+        assert!(geojson.get_tile(1, 1, 0).is_ok());
+        assert!(matches!(
+            geojson.get_tile(1, 0, 0),
+            Err(Error::TileNotFound)
+        ));
+
+        let mut tilejson = get_tile_details(&path, "points").unwrap().tilejson;
+
+        write(
+            &path,
+            json!({"type": "Feature", "geometry": {"type": "LineString", "coordinates": [[-100.0, 10.0], [-90.0, 20.0]]}, "properties": {"name": "road"}})
+                .to_string(),
+        )
+        .unwrap();
+        let tile = decompress(geojson.get_tile(1, 0, 0).unwrap()).unwrap();
+        let tile = Tile::decode(&tile[..]).unwrap();
+        assert_eq!(tile.layers[0].name, "points");
+        assert!(matches!(
+            geojson.get_tile(1, 1, 0),
+            Err(Error::TileNotFound)
+        ));
+
+        geojson.update_tilejson(&mut tilejson);
+        let bounds = tilejson.bounds.unwrap();
+        assert!((bounds.left + 100.0).abs() < 1e-6);
+        assert!((bounds.right + 90.0).abs() < 1e-6);
+        assert_eq!(tilejson.vector_layers.unwrap()[0].fields["name"], "String");
+        assert_eq!(
+            tilejson.other["tilestats"]["layers"][0]["geometry"],
+            "LineString"
+        );
+    }
+}
@@ -0,0 +1,87 @@
+//! Glob patterns selecting the files served, matched against paths relative to their source
+
+use regex::Regex;
+
+use crate::errors::{Error, Result};
+
+/// A pattern where `*` matches within a path segment, `**` across segments, `?` a single
+/// character and `[...]` a character class. A pattern without `/` matches the name of a file in
+/// any directory, like in `.gitignore` files.
+#[derive(Clone, Debug)]
+pub struct Glob {
+    regex: Regex,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Result<Self> {
+        let mut regex = String::from("^");
+        let relative = pattern.strip_prefix('/').unwrap_or(pattern);
+        if !pattern.contains('/') {
+            regex.push_str("(?:.*/)?");
+        }
+        let mut chars = relative.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    if chars.peek() == Some(&'/') {
+                        chars.next();
+                        regex.push_str("(?:.*/)?");
+                    } else {
+                        regex.push_str(".*");
+                    }
+                }
+                '*' => regex.push_str("[^/]*"),
+                '?' => regex.push_str("[^/]"),
+                '[' => {
+                    let mut class = String::new();
+                    for c in chars.by_ref() {
+                        if c == ']' {
+                            break;
+                        }
+                        class.push(c);
+                    }
+                    match class.strip_prefix('!') {
+                        Some(negated) => regex.push_str(&format!("[^{negated}]")),
+                        None => regex.push_str(&format!("[{class}]")),
+                    }
+                }
+                c => regex.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        regex.push('$');
+        Regex::new(&regex)
+            .map(|regex| Glob { regex })
+            .map_err(|_| Error::Config(format!("Invalid glob pattern: {pattern}")))
+    }
+
+    /// Whether a `/` separated relative path matches the pattern
+    pub fn matches(&self, path: &str) -> bool {
+        self.regex.is_match(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_globs() {
+        let glob = Glob::new("*.staging.mbtiles").unwrap();
+        assert!(glob.matches("world.staging.mbtiles"));
+        assert!(glob.matches("countries/world.staging.mbtiles"));
+        assert!(!glob.matches("world.mbtiles"));
+
+        let glob = Glob::new("scratch/*").unwrap();
+        assert!(glob.matches("scratch/world.mbtiles"));
+        assert!(!glob.matches("scratch/old/world.mbtiles"));
+        assert!(!glob.matches("countries/scratch/world.mbtiles"));
+
+        let glob = Glob::new("**/202?-[0-9][!0-9]/*.pmtiles").unwrap();
+        assert!(glob.matches("2024-1a/world.pmtiles"));
+        assert!(glob.matches("backups/2024-1a/world.pmtiles"));
+        assert!(!glob.matches("2024-12/world.pmtiles"));
+
+        assert!(Glob::new("[z-a]").is_err());
+    }
+}
@@ -0,0 +1,311 @@
+//! Tile pyramids of GeoPackages as described by
+//! <https://www.geopackage.org/spec/#tiles>
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use log::warn;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{params, OpenFlags, OptionalExtension};
+use tilejson::{tilejson, Bounds};
+
+use crate::errors::{Error, Result};
+use crate::tiles::{TileMeta, TileSource};
+use crate::utils::{get_data_format, meters_to_lonlat, DataFormat, WEB_MERCATOR_EXTENT};
+
+type Pool = r2d2::Pool<SqliteConnectionManager>;
+
+/// Position of a GeoPackage tile matrix in the Web Mercator tile grid
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct TileMatrix {
+    zoom_level: u32,
+    /// Web Mercator column and row of the top-left tile of the matrix
+    min_col: i64,
+    min_row: i64,
+}
+
+/// A tile pyramid table of a GeoPackage whose tile matrices are aligned with the Web Mercator
+/// tile grid
+#[derive(Clone, Debug)]
+pub struct GeoPackageTiles {
+    connection_pool: Pool,
+    table: String,
+    /// Tile matrices keyed by Web Mercator zoom
+    tile_matrices: HashMap<u32, TileMatrix>,
+}
+
+impl GeoPackageTiles {
+    /// Read the tile at the given XYZ position
+    pub fn get_tile(&self, z: u32, x: u32, y: u32) -> Result<Vec<u8>> {
+        let matrix = self.tile_matrices.get(&z).ok_or(Error::TileNotFound)?;
+        let connection = self.connection_pool.get().map_err(Error::Pool)?;
+        let query = format!(
+            "SELECT tile_data FROM {} WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+            quote_identifier(&self.table)
+        );
+        let mut statement = connection.prepare(&query).map_err(Error::DBConnection)?;
+        statement
+            .query_row(
+                params![
+                    matrix.zoom_level,
+                    i64::from(x) - matrix.min_col,
+                    i64::from(y) - matrix.min_row
+                ],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(Error::DBConnection)?
+            .ok_or(Error::TileNotFound)
+    }
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Locate a tile matrix in the Web Mercator tile grid from the top-left corner of its tile
+/// matrix set and the size of its tiles in meters. Returns `None` when its tiles don't line
+/// up with the tiles of a Web Mercator zoom level.
+fn web_mercator_zoom(min_x: f64, max_y: f64, tile_span: f64) -> Option<(u32, i64, i64)> {
+    let aligned = |value: f64| {
+        let rounded = value.round();
+        ((value - rounded).abs() < 1e-3).then_some(rounded)
+    };
+    let z = aligned((2.0 * WEB_MERCATOR_EXTENT / tile_span).log2())?;
+    if !(0.0..=30.0).contains(&z) {
+        return None;
+    }
+    let min_col = aligned((min_x + WEB_MERCATOR_EXTENT) / tile_span)?;
+    let min_row = aligned((WEB_MERCATOR_EXTENT - max_y) / tile_span)?;
+    Some((z as u32, min_col as i64, min_row as i64))
+}
+
+fn is_web_mercator(connection: &rusqlite::Connection, srs_id: i64) -> bool {
+    connection
+        .query_row(
+            "SELECT organization, organization_coordsys_id FROM gpkg_spatial_ref_sys WHERE srs_id = ?1",
+            params![srs_id],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)),
+        )
+        .map(|(organization, code)| organization.eq_ignore_ascii_case("EPSG") && code == 3857)
+        .unwrap_or(false)
+}
+
+/// Convert bounds in the given spatial reference system to WGS84, when it is either Web
+/// Mercator or WGS84
+fn get_bounds(
+    connection: &rusqlite::Connection,
+    srs_id: i64,
+    [min_x, min_y, max_x, max_y]: [f64; 4],
+) -> Option<Bounds> {
+    if is_web_mercator(connection, srs_id) {
+        let (left, bottom) = meters_to_lonlat(min_x, min_y);
+        let (right, top) = meters_to_lonlat(max_x, max_y);
+        return Some(Bounds::new(left, bottom, right, top));
+    }
+    let is_wgs84 = connection
+        .query_row(
+            "SELECT organization_coordsys_id FROM gpkg_spatial_ref_sys WHERE srs_id = ?1 AND organization = 'EPSG' COLLATE NOCASE",
+            params![srs_id],
+            |row| row.get::<_, i64>(0),
+        )
+        .is_ok_and(|code| code == 4326);
+    is_wgs84.then(|| Bounds::new(min_x, min_y, max_x, max_y))
+}
+
+fn get_table_details(
+    connection_pool: &Pool,
+    path: &Path,
+    table: &str,
+    identifier: Option<String>,
+    description: Option<String>,
+    contents_bounds: Option<(i64, [f64; 4])>,
+) -> Result<TileMeta> {
+    let connection = connection_pool.get().map_err(Error::Pool)?;
+    let (srs_id, min_x, min_y, max_x, max_y): (i64, f64, f64, f64, f64) = connection
+        .query_row(
+            "SELECT srs_id, min_x, min_y, max_x, max_y FROM gpkg_tile_matrix_set WHERE table_name = ?1",
+            params![table],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)),
+        )
+        .map_err(Error::DBConnection)?;
+    if !is_web_mercator(&connection, srs_id) {
+        return Err(Error::Config(format!(
+            "{table}: only Web Mercator tile matrix sets are supported"
+        )));
+    }
+
+    let mut tile_matrices = HashMap::new();
+    let mut statement = connection
+        .prepare("SELECT zoom_level, tile_width, pixel_x_size FROM gpkg_tile_matrix WHERE table_name = ?1")
+        .map_err(Error::DBConnection)?;
+    let rows = statement
+        .query_map(params![table], |row| {
+            Ok((
+                row.get::<_, u32>(0)?,
+                row.get::<_, f64>(1)?,
+                row.get::<_, f64>(2)?,
+            ))
+        })
+        .map_err(Error::DBConnection)?;
+    for row in rows {
+        let (zoom_level, tile_width, pixel_x_size) = row.map_err(Error::DBConnection)?;
+        match web_mercator_zoom(min_x, max_y, tile_width * pixel_x_size) {
+            Some((z, min_col, min_row)) => {
+                tile_matrices.insert(
+                    z,
+                    TileMatrix {
+                        zoom_level,
+                        min_col,
+                        min_row,
+                    },
+                );
+            }
+            None => warn!(
+                "{table}: zoom level {zoom_level} is not aligned with the Web Mercator tile grid"
+            ),
+        }
+    }
+    let (minzoom, maxzoom) = match (tile_matrices.keys().min(), tile_matrices.keys().max()) {
+        (Some(minzoom), Some(maxzoom)) => (*minzoom, *maxzoom),
+        _ => return Err(Error::MissingTable(format!("{table}: no tile matrix"))),
+    };
+
+    let tile_format = connection
+        .query_row(
+            &format!("SELECT tile_data FROM {} LIMIT 1", quote_identifier(table)),
+            [],
+            |row| Ok(get_data_format(&row.get::<_, Vec<u8>>(0)?)),
+        )
+        .unwrap_or(DataFormat::Unknown);
+    if !tile_format.is_image() {
+        return Err(Error::UnknownTileFormat(table.to_string()));
+    }
+
+    let mut tilejson = tilejson! {
+        tilejson: "3.0.0".to_string(),
+        tiles: vec!["".to_string()],
+    };
+    tilejson.name = Some(identifier.unwrap_or_else(|| table.to_string()));
+    tilejson.description = description.filter(|description| !description.is_empty());
+    tilejson.minzoom = Some(minzoom as u8);
+    tilejson.maxzoom = Some(maxzoom as u8);
+    tilejson.bounds = contents_bounds
+        .and_then(|(srs_id, bounds)| get_bounds(&connection, srs_id, bounds))
+        .or_else(|| get_bounds(&connection, srs_id, [min_x, min_y, max_x, max_y]));
+
+    Ok(TileMeta {
+        source: TileSource::GeoPackage(GeoPackageTiles {
+            connection_pool: connection_pool.clone(),
+            table: table.to_string(),
+            tile_matrices,
+        }),
+        path: PathBuf::from(path),
+        tilejson,
+        id: table.to_string(),
+        tile_format,
+        grid_format: None,
+        layer_type: None,
+        json: None,
+    })
+}
+
+/// Return the tile pyramid tables of a GeoPackage listed in `gpkg_contents`, each as its own
+/// tileset keyed by table name. Tables that can't be served are skipped with a warning.
+pub fn get_tilesets(path: &Path) -> Result<Vec<(String, TileMeta)>> {
+    let manager = SqliteConnectionManager::file(path).with_flags(OpenFlags::SQLITE_OPEN_READ_ONLY);
+    let connection_pool = r2d2::Pool::new(manager).map_err(Error::Pool)?;
+    let connection = connection_pool.get().map_err(Error::Pool)?;
+
+    let mut statement = connection
+        .prepare(
+            r#"SELECT table_name, identifier, description, srs_id, min_x, min_y, max_x, max_y
+                 FROM gpkg_contents
+                WHERE data_type = 'tiles'
+            "#,
+        )
+        .map_err(|_| Error::MissingTable(path.display().to_string()))?;
+    let tables = statement
+        .query_map([], |row| {
+            let bounds = match (
+                row.get(3)?,
+                row.get(4)?,
+                row.get(5)?,
+                row.get(6)?,
+                row.get(7)?,
+            ) {
+                (Some(srs_id), Some(min_x), Some(min_y), Some(max_x), Some(max_y)) => {
+                    Some((srs_id, [min_x, min_y, max_x, max_y]))
+                }
+                _ => None,
+            };
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, bounds))
+        })
+        .map_err(Error::DBConnection)?
+        .collect::<std::result::Result<Vec<(String, Option<String>, Option<String>, _)>, _>>()
+        .map_err(Error::DBConnection)?;
+
+    let mut tilesets = Vec::new();
+    for (table, identifier, description, bounds) in tables {
+        match get_table_details(
+            &connection_pool,
+            path,
+            &table,
+            identifier,
+            description,
+            bounds,
+        ) {
+            Ok(tile_meta) => tilesets.push((table, tile_meta)),
+            Err(err) => warn!("{}: {err}", path.display()),
+        }
+    }
+    Ok(tilesets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn web_mercator_tile_matrices() {
+        let world = 2.0 * WEB_MERCATOR_EXTENT;
+        assert_eq!(
+            web_mercator_zoom(-WEB_MERCATOR_EXTENT, WEB_MERCATOR_EXTENT, world),
+            Some((0, 0, 0))
+        );
+        // A matrix set covering the north-east quarter of the world, with tiles of zoom 3
+        assert_eq!(
+            web_mercator_zoom(0.0, WEB_MERCATOR_EXTENT, world / 8.0),
+            Some((3, 4, 0))
+        );
+        assert_eq!(
+            web_mercator_zoom(-WEB_MERCATOR_EXTENT, WEB_MERCATOR_EXTENT, world / 3.0),
+            None
+        );
+    }
+
+    #[test]
+    fn get_geopackage_tilesets() {
+        let tilesets = get_tilesets(&PathBuf::from("./tiles/geography-class.gpkg")).unwrap();
+        assert_eq!(tilesets.len(), 1);
+        let (table, tile_meta) = &tilesets[0];
+        assert_eq!(table, "geography_class");
+        assert_eq!(tile_meta.tile_format, DataFormat::Png);
+        assert_eq!(tile_meta.tilejson.name.as_deref(), Some("Geography Class"));
+        assert_eq!(tile_meta.tilejson.minzoom, Some(0));
+        assert_eq!(tile_meta.tilejson.maxzoom, Some(1));
+        let bounds = tile_meta.tilejson.bounds.unwrap();
+        assert!((bounds.left + 170.0).abs() < 1e-6);
+        assert!((bounds.top - 75.0).abs() < 1e-6);
+
+        let source = match &tile_meta.source {
+            TileSource::GeoPackage(source) => source,
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            get_data_format(&source.get_tile(1, 1, 0).unwrap()),
+            DataFormat::Png
+        );
+        assert!(matches!(source.get_tile(2, 0, 0), Err(Error::TileNotFound)));
+    }
+}
@@ -1,12 +1,34 @@
 use clap::Parser;
 use log::error;
 
+mod cog;
 mod config;
+mod directory;
+mod discovery;
 mod errors;
+mod fgb;
+mod fonts;
+mod geojson;
+mod glob;
+mod gpkg;
+mod mvt;
+mod ogcapi;
+mod pmtiles;
+mod pool;
+mod raster;
+mod s3;
 mod server;
 mod service;
+mod sprites;
+mod style;
+mod styles;
+mod tiler;
 mod tiles;
+mod tms;
 mod utils;
+mod vfs;
+mod wmts;
+mod zip;
 
 fn main() {
     eprintln!("####################################################################");
@@ -22,6 +44,12 @@ fn main() {
         std::process::exit(1)
     });
 
+    if let Some(config::Command::Discover) = args.command {
+        let report = discovery::report(&args.tilesets, &args.skipped);
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+        return;
+    }
+
     if let Err(e) = server::run(args) {
         error!("Server error: {e}");
         std::process::exit(1);
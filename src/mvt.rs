@@ -0,0 +1,140 @@
+//! Mapbox Vector Tile protobuf messages as described by
+//! <https://github.com/mapbox/vector-tile-spec/blob/master/2.1/vector_tile.proto>
+
+use std::collections::HashSet;
+
+use prost::Message;
+
+use crate::errors::{Error, Result};
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Tile {
+    #[prost(message, repeated, tag = "3")]
+    pub layers: Vec<Layer>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Value {
+    #[prost(string, optional, tag = "1")]
+    pub string_value: Option<String>,
+    #[prost(float, optional, tag = "2")]
+    pub float_value: Option<f32>,
+    #[prost(double, optional, tag = "3")]
+    pub double_value: Option<f64>,
+    #[prost(int64, optional, tag = "4")]
+    pub int_value: Option<i64>,
+    #[prost(uint64, optional, tag = "5")]
+    pub uint_value: Option<u64>,
+    #[prost(sint64, optional, tag = "6")]
+    pub sint_value: Option<i64>,
+    #[prost(bool, optional, tag = "7")]
+    pub bool_value: Option<bool>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, prost::Enumeration)]
+#[repr(i32)]
+pub enum GeomType {
+    Unknown = 0,
+    Point = 1,
+    Linestring = 2,
+    Polygon = 3,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Feature {
+    #[prost(uint64, optional, tag = "1")]
+    pub id: Option<u64>,
+    #[prost(uint32, repeated, tag = "2")]
+    pub tags: Vec<u32>,
+    #[prost(enumeration = "GeomType", optional, tag = "3")]
+    pub r#type: Option<i32>,
+    #[prost(uint32, repeated, tag = "4")]
+    pub geometry: Vec<u32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Layer {
+    #[prost(uint32, required, tag = "15")]
+    pub version: u32,
+    #[prost(string, required, tag = "1")]
+    pub name: String,
+    #[prost(message, repeated, tag = "2")]
+    pub features: Vec<Feature>,
+    #[prost(string, repeated, tag = "3")]
+    pub keys: Vec<String>,
+    #[prost(message, repeated, tag = "4")]
+    pub values: Vec<Value>,
+    #[prost(uint32, optional, tag = "5")]
+    pub extent: Option<u32>,
+}
+
+/// Name given to a layer of `tileset` whose name is already used by an earlier tileset
+pub fn prefixed_layer_name(tileset: &str, layer: &str) -> String {
+    format!("{tileset}:{layer}")
+}
+
+/// Merge the layers of uncompressed vector tiles into a single tile.
+///
+/// Tiles are given in drawing order along with their tileset name and the layer names that
+/// must be renamed for that tileset. Any other layer whose name was already used by an
+/// earlier tile is renamed as well, so that layer names stay unique in the merged tile.
+pub fn merge_tiles(tiles: &[(&str, &HashSet<String>, Vec<u8>)]) -> Result<Vec<u8>> {
+    let mut merged = Tile::default();
+    let mut names = HashSet::new();
+    for (tileset, collisions, data) in tiles {
+        let tile = match Tile::decode(&data[..]) {
+            Ok(tile) => tile,
+            Err(err) => return Err(Error::InvalidDataFormat(format!("{tileset}: {err}"))),
+        };
+        for mut layer in tile.layers {
+            if collisions.contains(&layer.name) || names.contains(&layer.name) {
+                layer.name = prefixed_layer_name(tileset, &layer.name);
+            }
+            names.insert(layer.name.clone());
+            merged.layers.push(layer);
+        }
+    }
+    Ok(merged.encode_to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile_with_layers(names: &[&str]) -> Vec<u8> {
+        Tile {
+            layers: names
+                .iter()
+                .map(|name| Layer {
+                    version: 2,
+                    name: name.to_string(),
+                    extent: Some(4096),
+                    ..Default::default()
+                })
+                .collect(),
+        }
+        .encode_to_vec()
+    }
+
+    #[test]
+    fn merge_tiles_renames_colliding_layers() {
+        let no_collisions = HashSet::new();
+        let collisions = HashSet::from(["roads".to_string()]);
+        let merged = merge_tiles(&[
+            ("a", &no_collisions, tile_with_layers(&["water", "roads"])),
+            ("b", &collisions, tile_with_layers(&["water", "labels"])),
+            ("c", &collisions, tile_with_layers(&["roads"])),
+        ])
+        .unwrap();
+        let names: Vec<String> = Tile::decode(&merged[..])
+            .unwrap()
+            .layers
+            .into_iter()
+            .map(|layer| layer.name)
+            .collect();
+        assert_eq!(
+            names,
+            vec!["water", "roads", "b:water", "labels", "c:roads"]
+        );
+    }
+}
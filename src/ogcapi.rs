@@ -0,0 +1,342 @@
+//! OGC API - Tiles documents, see <https://docs.ogc.org/is/20-057/20-057.html>
+
+use std::collections::HashMap;
+
+use serde_json::{json, Value as JSONValue};
+
+use crate::tiles::TileMeta;
+use crate::utils::{escape_xml, get_tile_limits, DataFormat, WEB_MERCATOR_EXTENT};
+
+/// Identifier of the only tile matrix set offered
+pub const TILE_MATRIX_SET: &str = "WebMercatorQuad";
+
+const TILE_MATRIX_SET_URI: &str =
+    "http://www.opengis.net/def/tilematrixset/OGC/1.0/WebMercatorQuad";
+
+const CRS: &str = "http://www.opengis.net/def/crs/EPSG/0/3857";
+
+const CRS84: &str = "http://www.opengis.net/def/crs/OGC/1.3/CRS84";
+
+/// Highest tile matrix of the `WebMercatorQuad` tile matrix set
+const MAX_TILE_MATRIX: u8 = 24;
+
+const CONFORMANCE: [&str; 12] = [
+    "http://www.opengis.net/spec/ogcapi-common-1/1.0/conf/core",
+    "http://www.opengis.net/spec/ogcapi-common-1/1.0/conf/landing-page",
+    "http://www.opengis.net/spec/ogcapi-common-1/1.0/conf/json",
+    "http://www.opengis.net/spec/ogcapi-common-1/1.0/conf/html",
+    "http://www.opengis.net/spec/ogcapi-common-2/1.0/conf/collections",
+    "http://www.opengis.net/spec/ogcapi-tiles-1/1.0/conf/core",
+    "http://www.opengis.net/spec/ogcapi-tiles-1/1.0/conf/tileset",
+    "http://www.opengis.net/spec/ogcapi-tiles-1/1.0/conf/tilesets-list",
+    "http://www.opengis.net/spec/ogcapi-tiles-1/1.0/conf/geodata-tilesets",
+    "http://www.opengis.net/spec/ogcapi-tiles-1/1.0/conf/png",
+    "http://www.opengis.net/spec/ogcapi-tiles-1/1.0/conf/jpeg",
+    "http://www.opengis.net/spec/ogcapi-tiles-1/1.0/conf/mvt",
+];
+
+/// Percent-encode a collection id so that tilesets in subdirectories fit in one path segment
+pub fn encode_collection_id(id: &str) -> String {
+    id.replace('%', "%25").replace('/', "%2F")
+}
+
+fn link(href: String, rel: &str, media_type: &str, title: &str) -> JSONValue {
+    json!({ "href": href, "rel": rel, "type": media_type, "title": title })
+}
+
+/// Self link along with the alternate JSON or HTML representation of a document
+fn self_links(url: &str, title: &str) -> Vec<JSONValue> {
+    vec![
+        link(format!("{url}?f=json"), "self", "application/json", title),
+        link(format!("{url}?f=html"), "alternate", "text/html", title),
+    ]
+}
+
+fn data_type(tile_meta: &TileMeta) -> &'static str {
+    match tile_meta.tile_format {
+        DataFormat::Pbf => "vector",
+        _ => "map",
+    }
+}
+
+pub fn get_landing_page(root_url: &str) -> JSONValue {
+    let mut links = self_links(root_url, "Landing page");
+    links.push(link(
+        format!("{root_url}/conformance"),
+        "http://www.opengis.net/def/rel/ogc/1.0/conformance",
+        "application/json",
+        "Conformance classes",
+    ));
+    links.push(link(
+        format!("{root_url}/collections"),
+        "http://www.opengis.net/def/rel/ogc/1.0/data",
+        "application/json",
+        "Collections",
+    ));
+    links.push(link(
+        format!("{root_url}/tileMatrixSets"),
+        "http://www.opengis.net/def/rel/ogc/1.0/tiling-schemes",
+        "application/json",
+        "Tile matrix sets",
+    ));
+    json!({
+        "title": "mbtileserver",
+        "description": "Map tiles served through OGC API - Tiles",
+        "links": links,
+    })
+}
+
+pub fn get_conformance() -> JSONValue {
+    json!({ "conformsTo": CONFORMANCE })
+}
+
+pub fn get_collection(id: &str, tile_meta: &TileMeta, root_url: &str) -> JSONValue {
+    let tilejson = &tile_meta.tilejson;
+    let bounds = tilejson.bounds.unwrap_or_default();
+    let url = format!("{root_url}/collections/{}", encode_collection_id(id));
+    let title = tilejson.name.as_deref().unwrap_or(id);
+    let mut links = self_links(&url, title);
+    links.push(link(
+        format!("{url}/tiles"),
+        &format!(
+            "http://www.opengis.net/def/rel/ogc/1.0/tilesets-{}",
+            data_type(tile_meta)
+        ),
+        "application/json",
+        "Tilesets",
+    ));
+    json!({
+        "id": id,
+        "title": title,
+        "description": tilejson.description,
+        "attribution": tilejson.attribution,
+        "dataType": data_type(tile_meta),
+        "extent": {
+            "spatial": {
+                "bbox": [[bounds.left, bounds.bottom, bounds.right, bounds.top]],
+                "crs": CRS84,
+            },
+        },
+        "crs": [CRS84, CRS],
+        "links": links,
+    })
+}
+
+pub fn get_collections(tilesets: &HashMap<String, TileMeta>, root_url: &str) -> JSONValue {
+    let mut ids: Vec<&String> = tilesets.keys().collect();
+    ids.sort();
+    let collections: Vec<JSONValue> = ids
+        .into_iter()
+        .map(|id| get_collection(id, &tilesets[id], root_url))
+        .collect();
+    json!({
+        "links": self_links(&format!("{root_url}/collections"), "Collections"),
+        "collections": collections,
+    })
+}
+
+/// Summary of the `WebMercatorQuad` tileset of a collection, as listed by its tilesets
+fn get_tileset_summary(id: &str, tile_meta: &TileMeta, root_url: &str) -> JSONValue {
+    let url = format!(
+        "{root_url}/collections/{}/tiles/{TILE_MATRIX_SET}",
+        encode_collection_id(id)
+    );
+    let mut links = self_links(&url, "Tileset");
+    links.push(link(
+        format!("{root_url}/tileMatrixSets/{TILE_MATRIX_SET}"),
+        "http://www.opengis.net/def/rel/ogc/1.0/tiling-scheme",
+        "application/json",
+        "Tile matrix set",
+    ));
+    json!({
+        "title": tile_meta.tilejson.name.as_deref().unwrap_or(id),
+        "dataType": data_type(tile_meta),
+        "crs": CRS,
+        "tileMatrixSetURI": TILE_MATRIX_SET_URI,
+        "links": links,
+    })
+}
+
+pub fn get_tilesets(id: &str, tile_meta: &TileMeta, root_url: &str) -> JSONValue {
+    let url = format!("{root_url}/collections/{}/tiles", encode_collection_id(id));
+    json!({
+        "links": self_links(&url, "Tilesets"),
+        "tilesets": [get_tileset_summary(id, tile_meta, root_url)],
+    })
+}
+
+pub fn get_tileset(id: &str, tile_meta: &TileMeta, root_url: &str) -> JSONValue {
+    let tilejson = &tile_meta.tilejson;
+    let bounds = tilejson.bounds.unwrap_or_default();
+    let minzoom = tilejson.minzoom.unwrap_or(0).min(MAX_TILE_MATRIX);
+    let maxzoom = tilejson
+        .maxzoom
+        .unwrap_or(MAX_TILE_MATRIX)
+        .min(MAX_TILE_MATRIX);
+
+    let mut tileset = get_tileset_summary(id, tile_meta, root_url);
+    let limits: Vec<JSONValue> = (minzoom..=maxzoom)
+        .map(|z| {
+            let (min_col, min_row, max_col, max_row) = get_tile_limits(&bounds, u32::from(z));
+            json!({
+                "tileMatrix": z.to_string(),
+                "minTileRow": min_row,
+                "maxTileRow": max_row,
+                "minTileCol": min_col,
+                "maxTileCol": max_col,
+            })
+        })
+        .collect();
+    tileset["tileMatrixSetLimits"] = json!(limits);
+    tileset["boundingBox"] = json!({
+        "lowerLeft": [bounds.left, bounds.bottom],
+        "upperRight": [bounds.right, bounds.top],
+        "crs": CRS84,
+    });
+    if let Some(center) = tilejson.center {
+        tileset["centerPoint"] = json!({
+            "coordinates": [center.longitude, center.latitude],
+            "tileMatrix": center.zoom.to_string(),
+            "crs": CRS84,
+        });
+    }
+    if tile_meta.tile_format == DataFormat::Pbf {
+        let layers: Vec<JSONValue> = tilejson
+            .vector_layers
+            .iter()
+            .flatten()
+            .map(|layer| {
+                json!({
+                    "id": layer.id,
+                    "description": layer.description,
+                    "minTileMatrix": layer.minzoom.map(|z| z.to_string()),
+                    "maxTileMatrix": layer.maxzoom.map(|z| z.to_string()),
+                    "propertiesSchema": {
+                        "type": "object",
+                        "properties": layer.fields.iter().map(|(name, description)| {
+                            (name.clone(), json!({ "description": description }))
+                        }).collect::<serde_json::Map<_, _>>(),
+                    },
+                })
+            })
+            .collect();
+        tileset["layers"] = json!(layers);
+    }
+    let mut tiles_link = link(
+        format!(
+            "{root_url}/collections/{}/tiles/{TILE_MATRIX_SET}/{{tileMatrix}}/{{tileRow}}/{{tileCol}}",
+            encode_collection_id(id)
+        ),
+        "item",
+        tile_meta.tile_format.content_type(),
+        "Tiles",
+    );
+    tiles_link["templated"] = json!(true);
+    tileset["links"].as_array_mut().unwrap().push(tiles_link);
+    tileset
+}
+
+pub fn get_tile_matrix_sets(root_url: &str) -> JSONValue {
+    let url = format!("{root_url}/tileMatrixSets/{TILE_MATRIX_SET}");
+    json!({
+        "tileMatrixSets": [{
+            "id": TILE_MATRIX_SET,
+            "title": "Google Maps Compatible for the World",
+            "uri": TILE_MATRIX_SET_URI,
+            "links": [link(url, "self", "application/json", TILE_MATRIX_SET)],
+        }],
+    })
+}
+
+pub fn get_tile_matrix_set() -> JSONValue {
+    let matrices: Vec<JSONValue> = (0..=MAX_TILE_MATRIX)
+        .map(|z| {
+            let size = 1u32 << z;
+            json!({
+                "id": z.to_string(),
+                "scaleDenominator": 559_082_264.028_717_8 / f64::from(size),
+                "cellSize": 156_543.033_928_041 / f64::from(size),
+                "cornerOfOrigin": "topLeft",
+                "pointOfOrigin": [-WEB_MERCATOR_EXTENT, WEB_MERCATOR_EXTENT],
+                "tileWidth": 256,
+                "tileHeight": 256,
+                "matrixWidth": size,
+                "matrixHeight": size,
+            })
+        })
+        .collect();
+    json!({
+        "id": TILE_MATRIX_SET,
+        "title": "Google Maps Compatible for the World",
+        "uri": TILE_MATRIX_SET_URI,
+        "crs": CRS,
+        "orderedAxes": ["E", "N"],
+        "wellKnownScaleSet": "http://www.opengis.net/def/wkss/OGC/1.0/GoogleMapsCompatible",
+        "tileMatrices": matrices,
+    })
+}
+
+/// Render a document as a simple HTML page listing its links and its JSON content
+pub fn to_html(title: &str, doc: &JSONValue) -> String {
+    let mut html = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"UTF-8\"/><title>{0}</title></head><body><h1>{0}</h1>",
+        escape_xml(title)
+    );
+    if let Some(description) = doc["description"].as_str() {
+        html.push_str(&format!("<p>{}</p>", escape_xml(description)));
+    }
+    let mut links: Vec<&JSONValue> = doc["links"].as_array().into_iter().flatten().collect();
+    for collection in doc["collections"].as_array().into_iter().flatten() {
+        links.extend(collection["links"].as_array().into_iter().flatten().take(1));
+    }
+    if !links.is_empty() {
+        html.push_str("<ul>");
+        for link in links {
+            let href = link["href"].as_str().unwrap_or_default();
+            html.push_str(&format!(
+                "<li><a href=\"{}\">{}</a> ({})</li>",
+                escape_xml(href),
+                escape_xml(link["title"].as_str().unwrap_or(href)),
+                escape_xml(link["rel"].as_str().unwrap_or_default())
+            ));
+        }
+        html.push_str("</ul>");
+    }
+    html.push_str(&format!(
+        "<pre>{}</pre></body></html>",
+        escape_xml(&serde_json::to_string_pretty(doc).unwrap())
+    ));
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tiles::get_tile_details;
+    use std::path::PathBuf;
+
+    #[test]
+    fn get_vector_tileset() {
+        let tile_meta = get_tile_details(
+            &PathBuf::from("./tiles/world_cities.mbtiles"),
+            "world_cities",
+        )
+        .unwrap();
+        let tileset = get_tileset("world_cities", &tile_meta, "http://localhost/ogcapi");
+        assert_eq!(tileset["dataType"], "vector");
+        assert_eq!(tileset["layers"][0]["id"], "cities");
+        assert_eq!(tileset["tileMatrixSetLimits"].as_array().unwrap().len(), 7);
+        assert_eq!(
+            tileset["links"][3]["href"],
+            "http://localhost/ogcapi/collections/world_cities/tiles/WebMercatorQuad/{tileMatrix}/{tileRow}/{tileCol}"
+        );
+    }
+
+    #[test]
+    fn encode_nested_collection_id() {
+        assert_eq!(
+            encode_collection_id("openstreetmap/open-streets-dc"),
+            "openstreetmap%2Fopen-streets-dc"
+        );
+    }
+}
@@ -0,0 +1,457 @@
+//! Reader of PMTiles v3 archives as described by
+//! <https://github.com/protomaps/PMTiles/blob/main/spec/v3/spec.md>
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use serde_json::Value as JSONValue;
+use tilejson::{tilejson, Bounds, Center};
+
+use crate::errors::{Error, Result};
+use crate::s3::Location;
+use crate::tiles::{parse_vector_layers, TileMeta, TileSource};
+use crate::utils::{decompress, encode, DataFormat};
+
+const HEADER_LENGTH: usize = 127;
+/// Leaf directories can't be nested deeper than this
+const MAX_DIRECTORY_DEPTH: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Compression {
+    None,
+    Gzip,
+}
+
+impl Compression {
+    fn new(value: u8) -> Result<Compression> {
+        match value {
+            1 => Ok(Compression::None),
+            2 => Ok(Compression::Gzip),
+            3 => Err(Error::InvalidDataFormat(String::from(
+                "brotli compressed PMTiles are not supported",
+            ))),
+            4 => Err(Error::InvalidDataFormat(String::from(
+                "zstd compressed PMTiles are not supported",
+            ))),
+            _ => Err(Error::InvalidDataFormat(format!(
+                "unknown PMTiles compression {value}"
+            ))),
+        }
+    }
+
+    fn decompress(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data),
+            Compression::Gzip => decompress(data),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Header {
+    root_offset: u64,
+    root_length: u64,
+    metadata_offset: u64,
+    metadata_length: u64,
+    leaf_offset: u64,
+    data_offset: u64,
+    internal_compression: Compression,
+    tile_compression: Compression,
+    tile_type: u8,
+    min_zoom: u8,
+    max_zoom: u8,
+    bounds: [f64; 4],
+    center: (f64, f64, u8),
+}
+
+impl Header {
+    fn parse(data: &[u8]) -> Result<Header> {
+        if data.len() < HEADER_LENGTH || &data[0..7] != b"PMTiles" {
+            return Err(Error::InvalidDataFormat(String::from(
+                "not a PMTiles archive",
+            )));
+        }
+        if data[7] != 3 {
+            return Err(Error::InvalidDataFormat(format!(
+                "unsupported PMTiles version {}",
+                data[7]
+            )));
+        }
+        let u64_at = |i: usize| u64::from_le_bytes(data[i..i + 8].try_into().unwrap());
+        let coord_at = |i: usize| {
+            f64::from(i32::from_le_bytes(data[i..i + 4].try_into().unwrap())) / 10_000_000.0
+        };
+        Ok(Header {
+            root_offset: u64_at(8),
+            root_length: u64_at(16),
+            metadata_offset: u64_at(24),
+            metadata_length: u64_at(32),
+            leaf_offset: u64_at(40),
+            data_offset: u64_at(56),
+            internal_compression: Compression::new(data[97])?,
+            tile_compression: Compression::new(data[98])?,
+            tile_type: data[99],
+            min_zoom: data[100],
+            max_zoom: data[101],
+            bounds: [coord_at(102), coord_at(106), coord_at(110), coord_at(114)],
+            center: (coord_at(119), coord_at(123), data[118]),
+        })
+    }
+
+    fn tile_format(&self) -> DataFormat {
+        match self.tile_type {
+            1 => DataFormat::Pbf,
+            2 => DataFormat::Png,
+            3 => DataFormat::Jpg,
+            4 => DataFormat::Webp,
+            _ => DataFormat::Unknown,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Entry {
+    tile_id: u64,
+    offset: u64,
+    length: u64,
+    run_length: u64,
+}
+
+fn read_varint(data: &[u8], position: &mut usize) -> Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = match data.get(*position) {
+            Some(byte) => *byte,
+            None => break,
+        };
+        *position += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Error::InvalidDataFormat(String::from(
+        "truncated PMTiles directory",
+    )))
+}
+
+/// Decode an uncompressed directory into its entries, sorted by tile id
+fn parse_directory(data: &[u8]) -> Result<Vec<Entry>> {
+    let mut position = 0;
+    let count = read_varint(data, &mut position)? as usize;
+    // Every entry takes at least one byte, which bounds the allocation below
+    if count > data.len() {
+        return Err(Error::InvalidDataFormat(String::from(
+            "invalid PMTiles directory",
+        )));
+    }
+    let mut entries = Vec::with_capacity(count);
+    let mut tile_id = 0;
+    for _ in 0..count {
+        tile_id = read_varint(data, &mut position)?.saturating_add(tile_id);
+        entries.push(Entry {
+            tile_id,
+            offset: 0,
+            length: 0,
+            run_length: 0,
+        });
+    }
+    for entry in entries.iter_mut() {
+        entry.run_length = read_varint(data, &mut position)?;
+    }
+    for entry in entries.iter_mut() {
+        entry.length = read_varint(data, &mut position)?;
+    }
+    for i in 0..count {
+        let value = read_varint(data, &mut position)?;
+        entries[i].offset = match (value, i) {
+            (0, i) if i > 0 => entries[i - 1].offset.saturating_add(entries[i - 1].length),
+            _ => value.saturating_sub(1),
+        };
+    }
+    Ok(entries)
+}
+
+/// Return the position of a tile on the Hilbert curves of all zoom levels up to `z`
+pub fn zxy_to_tile_id(z: u8, x: u32, y: u32) -> u64 {
+    let mut tile_id = ((1_u64 << (2 * u32::from(z))) - 1) / 3;
+    let (mut x, mut y) = (u64::from(x), u64::from(y));
+    let mut s = (1_u64 << z) / 2;
+    while s > 0 {
+        let rx = u64::from(x & s > 0);
+        let ry = u64::from(y & s > 0);
+        tile_id += s * s * ((3 * rx) ^ ry);
+        if ry == 0 {
+            if rx == 1 {
+                // Only the bits below `s` matter from now on
+                x = s.wrapping_sub(1).wrapping_sub(x);
+                y = s.wrapping_sub(1).wrapping_sub(y);
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    tile_id
+}
+
+/// Return the entry holding `tile_id`, or the leaf directory that may hold it
+fn find_entry(entries: &[Entry], tile_id: u64) -> Option<&Entry> {
+    let index = entries.partition_point(|entry| entry.tile_id <= tile_id);
+    let entry = entries.get(index.checked_sub(1)?)?;
+    if entry.run_length == 0 || tile_id < entry.tile_id.saturating_add(entry.run_length) {
+        return Some(entry);
+    }
+    None
+}
+
+/// A local PMTiles archive, keeping its root directory and the leaf directories read so far
+/// in memory
+#[derive(Debug)]
+pub struct PMTiles {
+    location: Location,
+    header: Header,
+    root: Vec<Entry>,
+    leaves: Mutex<HashMap<u64, Arc<Vec<Entry>>>>,
+}
+
+impl PMTiles {
+    pub fn open(location: Location) -> Result<PMTiles> {
+        let header = Header::parse(&location.read(0, HEADER_LENGTH as u64)?)?;
+        let mut pmtiles = PMTiles {
+            location,
+            header,
+            root: Vec::new(),
+            leaves: Mutex::new(HashMap::new()),
+        };
+        pmtiles.root =
+            pmtiles.read_directory(pmtiles.header.root_offset, pmtiles.header.root_length)?;
+        Ok(pmtiles)
+    }
+
+    fn read(&self, offset: u64, length: u64) -> Result<Vec<u8>> {
+        self.location.read(offset, length)
+    }
+
+    fn read_directory(&self, offset: u64, length: u64) -> Result<Vec<Entry>> {
+        let data = self.read(offset, length)?;
+        parse_directory(&self.header.internal_compression.decompress(data)?)
+    }
+
+    fn get_leaf(&self, offset: u64, length: u64) -> Result<Arc<Vec<Entry>>> {
+        if let Some(leaf) = self.leaves.lock().unwrap().get(&offset) {
+            return Ok(leaf.clone());
+        }
+        let leaf =
+            Arc::new(self.read_directory(self.header.leaf_offset.saturating_add(offset), length)?);
+        self.leaves.lock().unwrap().insert(offset, leaf.clone());
+        Ok(leaf)
+    }
+
+    pub fn get_metadata(&self) -> Result<JSONValue> {
+        if self.header.metadata_length == 0 {
+            return Ok(JSONValue::Object(Default::default()));
+        }
+        let data = self.read(self.header.metadata_offset, self.header.metadata_length)?;
+        let data = self.header.internal_compression.decompress(data)?;
+        serde_json::from_slice(&data).map_err(|err| Error::InvalidDataFormat(err.to_string()))
+    }
+
+    /// Read the tile at the given XYZ position. Vector tiles are returned gzipped, as they
+    /// are stored in mbtiles, and any other tile uncompressed.
+    pub fn get_tile(&self, z: u32, x: u32, y: u32) -> Result<Vec<u8>> {
+        if z > 31 || x >= 1 << z || y >= 1 << z {
+            return Err(Error::TileNotFound);
+        }
+        let tile_id = zxy_to_tile_id(z as u8, x, y);
+        let mut leaf;
+        let mut entries = &self.root;
+        for _ in 0..MAX_DIRECTORY_DEPTH {
+            let entry = find_entry(entries, tile_id).ok_or(Error::TileNotFound)?;
+            if entry.run_length > 0 {
+                let data = self.read(
+                    self.header.data_offset.saturating_add(entry.offset),
+                    entry.length,
+                )?;
+                return match (self.header.tile_format(), self.header.tile_compression) {
+                    (DataFormat::Pbf, Compression::Gzip) => Ok(data),
+                    (DataFormat::Pbf, Compression::None) => Ok(encode(&data)),
+                    (_, compression) => compression.decompress(data),
+                };
+            }
+            leaf = self.get_leaf(entry.offset, entry.length)?;
+            entries = &leaf;
+        }
+        Err(Error::InvalidDataFormat(format!(
+            "{}: too many nested directories",
+            self.location
+        )))
+    }
+}
+
+/// Open a local PMTiles archive and build its TileJSON from the header and metadata
+pub fn get_tile_details(path: &Path, tile_name: &str) -> Result<TileMeta> {
+    get_location_details(Location::File(path.to_path_buf()), tile_name)
+}
+
+/// Open a PMTiles archive, local or remote, and build its TileJSON from the header and
+/// metadata
+pub fn get_location_details(location: Location, tile_name: &str) -> Result<TileMeta> {
+    let path = PathBuf::from(location.to_string());
+    let pmtiles = PMTiles::open(location)?;
+    let header = &pmtiles.header;
+    let tile_format = match header.tile_format() {
+        DataFormat::Unknown => return Err(Error::UnknownTileFormat(tile_name.to_string())),
+        tile_format => tile_format,
+    };
+
+    let mut tilejson = tilejson! {
+        tilejson: "3.0.0".to_string(),
+        tiles: vec!["".to_string()],
+    };
+    tilejson.minzoom = Some(header.min_zoom);
+    tilejson.maxzoom = Some(header.max_zoom);
+    let [left, bottom, right, top] = header.bounds;
+    tilejson.bounds = Some(Bounds::new(left, bottom, right, top));
+    let (longitude, latitude, zoom) = header.center;
+    tilejson.center = Some(Center::new(longitude, latitude, zoom));
+
+    let mut layer_type = None;
+    let mut metadata = match pmtiles.get_metadata()? {
+        JSONValue::Object(metadata) => metadata,
+        _ => Default::default(),
+    };
+    for key in [
+        "name",
+        "version",
+        "description",
+        "attribution",
+        "legend",
+        "template",
+        "type",
+    ] {
+        let value = match metadata.remove(key) {
+            Some(JSONValue::String(value)) => value,
+            Some(value) => value.to_string(),
+            None => continue,
+        };
+        match key {
+            "name" => tilejson.name = Some(value),
+            "version" => tilejson.version = Some(value),
+            "description" => tilejson.description = Some(value),
+            "attribution" => tilejson.attribution = Some(value),
+            "legend" => tilejson.legend = Some(value),
+            "template" => tilejson.template = Some(value),
+            _ => layer_type = Some(value),
+        }
+    }
+    if let Some(layers) = metadata.remove("vector_layers") {
+        tilejson.vector_layers = Some(parse_vector_layers(tile_name, layers));
+    }
+    if let Some(tilestats) = metadata.remove("tilestats") {
+        tilejson.other.insert("tilestats".to_string(), tilestats);
+    }
+
+    Ok(TileMeta {
+        source: TileSource::PMTiles(Arc::new(pmtiles)),
+        path,
+        tilejson,
+        id: tile_name.to_string(),
+        tile_format,
+        grid_format: None,
+        layer_type,
+        json: Some(JSONValue::Object(metadata)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_varint(data: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            data.push((value as u8 & 0x7f) | 0x80);
+            value >>= 7;
+        }
+        data.push(value as u8);
+    }
+
+    #[test]
+    fn tile_ids() {
+        assert_eq!(zxy_to_tile_id(0, 0, 0), 0);
+        assert_eq!(zxy_to_tile_id(1, 0, 0), 1);
+        assert_eq!(zxy_to_tile_id(1, 0, 1), 2);
+        assert_eq!(zxy_to_tile_id(1, 1, 1), 3);
+        assert_eq!(zxy_to_tile_id(1, 1, 0), 4);
+        assert_eq!(zxy_to_tile_id(2, 0, 0), 5);
+        assert_eq!(zxy_to_tile_id(20, 0, 0), 366503875925);
+    }
+
+    #[test]
+    fn parse_directory_entries() {
+        let mut data = Vec::new();
+        for value in [3, 1, 2, 4, 1, 0, 2, 10, 20, 10, 1, 0, 101] {
+            write_varint(&mut data, value);
+        }
+        let entries = parse_directory(&data).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                Entry {
+                    tile_id: 1,
+                    offset: 0,
+                    length: 10,
+                    run_length: 1,
+                },
+                Entry {
+                    tile_id: 3,
+                    offset: 10,
+                    length: 20,
+                    run_length: 0,
+                },
+                Entry {
+                    tile_id: 7,
+                    offset: 100,
+                    length: 10,
+                    run_length: 2,
+                },
+            ]
+        );
+        assert_eq!(find_entry(&entries, 0), None);
+        assert_eq!(find_entry(&entries, 2), None);
+        assert_eq!(find_entry(&entries, 5).unwrap().tile_id, 3);
+        assert_eq!(find_entry(&entries, 8).unwrap().tile_id, 7);
+        assert_eq!(find_entry(&entries, 9), None);
+    }
+
+    #[test]
+    fn get_pmtiles_details() {
+        let tile_meta = get_tile_details(
+            &PathBuf::from("./tiles/pmtiles/world_cities.pmtiles"),
+            "pmtiles/world_cities",
+        )
+        .unwrap();
+        assert_eq!(tile_meta.tile_format, DataFormat::Pbf);
+        assert_eq!(
+            tile_meta.tilejson.name.as_deref(),
+            Some("Major cities from Natural Earth data")
+        );
+        assert_eq!(tile_meta.tilejson.maxzoom, Some(6));
+        assert_eq!(tile_meta.tilejson.vector_layers.unwrap()[0].id, "cities");
+        assert_eq!(tile_meta.layer_type.as_deref(), Some("overlay"));
+    }
+
+    #[test]
+    fn get_pmtiles_tile() {
+        let pmtiles = PMTiles::open(Location::File(PathBuf::from(
+            "./tiles/pmtiles/world_cities.pmtiles",
+        )))
+        .unwrap();
+        let tile = pmtiles.get_tile(0, 0, 0).unwrap();
+        assert_eq!(crate::utils::get_data_format(&tile), DataFormat::Gzip);
+        assert!(matches!(
+            pmtiles.get_tile(6, 0, 0),
+            Err(Error::TileNotFound)
+        ));
+    }
+}
@@ -0,0 +1,246 @@
+//! Connection pools of mbtiles files. A pool is only opened when its tileset is first read
+//! from, and closed again once it has not been used for a while, while the number of files
+//! open at once is capped so that the connections of all pools stay within a limit.
+
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+use log::debug;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::OpenFlags;
+use scheduled_thread_pool::ScheduledThreadPool;
+
+use crate::errors::{Error, Result};
+
+type Pool = r2d2::Pool<SqliteConnectionManager>;
+pub type Connection = r2d2::PooledConnection<SqliteConnectionManager>;
+
+/// Connections a single file can have open at once
+const CONNECTIONS_PER_FILE: u32 = 4;
+/// Interval pools are checked for idleness at
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+/// Time a request waits for a connection of a busy pool
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub const DEFAULT_IDLE_TIMEOUT: u64 = 60;
+pub const DEFAULT_MAX_CONNECTIONS: u32 = 256;
+
+#[derive(Debug)]
+struct Settings {
+    idle_timeout: Duration,
+    max_connections: u32,
+}
+
+/// The pools of a set of files sharing a connection limit
+#[derive(Debug)]
+pub struct Pools {
+    settings: RwLock<Settings>,
+    open: Mutex<Vec<Weak<LazyPool>>>,
+}
+
+lazy_static! {
+    static ref POOLS: Arc<Pools> = Arc::new(Pools::new(
+        Duration::from_secs(DEFAULT_IDLE_TIMEOUT),
+        DEFAULT_MAX_CONNECTIONS
+    ));
+    /// Thread shared by all pools, instead of the threads r2d2 starts for each of them
+    static ref THREAD_POOL: Arc<ScheduledThreadPool> = {
+        let thread_pool = Arc::new(ScheduledThreadPool::with_name("mbtiles-pool-{}", 1));
+        thread_pool.execute_at_fixed_rate(SWEEP_INTERVAL, SWEEP_INTERVAL, || {
+            POOLS.close_idle(Instant::now())
+        });
+        thread_pool
+    };
+}
+
+/// Set how long unused pools stay open and how many connections can be open at once
+pub fn configure(idle_timeout: Duration, max_connections: u32) {
+    let mut settings = POOLS.settings.write().unwrap();
+    settings.idle_timeout = idle_timeout;
+    settings.max_connections = max_connections;
+}
+
+impl Pools {
+    fn new(idle_timeout: Duration, max_connections: u32) -> Self {
+        Pools {
+            settings: RwLock::new(Settings {
+                idle_timeout,
+                max_connections,
+            }),
+            open: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Connections of each pool and number of pools open at once within the connection limit
+    fn limits(&self) -> (u32, usize) {
+        let max_connections = self.settings.read().unwrap().max_connections.max(1);
+        let per_file = CONNECTIONS_PER_FILE.min(max_connections);
+        (per_file, (max_connections / per_file) as usize)
+    }
+
+    /// Close the pools unused for longer than the idle timeout
+    fn close_idle(&self, now: Instant) {
+        let idle_timeout = self.settings.read().unwrap().idle_timeout;
+        let mut open = self.open.lock().unwrap();
+        open.retain(|pool| match pool.upgrade() {
+            Some(pool) => {
+                let mut state = pool.state.lock().unwrap();
+                if now.saturating_duration_since(state.last_used) >= idle_timeout {
+                    debug!("Closing idle pool of {}", pool.path.display());
+                    state.pool = None;
+                }
+                state.pool.is_some()
+            }
+            None => false,
+        });
+    }
+}
+
+/// Makes the file of a pool available before the pool is opened, e.g. by downloading it
+pub trait Fetch: Debug + Send + Sync {
+    fn fetch(&self, path: &Path) -> Result<()>;
+}
+
+#[derive(Debug)]
+struct PoolState {
+    pool: Option<Pool>,
+    last_used: Instant,
+}
+
+/// The connection pool of an mbtiles file, opened on demand
+#[derive(Debug)]
+pub struct LazyPool {
+    path: PathBuf,
+    pools: Arc<Pools>,
+    fetch: Option<Arc<dyn Fetch>>,
+    state: Mutex<PoolState>,
+}
+
+impl LazyPool {
+    pub fn new(path: &Path) -> Arc<Self> {
+        Self::with_pools(path, POOLS.clone(), None)
+    }
+
+    /// A pool whose file is fetched each time before the pool is opened
+    pub fn with_fetch(path: &Path, fetch: Arc<dyn Fetch>) -> Arc<Self> {
+        Self::with_pools(path, POOLS.clone(), Some(fetch))
+    }
+
+    fn with_pools(path: &Path, pools: Arc<Pools>, fetch: Option<Arc<dyn Fetch>>) -> Arc<Self> {
+        Arc::new(LazyPool {
+            path: path.to_path_buf(),
+            pools,
+            fetch,
+            state: Mutex::new(PoolState {
+                pool: None,
+                last_used: Instant::now(),
+            }),
+        })
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.state.lock().unwrap().pool.is_some()
+    }
+
+    /// Get a connection, opening the pool first when it is closed
+    pub fn get(self: &Arc<Self>) -> Result<Connection> {
+        let pool = {
+            let mut state = self.state.lock().unwrap();
+            state.last_used = Instant::now();
+            state.pool.clone()
+        };
+        let pool = match pool {
+            Some(pool) => pool,
+            None => {
+                // Outside of the lock of the open pools, as fetching can take a while
+                if let Some(fetch) = &self.fetch {
+                    fetch.fetch(&self.path)?;
+                }
+                self.open()?
+            }
+        };
+        pool.get().map_err(Error::Pool)
+    }
+
+    /// Open the pool, closing the least recently used ones when too many are open
+    fn open(self: &Arc<Self>) -> Result<Pool> {
+        let (per_file, max_open) = self.pools.limits();
+        let mut open = self.pools.open.lock().unwrap();
+        open.retain(|pool| pool.upgrade().is_some_and(|pool| pool.is_open()));
+        if let Some(pool) = self.state.lock().unwrap().pool.clone() {
+            // Opened by another request in the meantime
+            return Ok(pool);
+        }
+
+        while open.len() >= max_open {
+            let least_recently_used = open
+                .iter()
+                .enumerate()
+                .filter_map(|(i, pool)| Some((i, pool.upgrade()?)))
+                .min_by_key(|(_, pool)| pool.state.lock().unwrap().last_used);
+            match least_recently_used {
+                Some((i, pool)) => {
+                    debug!("Closing pool of {} to open another", pool.path.display());
+                    pool.state.lock().unwrap().pool = None;
+                    open.remove(i);
+                }
+                None => break,
+            }
+        }
+
+        let manager =
+            SqliteConnectionManager::file(&self.path).with_flags(OpenFlags::SQLITE_OPEN_READ_ONLY);
+        let pool = r2d2::Pool::builder()
+            .max_size(per_file)
+            .min_idle(Some(0))
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connection_timeout(CONNECTION_TIMEOUT)
+            .thread_pool(THREAD_POOL.clone())
+            .build(manager)
+            .map_err(Error::Pool)?;
+        debug!("Opened pool of {}", self.path.display());
+        let mut state = self.state.lock().unwrap();
+        state.pool = Some(pool.clone());
+        state.last_used = Instant::now();
+        open.push(Arc::downgrade(self));
+        Ok(pool)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn open_pools_on_demand() {
+        let pools = Arc::new(Pools::new(Duration::from_secs(60), 4));
+        let world = LazyPool::with_pools(
+            Path::new("./tiles/world_cities.mbtiles"),
+            pools.clone(),
+            None,
+        );
+        let geography = LazyPool::with_pools(
+            Path::new("./tiles/geography-class-png.mbtiles"),
+            pools.clone(),
+            None,
+        );
+        assert!(!world.is_open());
+
+        world.get().unwrap();
+        assert!(world.is_open());
+        // A single file can be open within the limit of 4 connections
+        geography.get().unwrap();
+        assert!(geography.is_open());
+        assert!(!world.is_open());
+
+        pools.close_idle(Instant::now());
+        assert!(geography.is_open());
+        pools.close_idle(Instant::now() + Duration::from_secs(60));
+        assert!(!geography.is_open());
+        assert!(pools.open.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,563 @@
+use std::io::Cursor;
+
+use image::imageops::{overlay, resize, FilterType};
+use image::{DynamicImage, ImageFormat, Rgba, RgbaImage};
+use serde_json::Value as JSONValue;
+
+use crate::errors::{Error, Result};
+use crate::utils::{get_blank_image, lonlat_to_pixel, DataFormat};
+
+fn image_format(data_format: DataFormat) -> Option<ImageFormat> {
+    match data_format {
+        DataFormat::Png => Some(ImageFormat::Png),
+        DataFormat::Jpg => Some(ImageFormat::Jpeg),
+        DataFormat::Webp => Some(ImageFormat::WebP),
+        _ => None,
+    }
+}
+
+/// Decode a raster tile stored in the given format
+pub fn decode_image(data: &[u8], data_format: DataFormat) -> Result<RgbaImage> {
+    let format = match image_format(data_format) {
+        Some(format) => format,
+        None => return Err(Error::InvalidDataFormat(data_format.format().to_string())),
+    };
+    match image::load_from_memory_with_format(data, format) {
+        Ok(image) => Ok(image.to_rgba8()),
+        Err(err) => Err(Error::Image(err)),
+    }
+}
+
+/// Encode an image to PNG, JPEG or WebP. JPEG output drops the alpha channel.
+pub fn encode_image(image: RgbaImage, data_format: DataFormat) -> Result<Vec<u8>> {
+    let format = match image_format(data_format) {
+        Some(format) => format,
+        None => return Err(Error::InvalidDataFormat(data_format.format().to_string())),
+    };
+    let image = match format {
+        ImageFormat::Jpeg => DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(image).to_rgb8()),
+        _ => DynamicImage::ImageRgba8(image),
+    };
+    let mut data = Cursor::new(Vec::new());
+    match image.write_to(&mut data, format) {
+        Ok(_) => Ok(data.into_inner()),
+        Err(err) => Err(Error::Image(err)),
+    }
+}
+
+/// Return an empty 256 x 256 tile in the given image format, transparent except in JPEG
+pub fn blank_image(data_format: DataFormat) -> Result<Vec<u8>> {
+    match data_format {
+        DataFormat::Png => Ok(get_blank_image()),
+        _ => encode_image(RgbaImage::new(256, 256), data_format),
+    }
+}
+
+/// Scale the alpha channel of an image by the given opacity in `[0, 1]`
+pub fn apply_opacity(image: &mut RgbaImage, opacity: f32) {
+    if opacity >= 1.0 {
+        return;
+    }
+    let opacity = opacity.max(0.0);
+    for pixel in image.pixels_mut() {
+        pixel[3] = (f32::from(pixel[3]) * opacity).round() as u8;
+    }
+}
+
+/// Draw raster tiles on top of each other, from the first to the last, and encode the result.
+///
+/// Each layer is given as its tile data, tile format and opacity. Layers whose size differs
+/// from the largest layer are resized to match it.
+pub fn composite_images(
+    layers: &[(Vec<u8>, DataFormat, f32)],
+    output: DataFormat,
+) -> Result<Vec<u8>> {
+    let mut images = Vec::new();
+    for (data, data_format, opacity) in layers {
+        let mut image = decode_image(data, *data_format)?;
+        apply_opacity(&mut image, *opacity);
+        images.push(image);
+    }
+    let size = images
+        .iter()
+        .map(|i| i.width().max(i.height()))
+        .max()
+        .unwrap_or(256);
+
+    let mut canvas = RgbaImage::new(size, size);
+    for image in images {
+        let image = if image.width() != size || image.height() != size {
+            resize(&image, size, size, FilterType::Triangle)
+        } else {
+            image
+        };
+        overlay(&mut canvas, &image, 0, 0);
+    }
+    encode_image(canvas, output)
+}
+
+/// Viewport of a static map: its size and the world pixel coordinates of its top-left corner
+/// at zoom `z`, as given by [`lonlat_to_pixel`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    pub z: u32,
+    pub left: f64,
+    pub top: f64,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Viewport {
+    /// Viewport of the given size centered on a longitude/latitude
+    pub fn from_center(lon: f64, lat: f64, z: u32, width: u32, height: u32) -> Self {
+        let (x, y) = lonlat_to_pixel(lon, lat, f64::from(z));
+        Viewport {
+            z,
+            left: x - f64::from(width) / 2.0,
+            top: y - f64::from(height) / 2.0,
+            width,
+            height,
+        }
+    }
+
+    /// Viewport of the given size centered on a bounding box, at the highest zoom level up to
+    /// `max_zoom`, and at most 30, at which the whole bounding box fits
+    pub fn from_bounds(bounds: [f64; 4], max_zoom: u32, width: u32, height: u32) -> Self {
+        let [min_lon, min_lat, max_lon, max_lat] = bounds;
+        let (left, bottom) = lonlat_to_pixel(min_lon, min_lat, 0.0);
+        let (right, top) = lonlat_to_pixel(max_lon, max_lat, 0.0);
+        let mut z = 0;
+        while z < max_zoom.min(30) {
+            let scale = f64::from(z + 1).exp2();
+            if (right - left) * scale > f64::from(width)
+                || (bottom - top) * scale > f64::from(height)
+            {
+                break;
+            }
+            z += 1;
+        }
+        let scale = f64::from(z).exp2();
+        Viewport {
+            z,
+            left: (left + right) / 2.0 * scale - f64::from(width) / 2.0,
+            top: (top + bottom) / 2.0 * scale - f64::from(height) / 2.0,
+            width,
+            height,
+        }
+    }
+
+    /// XYZ tiles covered by the viewport along with the position at which each is drawn.
+    /// Tiles are wrapped around the antimeridian and skipped beyond the poles.
+    pub fn tiles(&self) -> Vec<(u32, u32, i64, i64)> {
+        let n = 1i64 << self.z;
+        let first_x = (self.left / 256.0).floor() as i64;
+        let first_y = (self.top / 256.0).floor() as i64;
+        let last_x = ((self.left + f64::from(self.width)) / 256.0).ceil() as i64;
+        let last_y = ((self.top + f64::from(self.height)) / 256.0).ceil() as i64;
+        let mut tiles = Vec::new();
+        for ty in first_y.max(0)..last_y.min(n) {
+            for tx in first_x..last_x {
+                tiles.push((
+                    tx.rem_euclid(n) as u32,
+                    ty as u32,
+                    tx * 256 - self.left.round() as i64,
+                    ty * 256 - self.top.round() as i64,
+                ));
+            }
+        }
+        tiles
+    }
+
+    /// Position of a longitude/latitude in the viewport
+    pub fn project(&self, lon: f64, lat: f64) -> (f64, f64) {
+        let (x, y) = lonlat_to_pixel(lon, lat, f64::from(self.z));
+        (x - self.left, y - self.top)
+    }
+}
+
+/// Stitch the XYZ tiles covering a viewport into a single image, using `get_tile` to read
+/// each tile. Missing tiles are left transparent.
+pub fn render_static_map<F>(viewport: &Viewport, mut get_tile: F) -> RgbaImage
+where
+    F: FnMut(u32, u32, u32) -> Option<RgbaImage>,
+{
+    let mut canvas = RgbaImage::new(viewport.width, viewport.height);
+    for (x, y, left, top) in viewport.tiles() {
+        if let Some(tile) = get_tile(viewport.z, x, y) {
+            let tile = if tile.width() != 256 || tile.height() != 256 {
+                resize(&tile, 256, 256, FilterType::Triangle)
+            } else {
+                tile
+            };
+            overlay(&mut canvas, &tile, left, top);
+        }
+    }
+    canvas
+}
+
+fn parse_color(value: Option<&JSONValue>, default: Rgba<u8>) -> Rgba<u8> {
+    let hex = match value.and_then(JSONValue::as_str) {
+        Some(hex) => hex.trim_start_matches('#'),
+        None => return default,
+    };
+    let hex = match hex.len() {
+        3 => hex.chars().flat_map(|c| [c, c]).collect(),
+        6 => hex.to_string(),
+        _ => return default,
+    };
+    match u32::from_str_radix(&hex, 16) {
+        Ok(rgb) => Rgba([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8, default[3]]),
+        Err(_) => default,
+    }
+}
+
+fn blend(image: &mut RgbaImage, x: i64, y: i64, color: Rgba<u8>) {
+    if x < 0 || y < 0 || x >= i64::from(image.width()) || y >= i64::from(image.height()) {
+        return;
+    }
+    let pixel = image.get_pixel_mut(x as u32, y as u32);
+    let alpha = f32::from(color[3]) / 255.0;
+    for c in 0..3 {
+        pixel[c] =
+            (f32::from(color[c]) * alpha + f32::from(pixel[c]) * (1.0 - alpha)).round() as u8;
+    }
+    pixel[3] = pixel[3].max(color[3]);
+}
+
+fn fill_circle(image: &mut RgbaImage, (cx, cy): (f64, f64), radius: f64, color: Rgba<u8>) {
+    let r = radius.ceil() as i64;
+    let (x0, y0) = (cx.round() as i64, cy.round() as i64);
+    for dy in -r..=r {
+        for dx in -r..=r {
+            if ((dx * dx + dy * dy) as f64) <= radius * radius {
+                blend(image, x0 + dx, y0 + dy, color);
+            }
+        }
+    }
+}
+
+/// Clip a segment to a rectangle, with the Liang-Barsky algorithm
+fn clip_segment(
+    (x0, y0): (f64, f64),
+    (x1, y1): (f64, f64),
+    (min_x, min_y, max_x, max_y): (f64, f64, f64, f64),
+) -> Option<((f64, f64), (f64, f64))> {
+    let (dx, dy) = (x1 - x0, y1 - y0);
+    let (mut t0, mut t1) = (0.0_f64, 1.0_f64);
+    for (p, q) in [
+        (-dx, x0 - min_x),
+        (dx, max_x - x0),
+        (-dy, y0 - min_y),
+        (dy, max_y - y0),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else if p < 0.0 {
+            t0 = t0.max(q / p);
+        } else {
+            t1 = t1.min(q / p);
+        }
+    }
+    (t0 <= t1).then_some(((x0 + t0 * dx, y0 + t0 * dy), (x0 + t1 * dx, y0 + t1 * dy)))
+}
+
+/// Widest line drawn on a static map, in pixels
+const MAX_STROKE_WIDTH: f64 = 50.0;
+
+fn draw_line(image: &mut RgbaImage, points: &[(f64, f64)], width: f64, color: Rgba<u8>) {
+    let r = (width / 2.0).max(0.5);
+    let ri = r.ceil() as i64;
+    let (image_width, image_height) = (i64::from(image.width()), i64::from(image.height()));
+    // Pixels covered by the line, blended once even where the segments overlap
+    let mut covered = vec![false; (image_width * image_height) as usize];
+    let margin = r + 1.0;
+    let visible = (
+        -margin,
+        -margin,
+        image_width as f64 + margin,
+        image_height as f64 + margin,
+    );
+    for segment in points.windows(2) {
+        let ((x0, y0), (x1, y1)) = match clip_segment(segment[0], segment[1], visible) {
+            Some(clipped) => clipped,
+            None => continue,
+        };
+        let steps = (x1 - x0).abs().max((y1 - y0).abs()).ceil().max(1.0) as i64;
+        for step in 0..=steps {
+            let t = step as f64 / steps as f64;
+            let (x, y) = (x0 + (x1 - x0) * t, y0 + (y1 - y0) * t);
+            for dy in -ri..=ri {
+                for dx in -ri..=ri {
+                    let (px, py) = (x.round() as i64 + dx, y.round() as i64 + dy);
+                    if ((dx * dx + dy * dy) as f64) <= r * r
+                        && (0..image_width).contains(&px)
+                        && (0..image_height).contains(&py)
+                    {
+                        covered[(py * image_width + px) as usize] = true;
+                    }
+                }
+            }
+        }
+    }
+    for (i, _) in covered.iter().enumerate().filter(|(_, covered)| **covered) {
+        let i = i as i64;
+        blend(image, i % image_width, i / image_width, color);
+    }
+}
+
+fn fill_polygon(image: &mut RgbaImage, rings: &[Vec<(f64, f64)>], color: Rgba<u8>) {
+    let width = i64::from(image.width());
+    for y in 0..image.height() {
+        let scan = f64::from(y) + 0.5;
+        let mut crossings = Vec::new();
+        for ring in rings {
+            for edge in ring.windows(2) {
+                let ((x0, y0), (x1, y1)) = (edge[0], edge[1]);
+                if (y0 <= scan) != (y1 <= scan) {
+                    crossings.push(x0 + (scan - y0) / (y1 - y0) * (x1 - x0));
+                }
+            }
+        }
+        crossings.sort_by(f64::total_cmp);
+        for pair in crossings.chunks(2) {
+            if let [start, end] = pair {
+                // Only the part of the span within the image is drawn
+                let start = (start.round() as i64).clamp(0, width);
+                let end = (end.round() as i64).clamp(0, width);
+                for x in start..end {
+                    blend(image, x, i64::from(y), color);
+                }
+            }
+        }
+    }
+}
+
+fn draw_geometry(
+    image: &mut RgbaImage,
+    viewport: &Viewport,
+    geometry: &JSONValue,
+    properties: &JSONValue,
+) {
+    let project_line = |line: &JSONValue| -> Vec<(f64, f64)> {
+        line.as_array()
+            .map(|coords| {
+                coords
+                    .iter()
+                    .filter_map(|c| {
+                        let (lon, lat) = (c.get(0)?.as_f64()?, c.get(1)?.as_f64()?);
+                        let (x, y) = viewport.project(lon, lat);
+                        // Extreme coordinates project to infinite positions
+                        (x.is_finite() && y.is_finite()).then_some((x, y))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let coordinates = &geometry["coordinates"];
+    let marker = parse_color(
+        properties.get("marker-color"),
+        Rgba([0xe5, 0x39, 0x35, 255]),
+    );
+    let stroke = parse_color(properties.get("stroke"), Rgba([0x1e, 0x88, 0xe5, 255]));
+    let stroke_width = properties
+        .get("stroke-width")
+        .and_then(JSONValue::as_f64)
+        .unwrap_or(3.0)
+        .clamp(0.0, MAX_STROKE_WIDTH);
+    let mut fill = parse_color(properties.get("fill"), stroke);
+    fill[3] = (properties
+        .get("fill-opacity")
+        .and_then(JSONValue::as_f64)
+        .unwrap_or(0.3)
+        * 255.0)
+        .clamp(0.0, 255.0) as u8;
+
+    let draw_point = |image: &mut RgbaImage, point: &JSONValue| {
+        if let Some(&position) = project_line(&JSONValue::Array(vec![point.clone()])).first() {
+            fill_circle(image, position, 7.0, Rgba([255, 255, 255, 255]));
+            fill_circle(image, position, 5.0, marker);
+        }
+    };
+    let draw_polygon = |image: &mut RgbaImage, polygon: &JSONValue| {
+        let rings: Vec<Vec<(f64, f64)>> = polygon
+            .as_array()
+            .map(|rings| rings.iter().map(project_line).collect())
+            .unwrap_or_default();
+        fill_polygon(image, &rings, fill);
+        for ring in &rings {
+            draw_line(image, ring, stroke_width, stroke);
+        }
+    };
+    let parts = || coordinates.as_array().into_iter().flatten();
+
+    match geometry["type"].as_str() {
+        Some("Point") => draw_point(image, coordinates),
+        Some("MultiPoint") => parts().for_each(|point| draw_point(image, point)),
+        Some("LineString") => draw_line(image, &project_line(coordinates), stroke_width, stroke),
+        Some("MultiLineString") => {
+            parts().for_each(|line| draw_line(image, &project_line(line), stroke_width, stroke))
+        }
+        Some("Polygon") => draw_polygon(image, coordinates),
+        Some("MultiPolygon") => parts().for_each(|polygon| draw_polygon(image, polygon)),
+        Some("GeometryCollection") => {
+            for geometry in geometry["geometries"].as_array().into_iter().flatten() {
+                draw_geometry(image, viewport, geometry, properties);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Draw GeoJSON points as markers and lines and polygons as paths on a static map.
+/// Colors and widths follow the `marker-color`, `stroke`, `stroke-width`, `fill` and
+/// `fill-opacity` feature properties of the simplestyle spec.
+pub fn draw_geojson(image: &mut RgbaImage, viewport: &Viewport, geojson: &JSONValue) {
+    match geojson["type"].as_str() {
+        Some("FeatureCollection") => {
+            for feature in geojson["features"].as_array().into_iter().flatten() {
+                draw_geojson(image, viewport, feature);
+            }
+        }
+        Some("Feature") => draw_geometry(
+            image,
+            viewport,
+            &geojson["geometry"],
+            &geojson["properties"],
+        ),
+        Some(_) => draw_geometry(image, viewport, geojson, &JSONValue::Null),
+        None => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn solid_png(color: Rgba<u8>, size: u32) -> Vec<u8> {
+        encode_image(RgbaImage::from_pixel(size, size, color), DataFormat::Png).unwrap()
+    }
+
+    #[test]
+    fn composite_images_with_opacity() {
+        let data = composite_images(
+            &[
+                (solid_png(Rgba([0, 0, 255, 255]), 256), DataFormat::Png, 1.0),
+                (solid_png(Rgba([255, 0, 0, 255]), 512), DataFormat::Png, 0.5),
+            ],
+            DataFormat::Png,
+        )
+        .unwrap();
+        let image = decode_image(&data, DataFormat::Png).unwrap();
+        assert_eq!(image.dimensions(), (512, 512));
+        let pixel = image.get_pixel(10, 10);
+        assert!(pixel[3] >= 254);
+        assert!(pixel[0] > 120 && pixel[0] < 135);
+        assert!(pixel[2] > 120 && pixel[2] < 135);
+    }
+
+    #[test]
+    fn viewport_tiles() {
+        let viewport = Viewport::from_center(0.0, 0.0, 1, 300, 200);
+        assert_eq!(viewport.left, 106.0);
+        assert_eq!(viewport.top, 156.0);
+        assert_eq!(
+            viewport.tiles(),
+            vec![
+                (0, 0, -106, -156),
+                (1, 0, 150, -156),
+                (0, 1, -106, 100),
+                (1, 1, 150, 100)
+            ]
+        );
+
+        let viewport = Viewport::from_bounds([-10.0, -10.0, 10.0, 10.0], 18, 512, 512);
+        assert_eq!(viewport.z, 5);
+        assert_eq!(viewport.project(0.0, 0.0), (256.0, 256.0));
+
+        let viewport = Viewport::from_bounds([-1e-9, -1e-9, 1e-9, 1e-9], 255, 256, 256);
+        assert_eq!(viewport.z, 30);
+    }
+
+    #[test]
+    fn draw_geojson_beyond_viewport() {
+        let viewport = Viewport::from_center(0.0, 0.0, 30, 100, 100);
+        let mut image = RgbaImage::new(100, 100);
+        let geojson = serde_json::json!({
+            "type": "GeometryCollection",
+            "geometries": [
+                {"type": "LineString", "coordinates": [[-170.0, 0.0], [170.0, 0.0]]},
+                {"type": "Polygon", "coordinates": [[[-170.0, -80.0], [170.0, -80.0], [170.0, 80.0], [-170.0, -80.0]]]},
+                {"type": "LineString", "coordinates": [[1e308, 0.0], [-1e308, 1e308], [0.0, 0.0]]},
+                {"type": "Polygon", "coordinates": [[[1e308, 0.0], [-1e308, 1e308], [0.0, -1e308], [1e308, 0.0]]]}
+            ]
+        });
+        draw_geojson(&mut image, &viewport, &geojson);
+        assert_eq!(image.get_pixel(10, 50)[3], 255);
+        assert_ne!(image.get_pixel(90, 90)[3], 0);
+        assert_eq!(image.get_pixel(10, 10)[3], 0);
+    }
+
+    #[test]
+    fn draw_geojson_with_oversized_stroke() {
+        let viewport = Viewport::from_center(0.0, 0.0, 2, 200, 200);
+        let mut image = RgbaImage::new(200, 200);
+        let geojson = serde_json::json!({
+            "type": "Feature",
+            "properties": {"stroke-width": 1e308},
+            "geometry": {"type": "LineString", "coordinates": [[-170.0, 0.0], [170.0, 0.0]]}
+        });
+        let start = std::time::Instant::now();
+        draw_geojson(&mut image, &viewport, &geojson);
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        // Drawn 50px wide
+        let (_, y) = viewport.project(0.0, 0.0);
+        assert_ne!(image.get_pixel(100, y.round() as u32 + 24)[3], 0);
+        assert_eq!(image.get_pixel(100, y.round() as u32 + 27)[3], 0);
+    }
+
+    #[test]
+    fn render_static_map_with_geojson() {
+        let viewport = Viewport::from_center(0.0, 0.0, 0, 100, 100);
+        let mut image = render_static_map(&viewport, |_, _, _| {
+            Some(RgbaImage::from_pixel(256, 256, Rgba([0, 0, 0, 255])))
+        });
+        assert_eq!(image.get_pixel(0, 0), &Rgba([0, 0, 0, 255]));
+
+        let geojson = serde_json::json!({
+            "type": "FeatureCollection",
+            "features": [{
+                "type": "Feature",
+                "properties": {"marker-color": "#0f0"},
+                "geometry": {"type": "Point", "coordinates": [0.0, 0.0]}
+            }, {
+                "type": "Feature",
+                "properties": {"stroke": "#ff0000", "stroke-width": 1},
+                "geometry": {"type": "LineString", "coordinates": [[-30.0, 30.0], [30.0, 30.0]]}
+            }]
+        });
+        draw_geojson(&mut image, &viewport, &geojson);
+        assert_eq!(image.get_pixel(50, 50), &Rgba([0, 255, 0, 255]));
+        let (_, y) = viewport.project(0.0, 30.0);
+        assert_eq!(
+            image.get_pixel(60, y.round() as u32),
+            &Rgba([255, 0, 0, 255])
+        );
+    }
+
+    #[test]
+    fn composite_images_to_webp() {
+        let data = composite_images(
+            &[(
+                std::fs::read("./tiles/world.jpg").unwrap(),
+                DataFormat::Jpg,
+                1.0,
+            )],
+            DataFormat::Webp,
+        )
+        .unwrap();
+        assert_eq!(crate::utils::get_data_format(&data), DataFormat::Webp);
+    }
+}
@@ -1,15 +1,25 @@
+use std::sync::Arc;
+
 use hyper::service::{make_service_fn, service_fn};
 use hyper::Server;
 
 use crate::config::Args;
-use crate::service;
+use crate::service::{self, Assets};
 
 #[tokio::main]
 pub async fn run(args: Args) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let addr = ([0, 0, 0, 0], args.port).into();
     let server = Server::try_bind(&addr)?;
 
+    let assets = Arc::new(Assets {
+        fonts: args.fonts.clone(),
+        sprites: args.sprite_files.clone(),
+        styles: args.style_files.clone(),
+        skipped: args.enable_admin.then(|| args.skipped.clone()),
+    });
+
     let service = make_service_fn(move |_conn| {
+        let assets = assets.clone();
         let tilesets = args.tilesets.clone();
         let allowed_hosts = args.allowed_hosts.clone();
         let headers = args.headers.clone();
@@ -21,6 +31,8 @@ pub async fn run(args: Args) -> Result<(), Box<dyn std::error::Error + Send + Sy
                     allowed_hosts.clone(),
                     headers.clone(),
                     args.disable_preview,
+                    args.enable_tms,
+                    assets.clone(),
                 )
             }))
         }
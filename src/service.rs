@@ -1,21 +1,52 @@
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use hyper::header::{CONTENT_ENCODING, CONTENT_TYPE, HOST};
+use hyper::header::{ACCEPT, CONTENT_ENCODING, CONTENT_TYPE, HOST};
 use hyper::{Body, Request, Response, StatusCode};
 use lazy_static::lazy_static;
+use log::warn;
 use regex::Regex;
-use serde_json::json;
+use serde_json::{json, Value as JSONValue};
+use tilejson::TileJSON;
+use tokio::task;
 
+use crate::discovery::{self, SkippedFile};
 use crate::errors::Result;
-use crate::tiles::{get_grid_data, get_tile_data, TileMeta, TileSummaryJSON};
-use crate::utils::{encode, get_blank_image, DataFormat};
+use crate::fonts::{get_font_list, get_glyphs};
+use crate::mvt::{merge_tiles, prefixed_layer_name};
+use crate::raster::{
+    blank_image, composite_images, decode_image, draw_geojson, encode_image, render_static_map,
+    Viewport,
+};
+use crate::style::{get_layer_geometries, get_style};
+use crate::styles::rewrite_style;
+use crate::tiles::{get_composite_details, TileMeta, TileSource, TileSummaryJSON};
+use crate::utils::{
+    decompress, encode, get_query_param, get_query_param_ignore_case,
+    lonlat_to_tile, percent_decode_path, render_template, DataFormat,
+};
+use crate::{ogcapi, tms, wmts};
 
 lazy_static! {
     static ref TILE_URL_RE: Regex =
         Regex::new(r"^/services/(?P<tile_path>.*)/tiles/(?P<z>\d+)/(?P<x>\d+)/(?P<y>\d+)\.(?P<format>[a-zA-Z]+)/?(\?(?P<query>.*))?").unwrap();
+    static ref GRID_PIXEL_URL_RE: Regex =
+        Regex::new(r"^/services/(?P<tile_path>.*)/grid/(?P<z>\d+)/(?P<x>\d+)/(?P<y>\d+)/(?P<px>\d+)/(?P<py>\d+)\.json/?$").unwrap();
+    static ref STATIC_URL_RE: Regex =
+        Regex::new(r"^/services/(?P<tile_path>.*)/static/(?P<coords>[-\d.,]+)/(?P<width>\d+)x(?P<height>\d+)\.(?P<format>[a-zA-Z]+)/?$").unwrap();
+    static ref WMTS_TILE_URL_RE: Regex =
+        Regex::new(r"^/wmts/1\.0\.0/(?P<layer>.+)/(?P<style>[^/]+)/(?P<tile_matrix_set>[^/]+)/(?P<tile_matrix>[^/]+)/(?P<tile_row>[^/]+)/(?P<tile_col>[^/.]+)\.(?P<format>[a-zA-Z]+)/?$").unwrap();
+    static ref TMS_TILE_URL_RE: Regex =
+        Regex::new(r"^/tms/1\.0\.0/(?P<tile_path>.+)/(?P<z>\d+)/(?P<x>\d+)/(?P<y>\d+)\.(?P<format>[a-zA-Z]+)/?$").unwrap();
+    static ref OGC_TILE_URL_RE: Regex =
+        Regex::new(r"^/ogcapi/collections/(?P<id>.+)/tiles/WebMercatorQuad/(?P<z>\d+)/(?P<row>\d+)/(?P<col>\d+)/?$").unwrap();
+    static ref FONTS_URL_RE: Regex =
+        Regex::new(r"^/fonts/(?P<fontstack>[^/]+)/(?P<range>\d+-\d+)\.pbf$").unwrap();
+    static ref GRID_LONLAT_URL_RE: Regex =
+        Regex::new(r"^/services/(?P<tile_path>.*)/grid/(?P<lon>-?\d+(\.\d+)?),(?P<lat>-?\d+(\.\d+)?),(?P<z>\d+)\.json/?$").unwrap();
 }
 
-#[allow(dead_code)]
 static INTERNAL_SERVER_ERROR: &[u8] = b"Internal Server Error";
 static FORBIDDEN: &[u8] = b"Forbidden";
 static NOT_FOUND: &[u8] = b"Not Found";
@@ -42,7 +73,6 @@ fn forbidden() -> Response<Body> {
         .unwrap()
 }
 
-#[allow(dead_code)]
 fn server_error() -> Response<Body> {
     Response::builder()
         .status(StatusCode::INTERNAL_SERVER_ERROR)
@@ -50,6 +80,40 @@ fn server_error() -> Response<Body> {
         .unwrap()
 }
 
+/// Run a handler reading tiles or files, whose file, database or network IO blocks, on the
+/// blocking thread pool rather than on the async workers
+async fn blocking<F>(handler: F) -> Response<Body>
+where
+    F: FnOnce() -> Response<Body> + Send + 'static,
+{
+    task::spawn_blocking(handler).await.unwrap_or_else(|err| {
+        warn!("{err}");
+        server_error()
+    })
+}
+
+/// Bring the TileJSON of GeoJSON tilesets up to date with their files, which are read on the
+/// blocking thread pool when they changed
+async fn refresh_tilesets(
+    mut tilesets: HashMap<String, TileMeta>,
+) -> Option<HashMap<String, TileMeta>> {
+    if !tilesets
+        .values()
+        .any(|tile_meta| matches!(tile_meta.source, TileSource::GeoJson(_)))
+    {
+        return Some(tilesets);
+    }
+    task::spawn_blocking(move || {
+        for tile_meta in tilesets.values_mut() {
+            tile_meta.refresh_tilejson();
+        }
+        tilesets
+    })
+    .await
+    .map_err(|err| warn!("{err}"))
+    .ok()
+}
+
 fn bad_request(msg: String) -> Response<Body> {
     Response::builder()
         .status(StatusCode::BAD_REQUEST)
@@ -65,6 +129,740 @@ pub fn tile_map() -> Response<Body> {
     Response::new(body)
 }
 
+/// Resolve the UTFGrid feature at a pixel of a tile and return its data entry, along with the
+/// tileset template rendered for each interaction mode when the tileset has one
+fn grid_lookup(
+    tile_meta: &TileMeta,
+    z: u32,
+    x: u32,
+    y: u32,
+    px: u32,
+    py: u32,
+    headers: Vec<(String, String)>,
+) -> Response<Body> {
+    if tile_meta.grid_format.is_none() {
+        return not_found();
+    }
+    if x >= 1 << z || y >= 1 << z || px > 255 || py > 255 {
+        return bad_request(String::from("Invalid tile or pixel coordinates"));
+    }
+    let y: u32 = (1 << z) - 1 - y;
+    let grid = match tile_meta.get_grid(z, x, y) {
+        Ok(grid) => grid,
+        Err(_) => return no_content(),
+    };
+    let (key, data) = match grid.lookup(px, py) {
+        Some((key, data)) => (key, data.cloned().unwrap_or(JSONValue::Null)),
+        None => return no_content(),
+    };
+
+    let mut resp_json = json!({ "key": key, "data": data });
+    if let Some(template) = &tile_meta.tilejson.template {
+        resp_json["template"] = json!({
+            "location": render_template(template, &data, "location"),
+            "teaser": render_template(template, &data, "teaser"),
+            "full": render_template(template, &data, "full"),
+        });
+    }
+
+    let mut response = Response::builder();
+    for (k, v) in headers {
+        response = response.header(&k, &v);
+    }
+    response
+        .header(CONTENT_TYPE, DataFormat::Json.content_type())
+        .body(Body::from(resp_json.to_string()))
+        .unwrap()
+}
+
+/// Look up the tilesets of a comma-separated composite path such as `a,b,c`.
+/// Returns `None` when the path is not a composite or one of its tilesets does not exist.
+fn get_composite_members<'a>(
+    tilesets: &'a HashMap<String, TileMeta>,
+    tile_path: &'a str,
+) -> Option<Vec<(&'a str, &'a TileMeta)>> {
+    if !tile_path.contains(',') {
+        return None;
+    }
+    tile_path
+        .split(',')
+        .map(|name| tilesets.get(name).map(|tile_meta| (name, tile_meta)))
+        .collect()
+}
+
+/// Borrow composite members owned by a blocking handler
+fn borrow_members(members: &[(String, TileMeta)]) -> Vec<(&str, &TileMeta)> {
+    members
+        .iter()
+        .map(|(name, tile_meta)| (name.as_str(), tile_meta))
+        .collect()
+}
+
+/// Format of the tiles served by a composite. Vector tilesets can only be combined with
+/// vector tilesets and raster tilesets with raster tilesets, whatever their image format.
+fn get_composite_format(members: &[(&str, &TileMeta)]) -> Option<DataFormat> {
+    if members
+        .iter()
+        .all(|(_, m)| m.tile_format == DataFormat::Pbf)
+    {
+        Some(DataFormat::Pbf)
+    } else if members.iter().all(|(_, m)| m.tile_format.is_image()) {
+        Some(DataFormat::Png)
+    } else {
+        None
+    }
+}
+
+/// Parse the comma-separated `opacity` query parameter of a raster composite, one value in
+/// `[0, 1]` per member. Every member is fully opaque by default.
+fn get_composite_opacities(query: Option<&str>, count: usize) -> Option<Vec<f32>> {
+    let opacities = match get_query_param(query, "opacity") {
+        Some(opacities) => opacities,
+        None => return Some(vec![1.0; count]),
+    };
+    let opacities: Vec<f32> = opacities
+        .split(',')
+        .map(|v| {
+            v.trim()
+                .parse::<f32>()
+                .ok()
+                .filter(|v| (0.0..=1.0).contains(v))
+        })
+        .collect::<Option<_>>()?;
+    if opacities.len() != count {
+        return None;
+    }
+    Some(opacities)
+}
+
+/// Read the tile at the given position from each member of a raster composite and draw
+/// them on top of each other into a single image
+fn composite_raster_tile(
+    members: &[(&str, &TileMeta)],
+    z: u32,
+    x: u32,
+    y: u32,
+    output: DataFormat,
+    opacities: &[f32],
+    headers: Vec<(String, String)>,
+) -> Response<Body> {
+    let mut layers = Vec::new();
+    for ((_, tile_meta), opacity) in members.iter().zip(opacities.iter()) {
+        if let Ok(data) = tile_meta.get_tile(z, x, y) {
+            layers.push((data, tile_meta.tile_format, *opacity));
+        }
+    }
+    let data = if layers.is_empty() {
+        blank_image(output)
+    } else {
+        composite_images(&layers, output)
+    };
+    let data = match data {
+        Ok(data) => data,
+        Err(err) => {
+            warn!("{err}");
+            return server_error();
+        }
+    };
+
+    let mut response = Response::builder();
+    for (k, v) in headers {
+        response = response.header(&k, &v);
+    }
+    response
+        .header(CONTENT_TYPE, output.content_type())
+        .body(Body::from(data))
+        .unwrap()
+}
+
+/// Read the tile at the given position from each member of a composite and merge their
+/// layers into a single vector tile
+fn composite_tile(
+    members: &[(&str, &TileMeta)],
+    z: u32,
+    x: u32,
+    y: u32,
+    headers: Vec<(String, String)>,
+) -> Response<Body> {
+    let (_, collisions) = get_composite_details(members);
+    let mut tiles = Vec::new();
+    for ((tile_name, tile_meta), collisions) in members.iter().zip(collisions.iter()) {
+        if let Ok(data) = tile_meta.get_tile(z, x, y) {
+            match decompress(data) {
+                Ok(data) => tiles.push((*tile_name, collisions, data)),
+                Err(err) => warn!("{tile_name}: {err}"),
+            }
+        }
+    }
+    if tiles.is_empty() {
+        return no_content();
+    }
+    let data = match merge_tiles(&tiles) {
+        Ok(data) => data,
+        Err(err) => {
+            warn!("{err}");
+            return server_error();
+        }
+    };
+
+    let mut response = Response::builder();
+    for (k, v) in headers {
+        response = response.header(&k, &v);
+    }
+    response
+        .header(CONTENT_TYPE, DataFormat::Pbf.content_type())
+        .header(CONTENT_ENCODING, "gzip")
+        .body(Body::from(encode(&data)))
+        .unwrap()
+}
+
+/// Largest width or height of a static map image
+const MAX_STATIC_SIZE: u32 = 2048;
+/// Longest GeoJSON overlay of a static map, in bytes
+const MAX_STATIC_GEOJSON_LENGTH: usize = 16 * 1024;
+
+/// Render a static map of a raster tileset for the given viewport, with an optional GeoJSON
+/// overlay drawn on top
+fn static_map(
+    tile_meta: &TileMeta,
+    viewport: &Viewport,
+    output: DataFormat,
+    geojson: Option<JSONValue>,
+    headers: Vec<(String, String)>,
+) -> Response<Body> {
+    let mut image = render_static_map(viewport, |z, x, y| {
+        let data = tile_meta.get_tile(z, x, (1 << z) - 1 - y).ok()?;
+        match decode_image(&data, tile_meta.tile_format) {
+            Ok(tile) => Some(tile),
+            Err(err) => {
+                warn!("{}: {err}", tile_meta.id);
+                None
+            }
+        }
+    });
+    if let Some(geojson) = geojson {
+        draw_geojson(&mut image, viewport, &geojson);
+    }
+    let data = match encode_image(image, output) {
+        Ok(data) => data,
+        Err(err) => {
+            warn!("{err}");
+            return server_error();
+        }
+    };
+
+    let mut response = Response::builder();
+    for (k, v) in headers {
+        response = response.header(&k, &v);
+    }
+    response
+        .header(CONTENT_TYPE, output.content_type())
+        .body(Body::from(data))
+        .unwrap()
+}
+
+/// Serve a tile or UTFGrid of a tileset in the given format, `y` being the TMS row
+/// as stored in mbtiles
+fn tile_response(
+    tile_meta: &TileMeta,
+    z: u32,
+    x: u32,
+    y: u32,
+    data_format: &str,
+    headers: Vec<(String, String)>,
+) -> Response<Body> {
+    let mut response = Response::builder();
+    for (k, v) in headers {
+        response = response.header(&k, &v);
+    }
+
+    match data_format {
+        "json" => match tile_meta.grid_format {
+            Some(_) => match tile_meta.get_grid(z, x, y) {
+                Ok(data) => {
+                    let data = serde_json::to_vec(&data).unwrap();
+                    response
+                        .header(CONTENT_TYPE, DataFormat::Json.content_type())
+                        .header(CONTENT_ENCODING, "gzip")
+                        .body(Body::from(encode(&data)))
+                        .unwrap()
+                }
+                Err(_) => no_content(),
+            },
+            None => not_found(),
+        },
+        "pbf" => match tile_meta.get_tile(z, x, y) {
+            Ok(data) => response
+                .header(CONTENT_TYPE, DataFormat::Pbf.content_type())
+                .header(CONTENT_ENCODING, "gzip")
+                .body(Body::from(data))
+                .unwrap(),
+            Err(_) => no_content(),
+        },
+        _ => {
+            let data_format = DataFormat::new(data_format);
+            let data = match tile_meta
+                .get_image_tile(z, x, y, data_format)
+                .or_else(|_| blank_image(data_format))
+            {
+                Ok(data) => data,
+                Err(err) => {
+                    warn!("{err}");
+                    return server_error();
+                }
+            };
+            response
+                .header(CONTENT_TYPE, data_format.content_type())
+                .body(Body::from(data))
+                .unwrap()
+        }
+    }
+}
+
+fn wmts_exception(status: StatusCode, code: &str, locator: &str, text: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/xml")
+        .body(Body::from(wmts::exception_report(code, locator, text)))
+        .unwrap()
+}
+
+/// Serve a WMTS GetTile request. `format` is the tile extension of RESTful requests or the
+/// MIME type of KVP requests.
+fn wmts_tile(
+    tilesets: &HashMap<String, TileMeta>,
+    params: [&str; 5],
+    format: &str,
+    headers: Vec<(String, String)>,
+) -> Response<Body> {
+    let [layer, tile_matrix_set, tile_matrix, tile_row, tile_col] = params;
+    let tile_meta = match tilesets.get(layer) {
+        Some(tile_meta) => tile_meta,
+        None => {
+            return wmts_exception(
+                StatusCode::BAD_REQUEST,
+                "InvalidParameterValue",
+                "layer",
+                &format!("Unknown layer: {layer}"),
+            )
+        }
+    };
+    if tile_matrix_set != wmts::TILE_MATRIX_SET {
+        return wmts_exception(
+            StatusCode::BAD_REQUEST,
+            "InvalidParameterValue",
+            "tilematrixset",
+            &format!("Unknown tile matrix set: {tile_matrix_set}"),
+        );
+    }
+    if format != tile_meta.tile_format.format() && format != tile_meta.tile_format.content_type() {
+        return wmts_exception(
+            StatusCode::BAD_REQUEST,
+            "InvalidParameterValue",
+            "format",
+            &format!("Unsupported format: {format}"),
+        );
+    }
+    let z = match tile_matrix.parse::<u32>() {
+        Ok(z) if z <= 22 => z,
+        _ => {
+            return wmts_exception(
+                StatusCode::BAD_REQUEST,
+                "InvalidParameterValue",
+                "tilematrix",
+                &format!("Unknown tile matrix: {tile_matrix}"),
+            )
+        }
+    };
+    let (y, x) = match (tile_row.parse::<u32>(), tile_col.parse::<u32>()) {
+        (Ok(y), Ok(x)) if y < 1 << z && x < 1 << z => (y, x),
+        _ => {
+            return wmts_exception(
+                StatusCode::BAD_REQUEST,
+                "TileOutOfRange",
+                "tilerow",
+                "Tile row or column out of range",
+            )
+        }
+    };
+    let y: u32 = (1 << z) - 1 - y;
+    tile_response(tile_meta, z, x, y, tile_meta.tile_format.format(), headers)
+}
+
+/// Serve the WMTS capabilities document and KVP (`/wmts?REQUEST=...`) or RESTful
+/// (`/wmts/1.0.0/...`) GetTile requests
+fn wmts_service(
+    tilesets: &HashMap<String, TileMeta>,
+    path: &str,
+    query: Option<&str>,
+    root_url: &str,
+    headers: Vec<(String, String)>,
+) -> Response<Body> {
+    let capabilities = || {
+        Response::builder()
+            .header(CONTENT_TYPE, "application/xml")
+            .body(Body::from(wmts::get_capabilities(tilesets, root_url)))
+            .unwrap()
+    };
+
+    if path.trim_end_matches('/') == "/wmts" {
+        let param = |name: &str| get_query_param_ignore_case(query, name);
+        if let Some(service) = param("service") {
+            if !service.eq_ignore_ascii_case("WMTS") {
+                return wmts_exception(
+                    StatusCode::BAD_REQUEST,
+                    "InvalidParameterValue",
+                    "service",
+                    &format!("Unsupported service: {service}"),
+                );
+            }
+        }
+        return match param("request").as_deref() {
+            Some(r) if r.eq_ignore_ascii_case("GetCapabilities") => capabilities(),
+            Some(r) if r.eq_ignore_ascii_case("GetTile") => {
+                let names = [
+                    "layer",
+                    "tilematrixset",
+                    "tilematrix",
+                    "tilerow",
+                    "tilecol",
+                    "format",
+                ];
+                let mut values = Vec::new();
+                for name in names {
+                    match param(name) {
+                        Some(value) => values.push(value),
+                        None => {
+                            return wmts_exception(
+                                StatusCode::BAD_REQUEST,
+                                "MissingParameterValue",
+                                name,
+                                &format!("Missing parameter: {name}"),
+                            )
+                        }
+                    }
+                }
+                wmts_tile(
+                    tilesets,
+                    [&values[0], &values[1], &values[2], &values[3], &values[4]],
+                    &values[5],
+                    headers,
+                )
+            }
+            Some(r) => wmts_exception(
+                StatusCode::BAD_REQUEST,
+                "OperationNotSupported",
+                "request",
+                &format!("Unsupported request: {r}"),
+            ),
+            None => wmts_exception(
+                StatusCode::BAD_REQUEST,
+                "MissingParameterValue",
+                "request",
+                "Missing parameter: request",
+            ),
+        };
+    }
+
+    if path == "/wmts/1.0.0/WMTSCapabilities.xml" {
+        return capabilities();
+    }
+
+    match WMTS_TILE_URL_RE.captures(path) {
+        Some(matches) => {
+            let param = |name: &str| matches.name(name).unwrap().as_str();
+            wmts_tile(
+                tilesets,
+                [
+                    param("layer"),
+                    param("tile_matrix_set"),
+                    param("tile_matrix"),
+                    param("tile_row"),
+                    param("tile_col"),
+                ],
+                param("format"),
+                headers,
+            )
+        }
+        None => not_found(),
+    }
+}
+
+/// Serve the TileMapService and TileMap documents and tiles addressed in TMS row order
+fn tms_service(
+    tilesets: &HashMap<String, TileMeta>,
+    path: &str,
+    root_url: &str,
+    headers: Vec<(String, String)>,
+) -> Response<Body> {
+    let xml_response = |xml: String| {
+        Response::builder()
+            .header(CONTENT_TYPE, "application/xml")
+            .body(Body::from(xml))
+            .unwrap()
+    };
+
+    let path = path.trim_end_matches('/');
+    if path == "/tms" || path == "/tms/1.0.0" {
+        return xml_response(tms::get_tile_map_service(tilesets, root_url));
+    }
+
+    if let Some(matches) = TMS_TILE_URL_RE.captures(path) {
+        let tile_path = matches.name("tile_path").unwrap().as_str();
+        let tile_meta = match tilesets.get(tile_path) {
+            Some(tile_meta) => tile_meta,
+            None => return bad_request(format!("Tileset does not exist: {tile_path}")),
+        };
+        let coords: Vec<u32> = ["z", "x", "y"]
+            .iter()
+            .map(|name| {
+                matches
+                    .name(name)
+                    .unwrap()
+                    .as_str()
+                    .parse()
+                    .unwrap_or(u32::MAX)
+            })
+            .collect();
+        let (z, x, y) = (coords[0], coords[1], coords[2]);
+        if z > u32::from(tms::MAX_ZOOM) || x >= 1 << z || y >= 1 << z {
+            return bad_request(String::from("Invalid tile coordinates"));
+        }
+        let data_format = matches.name("format").unwrap().as_str();
+        return tile_response(tile_meta, z, x, y, data_format, headers);
+    }
+
+    match path.strip_prefix("/tms/1.0.0/") {
+        Some(tile_name) => match tilesets.get(tile_name) {
+            Some(tile_meta) => xml_response(tms::get_tile_map(tile_name, tile_meta, root_url)),
+            None => bad_request(format!("Tileset does not exist: {tile_name}")),
+        },
+        None => not_found(),
+    }
+}
+
+fn sprite_response(
+    file_name: &str,
+    data: Vec<u8>,
+    headers: Vec<(String, String)>,
+) -> Response<Body> {
+    let content_type = match file_name.ends_with(".json") {
+        true => "application/json",
+        false => "image/png",
+    };
+    let mut response = Response::builder();
+    for (k, v) in headers {
+        response = response.header(k, v);
+    }
+    response
+        .header(CONTENT_TYPE, content_type)
+        .body(Body::from(data))
+        .unwrap()
+}
+
+/// Serve the list of available fonts and glyph ranges of fontstacks
+fn fonts_service(fonts_dir: &Path, path: &str, headers: Vec<(String, String)>) -> Response<Body> {
+    let mut response = Response::builder();
+    for (k, v) in headers {
+        response = response.header(k, v);
+    }
+    if path == "/fonts.json" {
+        return response
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                serde_json::to_string(&get_font_list(fonts_dir)).unwrap(),
+            ))
+            .unwrap();
+    }
+
+    let matches = match FONTS_URL_RE.captures(path) {
+        Some(matches) => matches,
+        None => return not_found(),
+    };
+    let fontstack = percent_decode_path(matches.name("fontstack").unwrap().as_str());
+    let range = matches.name("range").unwrap().as_str();
+    match get_glyphs(fonts_dir, &fontstack, range) {
+        Ok(Some(data)) => response
+            .header(CONTENT_TYPE, "application/x-protobuf")
+            .body(Body::from(data))
+            .unwrap(),
+        Ok(None) => not_found(),
+        Err(err) => {
+            warn!("{err}");
+            server_error()
+        }
+    }
+}
+
+/// Serve the OGC API - Tiles landing page, conformance, collections, tilesets and tiles.
+/// Documents are returned as JSON or as HTML depending on the `f` query parameter or, when
+/// missing, the `Accept` header.
+fn ogcapi_service(
+    request: &Request<Body>,
+    tilesets: &HashMap<String, TileMeta>,
+    root_url: &str,
+    headers: Vec<(String, String)>,
+) -> Response<Body> {
+    let api_url = format!("{root_url}/ogcapi");
+    let html = match get_query_param(request.uri().query(), "f").as_deref() {
+        Some("html") => true,
+        Some("json") => false,
+        Some(f) => return bad_request(format!("Unsupported format: {f}")),
+        None => request
+            .headers()
+            .get(ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .is_some_and(|accept| accept.contains("text/html")),
+    };
+    let document = |title: &str, doc: JSONValue| {
+        let (content_type, body) = if html {
+            ("text/html", ogcapi::to_html(title, &doc))
+        } else {
+            ("application/json", doc.to_string())
+        };
+        Response::builder()
+            .header(CONTENT_TYPE, content_type)
+            .body(Body::from(body))
+            .unwrap()
+    };
+
+    let path = request.uri().path().trim_end_matches('/');
+    if let Some(matches) = OGC_TILE_URL_RE.captures(path) {
+        let id = percent_decode_path(matches.name("id").unwrap().as_str());
+        let tile_meta = match tilesets.get(&id) {
+            Some(tile_meta) => tile_meta,
+            None => return not_found(),
+        };
+        let coords: Vec<u32> = ["z", "row", "col"]
+            .iter()
+            .map(|name| {
+                matches
+                    .name(name)
+                    .unwrap()
+                    .as_str()
+                    .parse()
+                    .unwrap_or(u32::MAX)
+            })
+            .collect();
+        let (z, y, x) = (coords[0], coords[1], coords[2]);
+        if z > 24 || x >= 1 << z || y >= 1 << z {
+            return not_found();
+        }
+        let y: u32 = (1 << z) - 1 - y;
+        return tile_response(tile_meta, z, x, y, tile_meta.tile_format.format(), headers);
+    }
+
+    match path {
+        "/ogcapi" => document("mbtileserver", ogcapi::get_landing_page(&api_url)),
+        "/ogcapi/conformance" => document("Conformance", ogcapi::get_conformance()),
+        "/ogcapi/collections" => {
+            document("Collections", ogcapi::get_collections(tilesets, &api_url))
+        }
+        "/ogcapi/tileMatrixSets" => {
+            document("Tile matrix sets", ogcapi::get_tile_matrix_sets(&api_url))
+        }
+        "/ogcapi/tileMatrixSets/WebMercatorQuad" => {
+            document(ogcapi::TILE_MATRIX_SET, ogcapi::get_tile_matrix_set())
+        }
+        _ => {
+            let collection = match path.strip_prefix("/ogcapi/collections/") {
+                Some(collection) => collection,
+                None => return not_found(),
+            };
+            let (id, resource) = if let Some(id) = collection.strip_suffix("/tiles/WebMercatorQuad")
+            {
+                (id, "tileset")
+            } else if let Some(id) = collection.strip_suffix("/tiles") {
+                (id, "tilesets")
+            } else {
+                (collection, "collection")
+            };
+            let id = percent_decode_path(id);
+            let tile_meta = match tilesets.get(&id) {
+                Some(tile_meta) => tile_meta,
+                None => return not_found(),
+            };
+            let title = tile_meta.tilejson.name.as_deref().unwrap_or(&id);
+            match resource {
+                "tileset" => document(title, ogcapi::get_tileset(&id, tile_meta, &api_url)),
+                "tilesets" => document(title, ogcapi::get_tilesets(&id, tile_meta, &api_url)),
+                _ => document(title, ogcapi::get_collection(&id, tile_meta, &api_url)),
+            }
+        }
+    }
+}
+
+/// Return the default MapLibre GL style of a vector tileset or of combined vector tilesets
+fn tileset_style(
+    tilesets: &HashMap<String, TileMeta>,
+    tile_name: &str,
+    base_url: &str,
+) -> Response<Body> {
+    let (tilejson, geometries) = match tilesets.get(tile_name) {
+        Some(tile_meta) if tile_meta.tile_format == DataFormat::Pbf => (
+            tile_meta.tilejson.clone(),
+            get_layer_geometries(&tile_meta.tilejson),
+        ),
+        Some(_) => {
+            return bad_request(String::from(
+                "Styles are only generated for vector tilesets",
+            ))
+        }
+        None => match get_composite_members(tilesets, tile_name) {
+            Some(members) if get_composite_format(&members) == Some(DataFormat::Pbf) => {
+                let (tilejson, collisions) = get_composite_details(&members);
+                let mut geometries = HashMap::new();
+                for ((member_name, tile_meta), collisions) in members.iter().zip(collisions) {
+                    for (layer, geometry) in get_layer_geometries(&tile_meta.tilejson) {
+                        let layer = match collisions.contains(&layer) {
+                            true => prefixed_layer_name(member_name, &layer),
+                            false => layer,
+                        };
+                        geometries.insert(layer, geometry);
+                    }
+                }
+                (tilejson, geometries)
+            }
+            Some(_) => {
+                return bad_request(String::from(
+                    "Styles are only generated for vector tilesets",
+                ))
+            }
+            None => return bad_request(format!("Tileset does not exist: {tile_name}")),
+        },
+    };
+    let style = get_style(
+        tile_name,
+        &tilejson,
+        &format!("{base_url}/{tile_name}"),
+        &geometries,
+    );
+    Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&style).unwrap()))
+        .unwrap() // TODO handle error
+}
+
+/// Serialize the TileJSON in the version requested by the `tilejson` query parameter.
+///
+/// TileJSON 3.0.0 is the default, 2.x versions are kept for older clients and leave out
+/// `fillzoom` and `vector_layers`, which were introduced in 3.0.0.
+fn tilejson_response(mut tilejson: TileJSON, query: Option<&str>) -> Response<Body> {
+    match get_query_param(query, "tilejson").as_deref() {
+        None | Some("3.0.0") => (),
+        Some(version @ ("2.2.0" | "2.1.0" | "2.0.0")) => {
+            tilejson.tilejson = version.to_string();
+            tilejson.fillzoom = None;
+            tilejson.vector_layers = None;
+        }
+        Some(version) => return bad_request(format!("Unsupported TileJSON version: {version}")),
+    }
+    Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&tilejson).unwrap()))
+        .unwrap() // TODO handle error
+}
+
 fn get_host(req: &Request<Body>) -> Option<&str> {
     let host = req.uri().host();
     if host.is_some() {
@@ -100,12 +898,24 @@ fn is_host_valid(host: &Option<&str>, allowed_hosts: &[String]) -> bool {
     false
 }
 
+/// Files served alongside the tilesets to render them with a style
+#[derive(Debug, Default)]
+pub struct Assets {
+    pub fonts: Option<PathBuf>,
+    pub sprites: HashMap<String, Vec<u8>>,
+    pub styles: HashMap<String, JSONValue>,
+    /// Files skipped by the discovery of the tilesets, reported when admin endpoints are enabled
+    pub skipped: Option<Vec<SkippedFile>>,
+}
+
 pub async fn get_service(
     request: Request<Body>,
     tilesets: HashMap<String, TileMeta>,
     allowed_hosts: Vec<String>,
     headers: Vec<(String, String)>,
     disable_preview: bool,
+    enable_tms: bool,
+    assets: Arc<Assets>,
 ) -> Result<Response<Body>> {
     let host = get_host(&request);
 
@@ -113,6 +923,11 @@ pub async fn get_service(
         return Ok(forbidden());
     };
 
+    let tilesets = match refresh_tilesets(tilesets).await {
+        Some(tilesets) => tilesets,
+        None => return Ok(server_error()),
+    };
+
     let host = host.unwrap();
     let uri = request.uri();
     let path = uri.path();
@@ -120,69 +935,288 @@ pub async fn get_service(
         Some(scheme) => format!("{scheme}://"),
         None => "http://".to_string(),
     };
-    let base_url = format!("{scheme}{host}/services");
+    let root_url = format!("{scheme}{host}");
+    let base_url = format!("{root_url}/services");
+
+    if path == "/tms" || path.starts_with("/tms/") {
+        if !enable_tms {
+            return Ok(not_found());
+        }
+        let path = path.to_string();
+        return Ok(blocking(move || tms_service(&tilesets, &path, &root_url, headers)).await);
+    }
+
+    if path == "/admin/discovery" {
+        return Ok(match &assets.skipped {
+            Some(skipped) => Response::builder()
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    serde_json::to_string(&discovery::report(&tilesets, skipped)).unwrap(),
+                ))
+                .unwrap(),
+            None => not_found(),
+        });
+    }
+
+    if path == "/fonts.json" || path.starts_with("/fonts/") {
+        return Ok(match &assets.fonts {
+            Some(fonts_dir) => {
+                let (fonts_dir, path) = (fonts_dir.clone(), path.to_string());
+                blocking(move || fonts_service(&fonts_dir, &path, headers)).await
+            }
+            None => not_found(),
+        });
+    }
+
+    if let Some(file_name) = path.strip_prefix("/styles/") {
+        let style = file_name
+            .strip_suffix(".json")
+            .and_then(|name| assets.styles.get(name));
+        return Ok(match style {
+            Some(style) => {
+                let mut response = Response::builder();
+                for (k, v) in headers {
+                    response = response.header(k, v);
+                }
+                response
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        serde_json::to_string(&rewrite_style(style.clone(), &root_url)).unwrap(),
+                    ))
+                    .unwrap()
+            }
+            None => not_found(),
+        });
+    }
+
+    if let Some(file_name) = path.strip_prefix("/sprites/") {
+        return Ok(match assets.sprites.get(file_name) {
+            Some(data) => sprite_response(file_name, data.clone(), headers),
+            None => not_found(),
+        });
+    }
+
+    if path == "/ogcapi" || path.starts_with("/ogcapi/") {
+        return Ok(blocking(move || ogcapi_service(&request, &tilesets, &root_url, headers)).await);
+    }
+
+    if path == "/wmts" || path.starts_with("/wmts/") {
+        let path = path.to_string();
+        let query = uri.query().map(String::from);
+        return Ok(blocking(move || {
+            wmts_service(&tilesets, &path, query.as_deref(), &root_url, headers)
+        })
+        .await);
+    }
+
+    if let Some(matches) = GRID_PIXEL_URL_RE.captures(path) {
+        let tile_path = matches.name("tile_path").unwrap().as_str();
+        let tile_meta = match tilesets.get(tile_path) {
+            Some(tile_meta) => tile_meta,
+            None => return Ok(bad_request(format!("Tileset does not exist: {tile_path}"))),
+        };
+        let coords: Vec<u32> = ["z", "x", "y", "px", "py"]
+            .iter()
+            .map(|name| {
+                matches
+                    .name(name)
+                    .unwrap()
+                    .as_str()
+                    .parse()
+                    .unwrap_or(u32::MAX)
+            })
+            .collect();
+        if coords[0] > 30 {
+            return Ok(bad_request(String::from("Invalid zoom level")));
+        }
+        let tile_meta = tile_meta.clone();
+        return Ok(blocking(move || {
+            grid_lookup(
+                &tile_meta, coords[0], coords[1], coords[2], coords[3], coords[4], headers,
+            )
+        })
+        .await);
+    }
+
+    if let Some(matches) = GRID_LONLAT_URL_RE.captures(path) {
+        let tile_path = matches.name("tile_path").unwrap().as_str();
+        let tile_meta = match tilesets.get(tile_path) {
+            Some(tile_meta) => tile_meta,
+            None => return Ok(bad_request(format!("Tileset does not exist: {tile_path}"))),
+        };
+        let lon = matches
+            .name("lon")
+            .unwrap()
+            .as_str()
+            .parse::<f64>()
+            .unwrap();
+        let lat = matches
+            .name("lat")
+            .unwrap()
+            .as_str()
+            .parse::<f64>()
+            .unwrap();
+        let z = matches
+            .name("z")
+            .unwrap()
+            .as_str()
+            .parse::<u32>()
+            .unwrap_or(u32::MAX);
+        if z > 30 {
+            return Ok(bad_request(String::from("Invalid zoom level")));
+        }
+        return match lonlat_to_tile(lon, lat, z) {
+            Some((x, y, px, py)) => {
+                let tile_meta = tile_meta.clone();
+                Ok(blocking(move || grid_lookup(&tile_meta, z, x, y, px, py, headers)).await)
+            }
+            None => Ok(bad_request(String::from("Coordinates out of range"))),
+        };
+    }
+
+    if let Some(matches) = STATIC_URL_RE.captures(path) {
+        let tile_path = matches.name("tile_path").unwrap().as_str();
+        let tile_meta = match tilesets.get(tile_path) {
+            Some(tile_meta) => tile_meta,
+            None => return Ok(bad_request(format!("Tileset does not exist: {tile_path}"))),
+        };
+        if !tile_meta.tile_format.is_image() {
+            return Ok(bad_request(String::from(
+                "Static maps are only available for raster tilesets",
+            )));
+        }
+        let output = DataFormat::new(matches.name("format").unwrap().as_str());
+        if !output.is_image() {
+            return Ok(bad_request(String::from("Invalid image format")));
+        }
+        let width = matches.name("width").unwrap().as_str().parse().unwrap_or(0);
+        let height = matches
+            .name("height")
+            .unwrap()
+            .as_str()
+            .parse()
+            .unwrap_or(0);
+        if !(1..=MAX_STATIC_SIZE).contains(&width) || !(1..=MAX_STATIC_SIZE).contains(&height) {
+            return Ok(bad_request(format!(
+                "Image width and height must be between 1 and {MAX_STATIC_SIZE}"
+            )));
+        }
+        let coords: Vec<f64> = match matches
+            .name("coords")
+            .unwrap()
+            .as_str()
+            .split(',')
+            .map(|v| v.parse::<f64>())
+            .collect()
+        {
+            Ok(coords) => coords,
+            Err(_) => return Ok(bad_request(String::from("Invalid coordinates"))),
+        };
+        let viewport = match coords[..] {
+            [lon, lat, zoom] if zoom.fract() == 0.0 && (0.0..=30.0).contains(&zoom) => {
+                Viewport::from_center(lon, lat, zoom as u32, width, height)
+            }
+            [min_lon, min_lat, max_lon, max_lat] if min_lon < max_lon && min_lat < max_lat => {
+                let max_zoom = u32::from(tile_meta.tilejson.maxzoom.unwrap_or(22));
+                Viewport::from_bounds(
+                    [min_lon, min_lat, max_lon, max_lat],
+                    max_zoom,
+                    width,
+                    height,
+                )
+            }
+            _ => {
+                return Ok(bad_request(String::from(
+                    "Expected {lon},{lat},{zoom} or {minlon},{minlat},{maxlon},{maxlat}",
+                )))
+            }
+        };
+        let geojson = match get_query_param(request.uri().query(), "geojson") {
+            Some(geojson) if geojson.len() > MAX_STATIC_GEOJSON_LENGTH => {
+                return Ok(bad_request(format!(
+                    "GeoJSON overlays are limited to {MAX_STATIC_GEOJSON_LENGTH} bytes"
+                )))
+            }
+            Some(geojson) => match serde_json::from_str(&geojson) {
+                Ok(geojson) => Some(geojson),
+                Err(err) => return Ok(bad_request(format!("Invalid GeoJSON: {err}"))),
+            },
+            None => None,
+        };
+        let tile_meta = tile_meta.clone();
+        return Ok(
+            blocking(move || static_map(&tile_meta, &viewport, output, geojson, headers)).await,
+        );
+    }
 
     match TILE_URL_RE.captures(path) {
         Some(matches) => {
             let tile_path = matches.name("tile_path").unwrap().as_str();
-            let tile_meta = tilesets.get(tile_path).unwrap();
             let z = matches.name("z").unwrap().as_str().parse::<u32>().unwrap();
             let x = matches.name("x").unwrap().as_str().parse::<u32>().unwrap();
             let y = matches.name("y").unwrap().as_str().parse::<u32>().unwrap();
             let y: u32 = (1 << z) - 1 - y;
             let data_format = matches.name("format").unwrap().as_str();
+            let tile_meta = match tilesets.get(tile_path) {
+                Some(tile_meta) => tile_meta.clone(),
+                None => {
+                    let members = match get_composite_members(&tilesets, tile_path) {
+                        Some(members) => members,
+                        None => {
+                            return Ok(bad_request(format!("Tileset does not exist: {tile_path}")))
+                        }
+                    };
+                    let output = DataFormat::new(data_format);
+                    let members: Vec<(String, TileMeta)> = members
+                        .into_iter()
+                        .map(|(name, tile_meta)| (name.to_string(), tile_meta.clone()))
+                        .collect();
+                    let format = get_composite_format(&borrow_members(&members));
+                    return match format {
+                        Some(DataFormat::Pbf) if output == DataFormat::Pbf => {
+                            Ok(blocking(move || {
+                                composite_tile(&borrow_members(&members), z, x, y, headers)
+                            })
+                            .await)
+                        }
+                        Some(DataFormat::Png) if output.is_image() => {
+                            match get_composite_opacities(request.uri().query(), members.len()) {
+                                Some(opacities) => Ok(blocking(move || {
+                                    composite_raster_tile(
+                                        &borrow_members(&members),
+                                        z,
+                                        x,
+                                        y,
+                                        output,
+                                        &opacities,
+                                        headers,
+                                    )
+                                })
+                                .await),
+                                None => Ok(bad_request(String::from(
+                                    "Expected one opacity between 0 and 1 per tileset",
+                                ))),
+                            }
+                        }
+                        Some(_) => Ok(bad_request(format!(
+                            "Invalid tile format for combined tilesets: {data_format}"
+                        ))),
+                        None => Ok(bad_request(String::from(
+                            "Only tilesets of the same kind (vector or raster) can be combined",
+                        ))),
+                    };
+                }
+            };
             // For future use
             let _query_string = match matches.name("query") {
                 Some(q) => q.as_str(),
                 None => "",
             };
 
-            let mut response = Response::builder();
-            for (k, v) in headers {
-                response = response.header(&k, &v);
-            }
-
-            return match data_format {
-                "json" => match tile_meta.grid_format {
-                    Some(grid_format) => match get_grid_data(
-                        &tile_meta.connection_pool.get().unwrap(),
-                        grid_format,
-                        z,
-                        x,
-                        y,
-                    ) {
-                        Ok(data) => {
-                            let data = serde_json::to_vec(&data).unwrap();
-                            Ok(response
-                                .header(CONTENT_TYPE, DataFormat::Json.content_type())
-                                .header(CONTENT_ENCODING, "gzip")
-                                .body(Body::from(encode(&data)))
-                                .unwrap())
-                        }
-                        Err(_) => Ok(no_content()),
-                    },
-                    None => Ok(not_found()),
-                },
-                "pbf" => match get_tile_data(&tile_meta.connection_pool.get().unwrap(), z, x, y) {
-                    Ok(data) => Ok(response
-                        .header(CONTENT_TYPE, DataFormat::Pbf.content_type())
-                        .header(CONTENT_ENCODING, "gzip")
-                        .body(Body::from(data))
-                        .unwrap()),
-                    Err(_) => Ok(no_content()),
-                },
-                _ => {
-                    let data =
-                        match get_tile_data(&tile_meta.connection_pool.get().unwrap(), z, x, y) {
-                            Ok(data) => data,
-                            Err(_) => get_blank_image(),
-                        };
-                    Ok(response
-                        .header(CONTENT_TYPE, DataFormat::new(data_format).content_type())
-                        .body(Body::from(data))
-                        .unwrap())
-                }
-            };
+            let data_format = data_format.to_string();
+            return Ok(
+                blocking(move || tile_response(&tile_meta, z, x, y, &data_format, headers)).await,
+            );
         }
         None => {
             if path.starts_with("/services") {
@@ -205,53 +1239,91 @@ pub async fn get_service(
 
                 // Tileset details (/services/<tileset-path>)
                 let tile_name = segments[1..].join("/");
+                let query_string = match request.uri().query() {
+                    Some(q) => format!("?{q}"),
+                    None => String::new(),
+                };
                 let tile_meta = match tilesets.get(&tile_name) {
                     Some(tile_meta) => tile_meta.clone(),
                     None => {
+                        if let Some(members) = get_composite_members(&tilesets, &tile_name) {
+                            // Composite details (/services/<tileset-path>,<tileset-path>)
+                            let tile_format = match get_composite_format(&members) {
+                                Some(tile_format) => tile_format,
+                                None => return Ok(bad_request(String::from(
+                                    "Only tilesets of the same kind (vector or raster) can be combined",
+                                ))),
+                            };
+                            let (mut tilejson, _) = get_composite_details(&members);
+                            tilejson.tiles[0] = format!(
+                                "{base_url}/{tile_name}/tiles/{{z}}/{{x}}/{{y}}.{format}{query_string}",
+                                format = tile_format.format()
+                            );
+                            tilejson.other.insert("id".to_string(), json!(tile_name));
+                            tilejson
+                                .other
+                                .insert("format".to_string(), json!(tile_format));
+                            if !disable_preview {
+                                tilejson.other.insert(
+                                    "map".to_string(),
+                                    json!(format!("{base_url}/{tile_name}/map")),
+                                );
+                            }
+                            return Ok(tilejson_response(tilejson, request.uri().query()));
+                        }
+                        if segments[segments.len() - 1] == "style.json" {
+                            // Tileset default style (/services/<tileset-path>/style.json)
+                            let tile_name = segments[1..segments.len() - 1].join("/");
+                            return Ok(tileset_style(&tilesets, &tile_name, &base_url));
+                        }
                         if segments[segments.len() - 1] == "map" {
                             // Tileset map preview (/services/<tileset-path>/map)
                             let tile_name = segments[1..segments.len() - 1].join("/");
-                            return match tilesets.get(&tile_name) {
-                                Some(_) => {
-                                    if disable_preview {
-                                        return Ok(not_found());
-                                    }
-                                    Ok(tile_map())
-                                }
-                                None => {
-                                    Ok(bad_request(format!("Tileset does not exist: {tile_name}")))
-                                }
-                            };
+                            if !tilesets.contains_key(&tile_name)
+                                && get_composite_members(&tilesets, &tile_name).is_none()
+                            {
+                                return Ok(bad_request(format!(
+                                    "Tileset does not exist: {tile_name}"
+                                )));
+                            }
+                            if disable_preview {
+                                return Ok(not_found());
+                            }
+                            return Ok(tile_map());
                         }
                         return Ok(bad_request(format!("Tileset does not exist: {tile_name}")));
                     }
                 };
-                let query_string = match request.uri().query() {
-                    Some(q) => format!("?{q}"),
-                    None => String::new(),
+
+                let scheme = get_query_param(request.uri().query(), "scheme");
+                let tiles_url = match scheme.as_deref() {
+                    None | Some("xyz") => format!("{base_url}/{tile_name}/tiles"),
+                    Some("tms") if enable_tms => format!("{root_url}/tms/1.0.0/{tile_name}"),
+                    Some("tms") => {
+                        return Ok(bad_request(String::from("TMS endpoint is not enabled")))
+                    }
+                    Some(scheme) => return Ok(bad_request(format!("Invalid scheme: {scheme}"))),
                 };
 
                 let mut tilejson = tile_meta.tilejson.clone();
                 tilejson.tiles[0] = format!(
-                    "{base_url}/{tile_name}/tiles/{{z}}/{{x}}/{{y}}.{format}{query_string}",
+                    "{tiles_url}/{{z}}/{{x}}/{{y}}.{format}{query_string}",
                     format = tile_meta.tile_format.format()
                 );
+                tilejson.scheme = Some(scheme.unwrap_or_else(|| "xyz".to_string()));
                 tilejson.other.insert("id".to_string(), json!(tile_meta.id));
                 tilejson
                     .other
                     .insert("format".to_string(), json!(tile_meta.tile_format));
-                tilejson.other.insert(
-                    "grids".to_string(),
-                    json!(tile_meta.grid_format.map(|_| vec![format!(
-                        "{base_url}/{tile_name}/tiles/{{z}}/{{x}}/{{y}}.json{query_string}"
-                    )])),
-                );
+                tilejson.grids = tile_meta
+                    .grid_format
+                    .map(|_| vec![format!("{tiles_url}/{{z}}/{{x}}/{{y}}.json{query_string}")]);
                 tilejson
                     .other
                     .insert("type".to_string(), json!(tile_meta.layer_type));
-                if let Some(json_data) = tile_meta.json {
-                    for (k, v) in json_data.as_object().unwrap() {
-                        tilejson.other.insert(k.to_string(), v.clone());
+                if let Some(JSONValue::Object(json_data)) = tile_meta.json {
+                    for (k, v) in json_data {
+                        tilejson.other.insert(k, v);
                     }
                 }
                 if !disable_preview {
@@ -261,10 +1333,7 @@ pub async fn get_service(
                     );
                 }
 
-                return Ok(Response::builder()
-                    .header(CONTENT_TYPE, "application/json")
-                    .body(Body::from(serde_json::to_string(&tilejson).unwrap()))
-                    .unwrap()); // TODO handle error
+                return Ok(tilejson_response(tilejson, request.uri().query()));
             }
         }
     };
@@ -274,12 +1343,13 @@ pub async fn get_service(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::tiles::discover_tilesets;
-    use crate::utils::decode;
+    use crate::discovery::discover_tilesets;
+    use crate::fonts::{Fontstack, Glyph, Glyphs};
+    use crate::utils::{decode, get_blank_image};
     use hyper::body;
-    use serde_json;
-    use serde_json::Value as JSONValue;
-    use std::path::PathBuf;
+    use prost::Message;
+    use std::fs::{create_dir, write};
+    use tempdir::TempDir;
 
     async fn setup(
         host: &str,
@@ -293,13 +1363,15 @@ mod tests {
             .body(Body::from(""))
             .unwrap();
 
-        let tilesets = discover_tilesets(String::new(), &PathBuf::from("./tiles"));
+        let tilesets = discover_tilesets(&PathBuf::from("./tiles")).tilesets;
         get_service(
             request,
             tilesets,
             allowed_hosts.unwrap_or(vec!["*".to_string()]),
             headers.unwrap_or(vec![]),
             disable_preview,
+            true,
+            Arc::new(Assets::default()),
         )
         .await
         .unwrap()
@@ -434,21 +1506,39 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn get_existing_utfgrid_data() {
+    async fn get_non_existing_jpg_tile() {
+        // A missing JPEG tile should be blank in the requested format rather than PNG
         let response = setup(
             "http://localhost",
-            "/services/geography-class-png/tiles/0/0/0.json",
+            "/services/geography-class-jpg/tiles/2/0/0.jpg",
             None,
             None,
             false,
         )
         .await;
         assert_eq!(response.status(), 200);
-        let data: JSONValue = serde_json::from_str(
-            &decode(
-                body::to_bytes(response.into_body()).await.unwrap().to_vec(),
-                DataFormat::Gzip,
-            )
+        assert_eq!(response.headers().get(CONTENT_TYPE).unwrap(), "image/jpeg");
+        let data = body::to_bytes(response.into_body()).await.unwrap();
+        let image = decode_image(&data, DataFormat::Jpg).unwrap();
+        assert_eq!(image.dimensions(), (256, 256));
+    }
+
+    #[tokio::test]
+    async fn get_existing_utfgrid_data() {
+        let response = setup(
+            "http://localhost",
+            "/services/geography-class-png/tiles/0/0/0.json",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let data: JSONValue = serde_json::from_str(
+            &decode(
+                body::to_bytes(response.into_body()).await.unwrap().to_vec(),
+                DataFormat::Gzip,
+            )
             .unwrap(),
         )
         .unwrap();
@@ -471,6 +1561,788 @@ mod tests {
         assert_eq!(response.status(), 204);
     }
 
+    #[tokio::test]
+    async fn get_utfgrid_pixel_data() {
+        let response = setup(
+            "http://localhost",
+            "/services/geography-class-png/grid/1/0/0/113/193.json",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let data: JSONValue =
+            serde_json::from_slice(&body::to_bytes(response.into_body()).await.unwrap()).unwrap();
+        assert_eq!(data["data"]["admin"], "United States of America");
+        assert!(data["template"]["teaser"]
+            .as_str()
+            .unwrap()
+            .contains("<strong>United States of America</strong>"));
+    }
+
+    #[tokio::test]
+    async fn get_utfgrid_lonlat_data() {
+        let response = setup(
+            "http://localhost",
+            "/services/geography-class-png/grid/-100.5,40,1.json",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let data: JSONValue =
+            serde_json::from_slice(&body::to_bytes(response.into_body()).await.unwrap()).unwrap();
+        assert_eq!(data["data"]["admin"], "United States of America");
+
+        // Open ocean has no feature
+        let response = setup(
+            "http://localhost",
+            "/services/geography-class-png/grid/-150,0,1.json",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 204);
+    }
+
+    #[tokio::test]
+    async fn get_composite_tilejson() {
+        let response = setup(
+            "http://localhost",
+            "/services/world_cities,world_cities",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let data: JSONValue =
+            serde_json::from_slice(&body::to_bytes(response.into_body()).await.unwrap()).unwrap();
+        assert_eq!(
+            data["tiles"][0],
+            "http://localhost/services/world_cities,world_cities/tiles/{z}/{x}/{y}.pbf"
+        );
+        assert_eq!(data["vector_layers"][1]["id"], "world_cities:cities");
+    }
+
+    #[tokio::test]
+    async fn get_composite_tile() {
+        let response = setup(
+            "http://localhost",
+            "/services/world_cities,world_cities/tiles/0/0/0.pbf",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let data =
+            decompress(body::to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap();
+        let tile = <crate::mvt::Tile as prost::Message>::decode(&data[..]).unwrap();
+        let names: Vec<&str> = tile.layers.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, vec!["cities", "world_cities:cities"]);
+
+        let response = setup(
+            "http://localhost",
+            "/services/world_cities,geography-class-png/tiles/0/0/0.pbf",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn get_composite_raster_tile() {
+        let response = setup(
+            "http://localhost",
+            "/services/geography-class-jpg,geography-class-png/tiles/1/0/0.webp?opacity=1,0.5",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            DataFormat::Webp.content_type()
+        );
+        let data = body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(crate::utils::get_data_format(&data), DataFormat::Webp);
+
+        let response = setup(
+            "http://localhost",
+            "/services/geography-class-jpg,geography-class-png/tiles/1/0/0.png?opacity=1",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn get_static_map() {
+        let response = setup(
+            "http://localhost",
+            "/services/geography-class-png/static/-77.03,38.89,1/300x200.jpg",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let data = body::to_bytes(response.into_body()).await.unwrap();
+        let image = decode_image(&data, DataFormat::Jpg).unwrap();
+        assert_eq!(image.dimensions(), (300, 200));
+
+        let response = setup(
+            "http://localhost",
+            "/services/geography-class-png/static/-130,20,-60,55/400x300.png?geojson=%7B%22type%22%3A%22Point%22%2C%22coordinates%22%3A%5B-77.03%2C38.89%5D%7D",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let data = body::to_bytes(response.into_body()).await.unwrap();
+        let image = decode_image(&data, DataFormat::Png).unwrap();
+        assert_eq!(image.dimensions(), (400, 300));
+
+        let response = setup(
+            "http://localhost",
+            "/services/world_cities/static/0,0,1/300x200.png",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 400);
+
+        let response = setup(
+            "http://localhost",
+            "/services/geography-class-png/static/0,0,1/5000x200.png",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 400);
+
+        let response = setup(
+            "http://localhost",
+            &format!(
+                "/services/geography-class-png/static/0,0,1/300x200.png?geojson={}",
+                "%20".repeat(MAX_STATIC_GEOJSON_LENGTH + 1)
+            ),
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn get_wmts_capabilities() {
+        for path in [
+            "/wmts/1.0.0/WMTSCapabilities.xml",
+            "/wmts?SERVICE=WMTS&REQUEST=GetCapabilities",
+            "/wmts?service=wmts&request=getcapabilities",
+        ] {
+            let response = setup("http://localhost", path, None, None, false).await;
+            assert_eq!(response.status(), 200);
+            assert_eq!(
+                response.headers().get(CONTENT_TYPE).unwrap(),
+                "application/xml"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn get_wmts_tile() {
+        let response = setup(
+            "http://localhost",
+            "/wmts/1.0.0/geography-class-png/default/GoogleMapsCompatible/0/0/0.png",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let rest_tile = body::to_bytes(response.into_body()).await.unwrap();
+        assert_ne!(rest_tile, get_blank_image());
+
+        let response = setup(
+            "http://localhost",
+            "/wmts?SERVICE=WMTS&REQUEST=GetTile&VERSION=1.0.0&LAYER=geography-class-png&STYLE=default&TILEMATRIXSET=GoogleMapsCompatible&TILEMATRIX=0&TILEROW=0&TILECOL=0&FORMAT=image%2Fpng",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            body::to_bytes(response.into_body()).await.unwrap(),
+            rest_tile
+        );
+
+        // Rows are counted from the top like XYZ tiles
+        let response = setup(
+            "http://localhost",
+            "/wmts/1.0.0/geography-class-png/default/GoogleMapsCompatible/1/0/1.png",
+            None,
+            None,
+            false,
+        )
+        .await;
+        let wmts_tile = body::to_bytes(response.into_body()).await.unwrap();
+        let response = setup(
+            "http://localhost",
+            "/services/geography-class-png/tiles/1/1/0.png",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(
+            body::to_bytes(response.into_body()).await.unwrap(),
+            wmts_tile
+        );
+
+        let response = setup(
+            "http://localhost",
+            "/wmts?SERVICE=WMTS&REQUEST=GetTile&LAYER=geography-class-png&TILEMATRIXSET=GoogleMapsCompatible&TILEMATRIX=1&TILEROW=2&TILECOL=0&FORMAT=image/png",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn get_tms_documents() {
+        let response = setup("http://localhost", "/tms/1.0.0/", None, None, false).await;
+        assert_eq!(response.status(), 200);
+        let xml = body::to_bytes(response.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&xml)
+            .contains(r#"href="http://localhost/tms/1.0.0/openstreetmap/open-streets-dc""#));
+
+        let response = setup(
+            "http://localhost",
+            "/tms/1.0.0/openstreetmap/open-streets-dc",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let xml = body::to_bytes(response.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&xml).starts_with("<?xml"));
+    }
+
+    #[tokio::test]
+    async fn get_tms_tile() {
+        // TMS rows are not flipped
+        let response = setup(
+            "http://localhost",
+            "/tms/1.0.0/geography-class-png/1/1/0.png",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let tms_tile = body::to_bytes(response.into_body()).await.unwrap();
+        let response = setup(
+            "http://localhost",
+            "/services/geography-class-png/tiles/1/1/1.png",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(
+            body::to_bytes(response.into_body()).await.unwrap(),
+            tms_tile
+        );
+    }
+
+    #[tokio::test]
+    async fn get_tms_tilejson() {
+        let response = setup(
+            "http://localhost",
+            "/services/geography-class-png?scheme=tms",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let data: JSONValue =
+            serde_json::from_slice(&body::to_bytes(response.into_body()).await.unwrap()).unwrap();
+        assert_eq!(data["scheme"], "tms");
+        assert_eq!(
+            data["tiles"][0],
+            "http://localhost/tms/1.0.0/geography-class-png/{z}/{x}/{y}.png?scheme=tms"
+        );
+    }
+
+    #[tokio::test]
+    async fn get_pmtiles_tile() {
+        let tilesets = discover_tilesets(&PathBuf::from("./tiles")).tilesets;
+        let expected = tilesets["world_cities"].get_tile(3, 2, 4).unwrap();
+        let response = setup(
+            "http://localhost",
+            "/services/pmtiles/world_cities/tiles/3/2/3.pbf",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()[CONTENT_ENCODING], "gzip");
+        let data = body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(data, expected);
+
+        let response = setup(
+            "http://localhost",
+            "/services/pmtiles/world_cities",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let data: JSONValue =
+            serde_json::from_slice(&body::to_bytes(response.into_body()).await.unwrap()).unwrap();
+        assert_eq!(
+            data["tiles"][0],
+            "http://localhost/services/pmtiles/world_cities/tiles/{z}/{x}/{y}.pbf"
+        );
+        assert_eq!(data["vector_layers"][0]["id"], "cities");
+    }
+
+    #[tokio::test]
+    async fn get_geopackage_tile() {
+        let tilesets = discover_tilesets(&PathBuf::from("./tiles")).tilesets;
+        let expected = tilesets["geography-class-png"].get_tile(1, 1, 1).unwrap();
+        let response = setup(
+            "http://localhost",
+            "/services/geography-class/geography_class/tiles/1/1/0.png",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let data = body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(data, expected);
+    }
+
+    #[tokio::test]
+    async fn get_tile_directory_tile() {
+        let tilesets = discover_tilesets(&PathBuf::from("./tiles")).tilesets;
+        let expected = tilesets["geography-class-png"].get_tile(1, 1, 1).unwrap();
+        let response = setup(
+            "http://localhost",
+            "/services/geography-class-tms/tiles/1/1/0.png",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let data = body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(data, expected);
+    }
+
+    #[tokio::test]
+    async fn get_cog_tile() {
+        let response = setup(
+            "http://localhost",
+            "/services/europe/tiles/4/8/5.webp",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers().get(CONTENT_TYPE).unwrap(), "image/webp");
+        let data = body::to_bytes(response.into_body()).await.unwrap();
+        let image = decode_image(&data, DataFormat::Webp).unwrap();
+        assert_eq!(image.dimensions(), (256, 256));
+    }
+
+    #[tokio::test]
+    async fn get_geojson_tile() {
+        let response = setup(
+            "http://localhost",
+            "/services/incidents/tiles/1/0/0.pbf",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let data = body::to_bytes(response.into_body()).await.unwrap();
+        let tile = crate::mvt::Tile::decode(&decompress(data.to_vec()).unwrap()[..]).unwrap();
+        assert_eq!(tile.layers[0].name, "incidents");
+        // San Francisco and New York, and Paris in the buffer around the tile
+        assert_eq!(tile.layers[0].features.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn get_tilejson_versions() {
+        let response = setup(
+            "http://localhost",
+            "/services/world_cities",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let data: JSONValue =
+            serde_json::from_slice(&body::to_bytes(response.into_body()).await.unwrap()).unwrap();
+        assert_eq!(data["tilejson"], "3.0.0");
+        assert_eq!(data["vector_layers"][0]["id"], "cities");
+        assert_eq!(data["tilestats"]["layerCount"], 1);
+
+        let response = setup(
+            "http://localhost",
+            "/services/world_cities?tilejson=2.2.0",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let data: JSONValue =
+            serde_json::from_slice(&body::to_bytes(response.into_body()).await.unwrap()).unwrap();
+        assert_eq!(data["tilejson"], "2.2.0");
+        assert_eq!(data.get("vector_layers"), None);
+        assert_eq!(data.get("fillzoom"), None);
+        assert_eq!(data["tilestats"]["layerCount"], 1);
+
+        let response = setup(
+            "http://localhost",
+            "/services/world_cities?tilejson=1.0.0",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn get_vector_tileset_style() {
+        let response = setup(
+            "http://localhost",
+            "/services/world_cities/style.json",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let data: JSONValue =
+            serde_json::from_slice(&body::to_bytes(response.into_body()).await.unwrap()).unwrap();
+        assert_eq!(
+            data["sources"]["world_cities"]["url"],
+            "http://localhost/services/world_cities"
+        );
+        assert_eq!(data["layers"][0]["source-layer"], "cities");
+
+        let response = setup(
+            "http://localhost",
+            "/services/geography-class-png/style.json",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn disable_tms() {
+        let request = Request::builder()
+            .uri("http://localhost/tms/1.0.0/")
+            .body(Body::from(""))
+            .unwrap();
+        let tilesets = discover_tilesets(&PathBuf::from("./tiles")).tilesets;
+        let response = get_service(
+            request,
+            tilesets,
+            vec!["*".to_string()],
+            vec![],
+            false,
+            false,
+            Arc::new(Assets::default()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn get_discovery_report() {
+        let discovery = discover_tilesets(&PathBuf::from("./tiles"));
+        let request = |path: &str| {
+            Request::builder()
+                .uri(format!("http://localhost{path}"))
+                .body(Body::from(""))
+                .unwrap()
+        };
+        let response = get_service(
+            request("/admin/discovery"),
+            discovery.tilesets.clone(),
+            vec!["*".to_string()],
+            vec![],
+            false,
+            false,
+            Arc::new(Assets::default()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), 404);
+
+        let assets = Arc::new(Assets {
+            skipped: Some(discovery.skipped),
+            ..Default::default()
+        });
+        let response = get_service(
+            request("/admin/discovery"),
+            discovery.tilesets,
+            vec!["*".to_string()],
+            vec![],
+            false,
+            false,
+            assets,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+        let body = body::to_bytes(response.into_body()).await.unwrap();
+        let report: JSONValue = serde_json::from_slice(&body).unwrap();
+        assert_eq!(report["tilesets"].as_array().unwrap().len(), 13);
+        assert_eq!(report["skipped"][1]["path"], "./tiles/invalid.mbtiles");
+        assert_eq!(report["skipped"][1]["reason"], "missing_tables");
+    }
+
+    #[tokio::test]
+    async fn get_sprites() {
+        let assets = Arc::new(Assets {
+            sprites: HashMap::from([
+                ("basemap.json".to_string(), b"{}".to_vec()),
+                ("basemap@2x.png".to_string(), b"png".to_vec()),
+            ]),
+            ..Default::default()
+        });
+        for (path, status, content_type) in [
+            ("/sprites/basemap.json", 200, Some("application/json")),
+            ("/sprites/basemap@2x.png", 200, Some("image/png")),
+            ("/sprites/basemap.png", 404, None),
+        ] {
+            let request = Request::builder()
+                .uri(format!("http://localhost{path}"))
+                .body(Body::from(""))
+                .unwrap();
+            let response = get_service(
+                request,
+                HashMap::new(),
+                vec!["*".to_string()],
+                vec![],
+                false,
+                false,
+                assets.clone(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(response.status(), status, "{path}");
+            if let Some(content_type) = content_type {
+                assert_eq!(response.headers()[CONTENT_TYPE], content_type);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn get_styles() {
+        let assets = Arc::new(Assets {
+            styles: HashMap::from([(
+                "basemap".to_string(),
+                json!({
+                    "version": 8,
+                    "sources": {"cities": {"type": "vector", "url": "mbtiles://world_cities"}},
+                    "layers": [],
+                }),
+            )]),
+            ..Default::default()
+        });
+        for (path, status) in [("/styles/basemap.json", 200), ("/styles/missing.json", 404)] {
+            let request = Request::builder()
+                .uri(format!("http://localhost{path}"))
+                .body(Body::from(""))
+                .unwrap();
+            let response = get_service(
+                request,
+                HashMap::new(),
+                vec!["*".to_string()],
+                vec![("Cache-Control".to_string(), "max-age=60".to_string())],
+                false,
+                false,
+                assets.clone(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(response.status(), status, "{path}");
+            if status == 200 {
+                assert_eq!(response.headers()["cache-control"], "max-age=60");
+                let data: JSONValue =
+                    serde_json::from_slice(&body::to_bytes(response.into_body()).await.unwrap())
+                        .unwrap();
+                assert_eq!(
+                    data["sources"]["cities"]["url"],
+                    "http://localhost/services/world_cities"
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn get_fonts() {
+        let dir = TempDir::new("fonts").unwrap();
+        create_dir(dir.path().join("Open Sans Regular")).unwrap();
+        let glyphs = Glyphs {
+            stacks: vec![Fontstack {
+                name: "Open Sans Regular".to_string(),
+                range: "0-255".to_string(),
+                glyphs: vec![Glyph {
+                    id: 65,
+                    ..Default::default()
+                }],
+            }],
+        };
+        write(
+            dir.path().join("Open Sans Regular/0-255.pbf"),
+            glyphs.encode_to_vec(),
+        )
+        .unwrap();
+
+        for (path, status) in [
+            ("/fonts.json", 200),
+            ("/fonts/Open%20Sans%20Regular,Arial/0-255.pbf", 200),
+            ("/fonts/Arial/0-255.pbf", 404),
+            ("/fonts/Open%20Sans%20Regular/0-255.json", 404),
+        ] {
+            let request = Request::builder()
+                .uri(format!("http://localhost{path}"))
+                .body(Body::from(""))
+                .unwrap();
+            let response = get_service(
+                request,
+                HashMap::new(),
+                vec!["*".to_string()],
+                vec![("Cache-Control".to_string(), "max-age=60".to_string())],
+                false,
+                false,
+                Arc::new(Assets {
+                    fonts: Some(dir.path().to_path_buf()),
+                    ..Default::default()
+                }),
+            )
+            .await
+            .unwrap();
+            assert_eq!(response.status(), status, "{path}");
+            if status == 200 {
+                assert_eq!(response.headers()["cache-control"], "max-age=60");
+            }
+            if path == "/fonts.json" {
+                let data: JSONValue =
+                    serde_json::from_slice(&body::to_bytes(response.into_body()).await.unwrap())
+                        .unwrap();
+                assert_eq!(data, json!(["Open Sans Regular"]));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn get_ogcapi_documents() {
+        for path in [
+            "/ogcapi",
+            "/ogcapi/conformance",
+            "/ogcapi/collections",
+            "/ogcapi/collections/openstreetmap%2Fopen-streets-dc",
+            "/ogcapi/collections/world_cities/tiles",
+            "/ogcapi/collections/world_cities/tiles/WebMercatorQuad",
+            "/ogcapi/tileMatrixSets/WebMercatorQuad",
+        ] {
+            let response = setup("http://localhost", path, None, None, false).await;
+            assert_eq!(response.status(), 200, "{path}");
+            assert_eq!(
+                response.headers().get(CONTENT_TYPE).unwrap(),
+                "application/json"
+            );
+        }
+
+        let response = setup(
+            "http://localhost",
+            "/ogcapi/collections?f=html",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.headers().get(CONTENT_TYPE).unwrap(), "text/html");
+        let html = body::to_bytes(response.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&html)
+            .contains("http://localhost/ogcapi/collections/geography-class-png?f=json"));
+
+        let response = setup(
+            "http://localhost",
+            "/ogcapi/collections/missing",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn get_ogcapi_tile() {
+        let response = setup(
+            "http://localhost",
+            "/ogcapi/collections/geography-class-png/tiles/WebMercatorQuad/1/1/0",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let ogc_tile = body::to_bytes(response.into_body()).await.unwrap();
+        let response = setup(
+            "http://localhost",
+            "/services/geography-class-png/tiles/1/0/1.png",
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert_eq!(
+            body::to_bytes(response.into_body()).await.unwrap(),
+            ogc_tile
+        );
+    }
+
     #[tokio::test]
     async fn disable_preview() {
         let response = setup(
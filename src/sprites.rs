@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::{read, read_dir};
+use std::path::Path;
+
+use image::RgbaImage;
+use log::warn;
+use resvg::tiny_skia::{Pixmap, Transform};
+use resvg::usvg::{Options, Tree};
+use serde_json::{json, Map, Value as JSONValue};
+
+use crate::errors::{Error, Result};
+use crate::raster::encode_image;
+use crate::utils::DataFormat;
+
+/// Gap left between icons of a sprite sheet so that they don't bleed into each other
+const ICON_PADDING: u32 = 1;
+
+/// Load the sprite files of the sprites directory, keyed by file name.
+///
+/// `.json` and `.png` files, including their `@2x` variants, are kept as they are. Each
+/// subdirectory of SVG icons is packed into a sprite named after the subdirectory, at 1x and
+/// 2x, unless a sprite with that name already exists. SVG icons found directly in the sprites
+/// directory make up the `sprite` sprite.
+pub fn load_sprites(sprites_dir: &Path) -> HashMap<String, Vec<u8>> {
+    let mut sprites = HashMap::new();
+    let mut icon_sets = Vec::new();
+    let entries = match read_dir(sprites_dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            warn!("{}: {err}", sprites_dir.display());
+            return sprites;
+        }
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if path.is_dir() {
+            icon_sets.push((file_name, get_icons(&path)));
+        } else if matches!(
+            path.extension().and_then(OsStr::to_str),
+            Some("json" | "png")
+        ) {
+            match read(&path) {
+                Ok(data) => {
+                    sprites.insert(file_name, data);
+                }
+                Err(err) => warn!("{}: {err}", path.display()),
+            }
+        }
+    }
+    icon_sets.push((String::from("sprite"), get_icons(sprites_dir)));
+
+    for (name, icons) in icon_sets {
+        if icons.is_empty() || sprites.contains_key(&format!("{name}.json")) {
+            continue;
+        }
+        for (pixel_ratio, suffix) in [(1, ""), (2, "@2x")] {
+            match build_sprite(&icons, pixel_ratio) {
+                Ok((index, image)) => {
+                    sprites.insert(format!("{name}{suffix}.json"), index);
+                    sprites.insert(format!("{name}{suffix}.png"), image);
+                }
+                Err(err) => warn!("Could not build sprite {name}{suffix}: {err}"),
+            }
+        }
+    }
+    sprites
+}
+
+/// Return the name and content of the SVG icons of a directory, sorted by name
+fn get_icons(dir: &Path) -> Vec<(String, Vec<u8>)> {
+    let mut icons: Vec<(String, Vec<u8>)> = match read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().and_then(OsStr::to_str) == Some("svg"))
+            .filter_map(|path| {
+                let name = path.file_stem()?.to_str()?.to_string();
+                match read(&path) {
+                    Ok(data) => Some((name, data)),
+                    Err(err) => {
+                        warn!("{}: {err}", path.display());
+                        None
+                    }
+                }
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    icons.sort();
+    icons
+}
+
+fn rasterize_icon(data: &[u8], pixel_ratio: u32) -> Result<RgbaImage> {
+    let tree = Tree::from_data(data, &Options::default())
+        .map_err(|err| Error::InvalidDataFormat(err.to_string()))?;
+    let size = tree.size();
+    let scale = pixel_ratio as f32;
+    let width = (size.width() * scale).ceil() as u32;
+    let height = (size.height() * scale).ceil() as u32;
+    let mut pixmap = Pixmap::new(width, height)
+        .ok_or_else(|| Error::InvalidDataFormat(format!("Invalid icon size {width}x{height}")))?;
+    resvg::render(
+        &tree,
+        Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+
+    let mut image = RgbaImage::new(width, height);
+    for (pixel, color) in image.pixels_mut().zip(pixmap.pixels()) {
+        let color = color.demultiply();
+        pixel.0 = [color.red(), color.green(), color.blue(), color.alpha()];
+    }
+    Ok(image)
+}
+
+/// Rasterize SVG icons and pack them into a sprite sheet.
+///
+/// Returns the sprite index JSON and the PNG sprite sheet. Icons are laid out in rows, tallest
+/// first, in a sheet about as wide as it is high. Icons that can't be rasterized are skipped.
+pub fn build_sprite(icons: &[(String, Vec<u8>)], pixel_ratio: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+    let mut images: Vec<(&str, RgbaImage)> = icons
+        .iter()
+        .filter_map(|(name, data)| match rasterize_icon(data, pixel_ratio) {
+            Ok(image) => Some((name.as_str(), image)),
+            Err(err) => {
+                warn!("Invalid icon {name}: {err}");
+                None
+            }
+        })
+        .collect();
+    images.sort_by_key(|(_, image)| std::cmp::Reverse(image.height()));
+
+    let area: u32 = images
+        .iter()
+        .map(|(_, image)| (image.width() + ICON_PADDING) * (image.height() + ICON_PADDING))
+        .sum();
+    let max_width = images
+        .iter()
+        .map(|(_, image)| image.width() + ICON_PADDING)
+        .max()
+        .unwrap_or(1);
+    let sheet_width = max_width.max(f64::from(area).sqrt().ceil() as u32);
+
+    let mut positions = Vec::with_capacity(images.len());
+    let (mut x, mut y, mut row_height, mut sheet_height) = (0, 0, 0, 1);
+    for (_, image) in &images {
+        if x + image.width() > sheet_width {
+            x = 0;
+            y += row_height;
+            row_height = 0;
+        }
+        positions.push((x, y));
+        x += image.width() + ICON_PADDING;
+        row_height = row_height.max(image.height() + ICON_PADDING);
+        sheet_height = sheet_height.max(y + image.height());
+    }
+
+    let mut sheet = RgbaImage::new(sheet_width, sheet_height);
+    let mut index = Map::new();
+    for ((name, image), (x, y)) in images.iter().zip(positions) {
+        image::imageops::replace(&mut sheet, image, i64::from(x), i64::from(y));
+        index.insert(
+            name.to_string(),
+            json!({
+                "x": x,
+                "y": y,
+                "width": image.width(),
+                "height": image.height(),
+                "pixelRatio": pixel_ratio,
+            }),
+        );
+    }
+
+    let index = serde_json::to_vec(&JSONValue::Object(index)).unwrap();
+    Ok((index, encode_image(sheet, DataFormat::Png)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir, write};
+
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::raster::decode_image;
+
+    fn square_icon(size: u32, color: &str) -> Vec<u8> {
+        format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{size}" height="{size}"><rect width="{size}" height="{size}" fill="{color}"/></svg>"#
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn build_sprite_sheet() {
+        let icons = vec![
+            ("marker".to_string(), square_icon(10, "red")),
+            ("park".to_string(), square_icon(20, "green")),
+        ];
+        let (index, image) = build_sprite(&icons, 2).unwrap();
+        let index: JSONValue = serde_json::from_slice(&index).unwrap();
+        assert_eq!(
+            index["park"],
+            json!({"x": 0, "y": 0, "width": 40, "height": 40, "pixelRatio": 2})
+        );
+        assert_eq!(index["marker"]["width"], 20);
+
+        let image = decode_image(&image, DataFormat::Png).unwrap();
+        let x = index["marker"]["x"].as_u64().unwrap() as u32;
+        let y = index["marker"]["y"].as_u64().unwrap() as u32;
+        assert_eq!(image.get_pixel(x + 10, y + 10).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(20, 20).0, [0, 128, 0, 255]);
+    }
+
+    #[test]
+    fn load_sprites_directory() {
+        let dir = TempDir::new("sprites").unwrap();
+        write(dir.path().join("basemap.json"), b"{}").unwrap();
+        write(dir.path().join("basemap.png"), b"png").unwrap();
+        create_dir(dir.path().join("basemap")).unwrap();
+        write(dir.path().join("basemap/marker.svg"), square_icon(8, "red")).unwrap();
+        create_dir(dir.path().join("poi")).unwrap();
+        write(dir.path().join("poi/marker.svg"), square_icon(8, "red")).unwrap();
+
+        let sprites = load_sprites(dir.path());
+        let mut names: Vec<&str> = sprites.keys().map(String::as_str).collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                "basemap.json",
+                "basemap.png",
+                "poi.json",
+                "poi.png",
+                "poi@2x.json",
+                "poi@2x.png"
+            ]
+        );
+        assert_eq!(sprites["basemap.json"], b"{}");
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+
+use serde_json::{json, Value as JSONValue};
+use tilejson::{TileJSON, VectorLayer};
+
+/// Return the geometry type of each layer listed in the `tilestats` of a TileJSON
+pub fn get_layer_geometries(tilejson: &TileJSON) -> HashMap<String, String> {
+    let layers = match tilejson
+        .other
+        .get("tilestats")
+        .and_then(|tilestats| tilestats.get("layers"))
+    {
+        Some(JSONValue::Array(layers)) => layers,
+        _ => return HashMap::new(),
+    };
+    layers
+        .iter()
+        .filter_map(|layer| {
+            let name = layer.get("layer")?.as_str()?;
+            let geometry = layer.get("geometry")?.as_str()?;
+            Some((name.to_string(), geometry.to_string()))
+        })
+        .collect()
+}
+
+/// Pick a color for a layer from its name, so that it stays the same across requests
+fn layer_color(layer: &str) -> String {
+    // FNV-1a
+    let hash = layer.bytes().fold(0x811c9dc5_u32, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x01000193)
+    });
+    format!("hsl({}, 70%, 50%)", hash % 360)
+}
+
+fn style_layer(
+    source: &str,
+    layer: &VectorLayer,
+    layer_type: &str,
+    geometry: Option<&str>,
+) -> JSONValue {
+    let color = layer_color(&layer.id);
+    let paint = match layer_type {
+        "fill" => json!({
+            "fill-color": color,
+            "fill-opacity": 0.4,
+            "fill-outline-color": color,
+        }),
+        "line" => json!({
+            "line-color": color,
+            "line-width": 1.5,
+        }),
+        _ => json!({
+            "circle-color": color,
+            "circle-radius": 4,
+            "circle-stroke-color": "#fff",
+            "circle-stroke-width": 1,
+        }),
+    };
+    let mut style_layer = json!({
+        "id": format!("{}-{layer_type}", layer.id),
+        "type": layer_type,
+        "source": source,
+        "source-layer": layer.id,
+        "paint": paint,
+    });
+    if let Some(geometry) = geometry {
+        style_layer["filter"] = json!(["==", ["geometry-type"], geometry]);
+    }
+    if let Some(minzoom) = layer.minzoom {
+        style_layer["minzoom"] = json!(minzoom);
+    }
+    if let Some(maxzoom) = layer.maxzoom {
+        // Style maxzoom is exclusive, unlike the one of vector layers
+        style_layer["maxzoom"] = json!(u16::from(maxzoom) + 1);
+    }
+    style_layer
+}
+
+/// Build a MapLibre GL style drawing every layer of a vector tileset.
+///
+/// Layers are drawn as fills, lines or circles according to their geometry type in
+/// `geometries`. Layers of unknown geometry type get all three, filtered by geometry type.
+pub fn get_style(
+    tile_name: &str,
+    tilejson: &TileJSON,
+    tilejson_url: &str,
+    geometries: &HashMap<String, String>,
+) -> JSONValue {
+    let mut fills = Vec::new();
+    let mut lines = Vec::new();
+    let mut circles = Vec::new();
+    for layer in tilejson.vector_layers.iter().flatten() {
+        match geometries.get(&layer.id).map(String::as_str) {
+            Some("Polygon") => fills.push(style_layer(tile_name, layer, "fill", None)),
+            Some("LineString") => lines.push(style_layer(tile_name, layer, "line", None)),
+            Some("Point") => circles.push(style_layer(tile_name, layer, "circle", None)),
+            _ => {
+                fills.push(style_layer(tile_name, layer, "fill", Some("Polygon")));
+                lines.push(style_layer(tile_name, layer, "line", Some("LineString")));
+                circles.push(style_layer(tile_name, layer, "circle", Some("Point")));
+            }
+        }
+    }
+    let layers: Vec<JSONValue> = fills.into_iter().chain(lines).chain(circles).collect();
+
+    let mut style = json!({
+        "version": 8,
+        "name": tilejson.name.as_deref().unwrap_or(tile_name),
+        "sources": {
+            tile_name: {
+                "type": "vector",
+                "url": tilejson_url,
+            },
+        },
+        "layers": layers,
+    });
+    if let Some(center) = tilejson.center {
+        style["center"] = json!([center.longitude, center.latitude]);
+        style["zoom"] = json!(center.zoom);
+    }
+    style
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::tiles::get_tile_details;
+
+    #[test]
+    fn get_vector_tileset_style() {
+        let tile_meta = get_tile_details(
+            &PathBuf::from("./tiles/world_cities.mbtiles"),
+            "world_cities",
+        )
+        .unwrap();
+        let geometries = get_layer_geometries(&tile_meta.tilejson);
+        assert_eq!(geometries["cities"], "Point");
+
+        let style = get_style(
+            "world_cities",
+            &tile_meta.tilejson,
+            "http://localhost/services/world_cities",
+            &geometries,
+        );
+        assert_eq!(style["version"], 8);
+        assert_eq!(
+            style["sources"]["world_cities"]["url"],
+            "http://localhost/services/world_cities"
+        );
+        let layers = style["layers"].as_array().unwrap();
+        assert_eq!(layers.len(), 1);
+        assert_eq!(layers[0]["type"], "circle");
+        assert_eq!(layers[0]["source-layer"], "cities");
+        assert_eq!(layers[0]["paint"]["circle-color"], layer_color("cities"));
+    }
+
+    #[test]
+    fn get_style_of_unknown_geometry() {
+        let mut tilejson = tilejson::tilejson! { tiles: vec![] };
+        let mut roads = VectorLayer::new("roads".to_string(), HashMap::new());
+        roads.maxzoom = Some(255);
+        tilejson.vector_layers = Some(vec![roads]);
+        let style = get_style("roads", &tilejson, "", &HashMap::new());
+        let types: Vec<&str> = style["layers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|layer| layer["type"].as_str().unwrap())
+            .collect();
+        assert_eq!(types, vec!["fill", "line", "circle"]);
+        assert_eq!(style["layers"][0]["maxzoom"], 256);
+    }
+}
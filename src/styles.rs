@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::{read_dir, read_to_string};
+use std::path::Path;
+
+use log::warn;
+use serde_json::{json, Value as JSONValue};
+
+use crate::tiles::TileMeta;
+
+/// Load the style JSON files of the styles directory, keyed by file name without extension
+pub fn load_styles(styles_dir: &Path) -> HashMap<String, JSONValue> {
+    let mut styles = HashMap::new();
+    let entries = match read_dir(styles_dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            warn!("{}: {err}", styles_dir.display());
+            return styles;
+        }
+    };
+    for path in entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+    {
+        if path.extension().and_then(OsStr::to_str) != Some("json") {
+            continue;
+        }
+        let name = match path.file_stem().and_then(OsStr::to_str) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        let style = read_to_string(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|data| serde_json::from_str(&data).map_err(|err| err.to_string()));
+        match style {
+            Ok(style @ JSONValue::Object(_)) => {
+                styles.insert(name, style);
+            }
+            Ok(_) => warn!("{}: style is not a JSON object", path.display()),
+            Err(err) => warn!("{}: {err}", path.display()),
+        }
+    }
+    styles
+}
+
+/// Return the tileset of an `mbtiles://<tileset>` (or `mbtiles://{tileset}`) source URL
+fn get_source_tileset(url: &str) -> Option<&str> {
+    let tileset = url.strip_prefix("mbtiles://")?;
+    Some(
+        tileset
+            .strip_prefix('{')
+            .and_then(|t| t.strip_suffix('}'))
+            .unwrap_or(tileset),
+    )
+}
+
+fn is_absolute_url(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+/// Return the tilesets referenced by the sources of a style that are not being served
+pub fn get_missing_tilesets(
+    style: &JSONValue,
+    tilesets: &HashMap<String, TileMeta>,
+) -> Vec<String> {
+    let sources = match style.get("sources") {
+        Some(JSONValue::Object(sources)) => sources,
+        _ => return Vec::new(),
+    };
+    sources
+        .values()
+        .filter_map(|source| get_source_tileset(source.get("url")?.as_str()?))
+        .filter(|tileset| !tilesets.contains_key(*tileset))
+        .map(String::from)
+        .collect()
+}
+
+/// Turn a local sprite reference, e.g. `sprites://basemap` or `basemap`, into a sprite URL
+fn rewrite_sprite_url(url: &str, root_url: &str) -> String {
+    if is_absolute_url(url) {
+        return url.to_string();
+    }
+    let name = url.rsplit('/').next().unwrap_or(url);
+    format!("{root_url}/sprites/{name}")
+}
+
+/// Rewrite the references of a style to this server into absolute URLs.
+///
+/// `mbtiles://<tileset>` sources point to the TileJSON of the tileset, while glyphs and
+/// sprites that are not absolute URLs point to the fonts and sprites endpoints.
+pub fn rewrite_style(mut style: JSONValue, root_url: &str) -> JSONValue {
+    if let Some(JSONValue::Object(sources)) = style.get_mut("sources") {
+        for source in sources.values_mut() {
+            let tileset = source
+                .get("url")
+                .and_then(JSONValue::as_str)
+                .and_then(get_source_tileset)
+                .map(String::from);
+            if let Some(tileset) = tileset {
+                source["url"] = json!(format!("{root_url}/services/{tileset}"));
+            }
+        }
+    }
+
+    if let Some(glyphs) = style.get("glyphs").and_then(JSONValue::as_str) {
+        if !is_absolute_url(glyphs) {
+            style["glyphs"] = json!(format!("{root_url}/fonts/{{fontstack}}/{{range}}.pbf"));
+        }
+    }
+
+    match style.get_mut("sprite") {
+        Some(JSONValue::String(sprite)) => *sprite = rewrite_sprite_url(sprite, root_url),
+        Some(JSONValue::Array(sprites)) => {
+            for sprite in sprites {
+                if let Some(JSONValue::String(url)) = sprite.get_mut("url") {
+                    *url = rewrite_sprite_url(url, root_url);
+                }
+            }
+        }
+        _ => (),
+    }
+
+    style
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::discovery::discover_tilesets;
+
+    fn style() -> JSONValue {
+        json!({
+            "version": 8,
+            "sources": {
+                "cities": {"type": "vector", "url": "mbtiles://world_cities"},
+                "streets": {"type": "vector", "url": "mbtiles://{openstreetmap/streets}"},
+                "satellite": {"type": "raster", "url": "https://example.com/satellite.json"},
+            },
+            "glyphs": "{fontstack}/{range}.pbf",
+            "sprite": "sprites://basemap",
+            "layers": [],
+        })
+    }
+
+    #[test]
+    fn rewrite_style_urls() {
+        let style = rewrite_style(style(), "http://localhost:3000");
+        assert_eq!(
+            style["sources"]["cities"]["url"],
+            "http://localhost:3000/services/world_cities"
+        );
+        assert_eq!(
+            style["sources"]["streets"]["url"],
+            "http://localhost:3000/services/openstreetmap/streets"
+        );
+        assert_eq!(
+            style["sources"]["satellite"]["url"],
+            "https://example.com/satellite.json"
+        );
+        assert_eq!(
+            style["glyphs"],
+            "http://localhost:3000/fonts/{fontstack}/{range}.pbf"
+        );
+        assert_eq!(style["sprite"], "http://localhost:3000/sprites/basemap");
+    }
+
+    #[test]
+    fn find_missing_tilesets() {
+        let tilesets = discover_tilesets(&PathBuf::from("./tiles")).tilesets;
+        assert_eq!(
+            get_missing_tilesets(&style(), &tilesets),
+            vec!["openstreetmap/streets"]
+        );
+    }
+}
@@ -0,0 +1,704 @@
+//! Cutting features into vector tiles on the fly, with the simplification and clipping per
+//! zoom level of <https://github.com/mapbox/geojson-vt>
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+use prost::Message;
+use serde_json::Value as JSONValue;
+use tilejson::Bounds;
+
+use crate::mvt::{Feature as TileFeature, GeomType, Layer, Tile, Value};
+use crate::utils::WEB_MERCATOR_EXTENT;
+
+/// Size of a tile in tile coordinates
+pub const EXTENT: u32 = 4096;
+/// Highest zoom level tiles are cut for
+pub const MAX_ZOOM: u32 = 14;
+/// Margin around tiles, in tile coordinates, so that features crossing tile edges are drawn
+/// seamlessly
+const BUFFER: f64 = 64.0;
+/// Simplification tolerance, in tile coordinates
+const TOLERANCE: f64 = 3.0;
+/// Zoom level of the grid features are indexed by
+const INDEX_ZOOM: u32 = 5;
+
+/// Position in the Web Mercator unit square, along with its importance for simplification as
+/// the squared distance it adds to a simplified line
+pub type Point = [f64; 3];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Geometry {
+    Points(Vec<Point>),
+    Lines(Vec<Vec<Point>>),
+    /// Polygons, as their exterior ring followed by their holes
+    Polygons(Vec<Vec<Vec<Point>>>),
+}
+
+#[derive(Clone, Debug)]
+pub struct Feature {
+    pub id: Option<u64>,
+    pub geometry: Geometry,
+    pub properties: Vec<(String, JSONValue)>,
+    /// Bounding box in the Web Mercator unit square
+    bbox: [f64; 4],
+}
+
+/// Project a WGS84 position into the Web Mercator unit square, with its origin at the top-left
+pub fn project(lon: f64, lat: f64) -> Point {
+    let sin = (lat * PI / 180.0).sin();
+    let y = 0.5 - 0.25 * ((1.0 + sin) / (1.0 - sin)).ln() / PI;
+    [lon / 360.0 + 0.5, y.clamp(0.0, 1.0), 0.0]
+}
+
+/// Convert a position of the Web Mercator unit square back to WGS84
+pub fn unproject(x: f64, y: f64) -> (f64, f64) {
+    let lon = (x - 0.5) * 360.0;
+    let lat = (PI * (1.0 - 2.0 * y)).sinh().atan() * 180.0 / PI;
+    (lon, lat)
+}
+
+/// Coordinate reference systems of the sources tiles are cut or rendered from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Crs {
+    /// Longitude and latitude, EPSG:4326
+    Wgs84,
+    /// Web Mercator meters, EPSG:3857
+    WebMercator,
+}
+
+impl Crs {
+    /// Project a position of this reference system into the Web Mercator unit square
+    pub fn project(&self, x: f64, y: f64) -> Point {
+        match self {
+            Crs::Wgs84 => project(x, y),
+            Crs::WebMercator => [
+                x / (2.0 * WEB_MERCATOR_EXTENT) + 0.5,
+                (0.5 - y / (2.0 * WEB_MERCATOR_EXTENT)).clamp(0.0, 1.0),
+                0.0,
+            ],
+        }
+    }
+
+    /// Convert a position of the Web Mercator unit square to this reference system
+    pub fn unproject(&self, x: f64, y: f64) -> (f64, f64) {
+        match self {
+            Crs::Wgs84 => unproject(x, y),
+            Crs::WebMercator => (
+                (x - 0.5) * 2.0 * WEB_MERCATOR_EXTENT,
+                (0.5 - y) * 2.0 * WEB_MERCATOR_EXTENT,
+            ),
+        }
+    }
+
+    /// Convert a position of this reference system to WGS84
+    pub fn lonlat(&self, x: f64, y: f64) -> (f64, f64) {
+        let [x, y, _] = self.project(x, y);
+        unproject(x, y)
+    }
+}
+
+fn squared_segment_distance(point: &Point, a: &Point, b: &Point) -> f64 {
+    let (mut x, mut y) = (a[0], a[1]);
+    let (dx, dy) = (b[0] - x, b[1] - y);
+    if dx != 0.0 || dy != 0.0 {
+        let t = ((point[0] - x) * dx + (point[1] - y) * dy) / (dx * dx + dy * dy);
+        if t > 1.0 {
+            (x, y) = (b[0], b[1]);
+        } else if t > 0.0 {
+            (x, y) = (x + dx * t, y + dy * t);
+        }
+    }
+    (point[0] - x).powi(2) + (point[1] - y).powi(2)
+}
+
+/// Rank the points of a line with Douglas-Peucker, so that simplifying it for any tolerance is
+/// a matter of keeping the points more important than the squared tolerance
+fn rank_points(points: &mut [Point]) {
+    let last = match points.len() {
+        0 => return,
+        length => length - 1,
+    };
+    points[0][2] = 1.0;
+    points[last][2] = 1.0;
+    let mut segments = vec![(0, last)];
+    while let Some((first, last)) = segments.pop() {
+        let mut max_distance = 0.0;
+        let mut index = first;
+        for i in first + 1..last {
+            let distance = squared_segment_distance(&points[i], &points[first], &points[last]);
+            if distance > max_distance {
+                index = i;
+                max_distance = distance;
+            }
+        }
+        if index > first {
+            points[index][2] = max_distance;
+            segments.push((first, index));
+            segments.push((index, last));
+        }
+    }
+}
+
+impl Feature {
+    pub fn new(
+        id: Option<u64>,
+        mut geometry: Geometry,
+        properties: Vec<(String, JSONValue)>,
+    ) -> Self {
+        let mut bbox = [f64::MAX, f64::MAX, f64::MIN, f64::MIN];
+        let mut extend = |points: &[Point]| {
+            for point in points {
+                bbox[0] = bbox[0].min(point[0]);
+                bbox[1] = bbox[1].min(point[1]);
+                bbox[2] = bbox[2].max(point[0]);
+                bbox[3] = bbox[3].max(point[1]);
+            }
+        };
+        match &mut geometry {
+            Geometry::Points(points) => extend(points),
+            Geometry::Lines(lines) => {
+                for line in lines {
+                    rank_points(line);
+                    extend(line);
+                }
+            }
+            Geometry::Polygons(polygons) => {
+                for ring in polygons.iter_mut().flatten() {
+                    rank_points(ring);
+                    extend(ring);
+                }
+            }
+        }
+        Feature {
+            id,
+            geometry,
+            properties,
+            bbox,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bbox[0] > self.bbox[2]
+    }
+
+    fn geometry_type(&self) -> &'static str {
+        match self.geometry {
+            Geometry::Points(_) => "Point",
+            Geometry::Lines(_) => "LineString",
+            Geometry::Polygons(_) => "Polygon",
+        }
+    }
+}
+
+fn intersect(a: &Point, b: &Point, axis: usize, k: f64) -> Point {
+    let t = (k - a[axis]) / (b[axis] - a[axis]);
+    let mut point = [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t, 1.0];
+    point[axis] = k;
+    point
+}
+
+/// Clip a line to `k1 <= point[axis] <= k2`, splitting it where it leaves the band
+fn clip_line(line: &[Point], axis: usize, k1: f64, k2: f64) -> Vec<Vec<Point>> {
+    let mut lines = Vec::new();
+    let mut slice = Vec::new();
+    for segment in line.windows(2) {
+        let (a, b) = (&segment[0], &segment[1]);
+        let (ak, bk) = (a[axis], b[axis]);
+        let mut exited = false;
+        if ak < k1 {
+            if bk > k1 {
+                slice.push(intersect(a, b, axis, k1));
+            }
+            if bk > k2 {
+                slice.push(intersect(a, b, axis, k2));
+                exited = true;
+            }
+        } else if ak > k2 {
+            if bk < k2 {
+                slice.push(intersect(a, b, axis, k2));
+            }
+            if bk < k1 {
+                slice.push(intersect(a, b, axis, k1));
+                exited = true;
+            }
+        } else {
+            slice.push(*a);
+            if bk < k1 {
+                slice.push(intersect(a, b, axis, k1));
+                exited = true;
+            } else if bk > k2 {
+                slice.push(intersect(a, b, axis, k2));
+                exited = true;
+            }
+        }
+        if exited {
+            lines.push(std::mem::take(&mut slice));
+        }
+    }
+    if let Some(last) = line.last() {
+        if (k1..=k2).contains(&last[axis]) {
+            slice.push(*last);
+        }
+    }
+    lines.push(slice);
+    lines.retain(|line| line.len() > 1);
+    lines
+}
+
+/// Clip a closed ring to `k1 <= point[axis] <= k2`, following the band edges where the ring
+/// leaves it
+fn clip_ring(ring: &[Point], axis: usize, k1: f64, k2: f64) -> Vec<Point> {
+    let mut clipped = ring.to_vec();
+    for (k, keep_above) in [(k1, true), (k2, false)] {
+        let inside = |point: &Point| (point[axis] >= k) == keep_above || point[axis] == k;
+        let input = std::mem::take(&mut clipped);
+        for segment in input.windows(2) {
+            let (a, b) = (&segment[0], &segment[1]);
+            match (inside(a), inside(b)) {
+                (true, true) => clipped.push(*a),
+                (true, false) => {
+                    clipped.push(*a);
+                    clipped.push(intersect(a, b, axis, k));
+                }
+                (false, true) => clipped.push(intersect(a, b, axis, k)),
+                (false, false) => (),
+            }
+        }
+        if let Some(first) = clipped.first().copied() {
+            clipped.push(first);
+        }
+    }
+    if clipped.len() < 4 {
+        clipped.clear();
+    }
+    clipped
+}
+
+/// Clip a geometry to the `[min_x, min_y, max_x, max_y]` box, returning `None` when nothing
+/// is left
+fn clip_geometry(geometry: &Geometry, bbox: [f64; 4]) -> Option<Geometry> {
+    let geometry = match geometry {
+        Geometry::Points(points) => Geometry::Points(
+            points
+                .iter()
+                .filter(|point| {
+                    (bbox[0]..=bbox[2]).contains(&point[0])
+                        && (bbox[1]..=bbox[3]).contains(&point[1])
+                })
+                .copied()
+                .collect(),
+        ),
+        Geometry::Lines(lines) => Geometry::Lines(
+            lines
+                .iter()
+                .flat_map(|line| clip_line(line, 0, bbox[0], bbox[2]))
+                .flat_map(|line| clip_line(&line, 1, bbox[1], bbox[3]))
+                .collect(),
+        ),
+        Geometry::Polygons(polygons) => Geometry::Polygons(
+            polygons
+                .iter()
+                .filter_map(|rings| {
+                    let mut clipped = rings.iter().map(|ring| {
+                        clip_ring(&clip_ring(ring, 0, bbox[0], bbox[2]), 1, bbox[1], bbox[3])
+                    });
+                    // A polygon whose exterior ring was clipped away is gone with its holes
+                    let exterior = clipped.next().filter(|ring| !ring.is_empty())?;
+                    Some(
+                        std::iter::once(exterior)
+                            .chain(clipped.filter(|ring| !ring.is_empty()))
+                            .collect(),
+                    )
+                })
+                .collect(),
+        ),
+    };
+    match &geometry {
+        Geometry::Points(points) if points.is_empty() => None,
+        Geometry::Lines(lines) if lines.is_empty() => None,
+        Geometry::Polygons(polygons) if polygons.is_empty() => None,
+        _ => Some(geometry),
+    }
+}
+
+/// Bounding box of the tile at the given XYZ position in the Web Mercator unit square,
+/// including the buffer around the tile
+pub fn tile_bbox(z: u32, x: u32, y: u32) -> [f64; 4] {
+    let scale = f64::from(1_u32 << z);
+    let buffer = BUFFER / f64::from(EXTENT);
+    [
+        (f64::from(x) - buffer) / scale,
+        (f64::from(y) - buffer) / scale,
+        (f64::from(x) + 1.0 + buffer) / scale,
+        (f64::from(y) + 1.0 + buffer) / scale,
+    ]
+}
+
+fn command(id: u32, count: u32) -> u32 {
+    (id & 0x7) | (count << 3)
+}
+
+fn zigzag(value: i32) -> u32 {
+    ((value << 1) ^ (value >> 31)) as u32
+}
+
+/// Encodes the geometry of a feature as MVT drawing commands, in tile coordinates
+struct GeometryEncoder {
+    commands: Vec<u32>,
+    cursor: (i32, i32),
+}
+
+impl GeometryEncoder {
+    fn push_points(&mut self, id: u32, points: &[(i32, i32)]) {
+        self.commands.push(command(id, points.len() as u32));
+        for &(x, y) in points {
+            self.commands.push(zigzag(x - self.cursor.0));
+            self.commands.push(zigzag(y - self.cursor.1));
+            self.cursor = (x, y);
+        }
+    }
+
+    fn push_line(&mut self, points: &[(i32, i32)]) {
+        self.push_points(1, &points[..1]);
+        self.push_points(2, &points[1..]);
+    }
+}
+
+/// Area of a ring by the surveyor's formula, positive for clockwise rings in tile coordinates
+fn ring_area(ring: &[(i32, i32)]) -> i64 {
+    ring.iter()
+        .zip(ring.iter().cycle().skip(1))
+        .map(|(a, b)| i64::from(a.0) * i64::from(b.1) - i64::from(b.0) * i64::from(a.1))
+        .sum()
+}
+
+/// Encode a clipped geometry in the coordinates of the tile at `(z, x, y)`, keeping the
+/// points more important than the tolerance of the zoom level
+fn encode_geometry(geometry: &Geometry, z: u32, x: u32, y: u32) -> Option<(GeomType, Vec<u32>)> {
+    let scale = f64::from(1_u32 << z);
+    let sq_tolerance = (TOLERANCE / (scale * f64::from(EXTENT))).powi(2);
+    let transform = |points: &[Point], simplify: bool| -> Vec<(i32, i32)> {
+        let mut transformed: Vec<(i32, i32)> = Vec::with_capacity(points.len());
+        for point in points {
+            if simplify && point[2] <= sq_tolerance {
+                continue;
+            }
+            let position = (
+                ((point[0] * scale - f64::from(x)) * f64::from(EXTENT)).round() as i32,
+                ((point[1] * scale - f64::from(y)) * f64::from(EXTENT)).round() as i32,
+            );
+            if transformed.last() != Some(&position) {
+                transformed.push(position);
+            }
+        }
+        transformed
+    };
+
+    let mut encoder = GeometryEncoder {
+        commands: Vec::new(),
+        cursor: (0, 0),
+    };
+    let geom_type = match geometry {
+        Geometry::Points(points) => {
+            encoder.push_points(1, &transform(points, false));
+            GeomType::Point
+        }
+        Geometry::Lines(lines) => {
+            for line in lines {
+                let line = transform(line, true);
+                if line.len() > 1 {
+                    encoder.push_line(&line);
+                }
+            }
+            GeomType::Linestring
+        }
+        Geometry::Polygons(polygons) => {
+            for rings in polygons {
+                for (index, ring) in rings.iter().enumerate() {
+                    let mut ring = transform(ring, true);
+                    // Rings are closed by a command rather than by repeating their first point
+                    if ring.len() > 1 && ring.first() == ring.last() {
+                        ring.pop();
+                    }
+                    let area = ring_area(&ring);
+                    if ring.len() < 3 || area == 0 {
+                        if index == 0 {
+                            break;
+                        }
+                        continue;
+                    }
+                    // Exterior rings are clockwise and holes counter-clockwise
+                    if (area > 0) != (index == 0) {
+                        ring.reverse();
+                    }
+                    encoder.push_line(&ring);
+                    encoder.commands.push(command(7, 1));
+                }
+            }
+            GeomType::Polygon
+        }
+    };
+    (!encoder.commands.is_empty()).then_some((geom_type, encoder.commands))
+}
+
+/// Convert a GeoJSON property into an MVT value, as JSON when it is an object or an array
+fn property_value(value: &JSONValue) -> Option<Value> {
+    let mut tile_value = Value::default();
+    match value {
+        JSONValue::Null => return None,
+        JSONValue::Bool(value) => tile_value.bool_value = Some(*value),
+        JSONValue::String(value) => tile_value.string_value = Some(value.clone()),
+        JSONValue::Number(number) => {
+            if let Some(value) = number.as_u64() {
+                tile_value.uint_value = Some(value);
+            } else if let Some(value) = number.as_i64() {
+                tile_value.sint_value = Some(value);
+            } else {
+                tile_value.double_value = number.as_f64();
+            }
+        }
+        value => tile_value.string_value = Some(value.to_string()),
+    }
+    Some(tile_value)
+}
+
+/// Features indexed by the tiles of a coarse zoom level they overlap
+#[derive(Debug)]
+pub struct TileIndex {
+    features: Vec<Feature>,
+    grid: HashMap<(u32, u32), Vec<usize>>,
+}
+
+impl TileIndex {
+    pub fn new(features: Vec<Feature>) -> Self {
+        let features: Vec<Feature> = features
+            .into_iter()
+            .filter(|feature| !feature.is_empty())
+            .collect();
+        let cells = f64::from(1_u32 << INDEX_ZOOM);
+        let buffer = BUFFER / f64::from(EXTENT) / cells;
+        let max_cell = (1 << INDEX_ZOOM) - 1;
+        let cell = |value: f64| ((value * cells).floor().max(0.0) as u32).min(max_cell);
+        let mut grid: HashMap<(u32, u32), Vec<usize>> = HashMap::new();
+        for (index, feature) in features.iter().enumerate() {
+            let [min_x, min_y, max_x, max_y] = feature.bbox;
+            for x in cell(min_x - buffer)..=cell(max_x + buffer) {
+                for y in cell(min_y - buffer)..=cell(max_y + buffer) {
+                    grid.entry((x, y)).or_default().push(index);
+                }
+            }
+        }
+        TileIndex { features, grid }
+    }
+
+    /// WGS84 bounds of all features
+    pub fn bounds(&self) -> Option<Bounds> {
+        let mut bbox = [f64::MAX, f64::MAX, f64::MIN, f64::MIN];
+        for feature in &self.features {
+            bbox[0] = bbox[0].min(feature.bbox[0]);
+            bbox[1] = bbox[1].min(feature.bbox[1]);
+            bbox[2] = bbox[2].max(feature.bbox[2]);
+            bbox[3] = bbox[3].max(feature.bbox[3]);
+        }
+        if self.features.is_empty() {
+            return None;
+        }
+        let (left, top) = unproject(bbox[0], bbox[1]);
+        let (right, bottom) = unproject(bbox[2], bbox[3]);
+        Some(Bounds::new(left, bottom, right, top))
+    }
+
+    /// Property names and their type, as listed in the `fields` of a TileJSON vector layer
+    pub fn fields(&self) -> HashMap<String, String> {
+        let mut fields = HashMap::new();
+        for (name, value) in self.features.iter().flat_map(|feature| &feature.properties) {
+            let field_type = match value {
+                JSONValue::Null => continue,
+                JSONValue::Bool(_) => "Boolean",
+                JSONValue::Number(_) => "Number",
+                _ => "String",
+            };
+            fields
+                .entry(name.clone())
+                .and_modify(|existing: &mut String| {
+                    if existing != field_type {
+                        *existing = "Mixed".to_string();
+                    }
+                })
+                .or_insert_with(|| field_type.to_string());
+        }
+        fields
+    }
+
+    /// Geometry type shared by all features, as named in tilestats
+    pub fn geometry_type(&self) -> Option<&'static str> {
+        let mut types = self.features.iter().map(Feature::geometry_type);
+        let first = types.next()?;
+        types
+            .all(|geometry_type| geometry_type == first)
+            .then_some(first)
+    }
+
+    pub fn feature_count(&self) -> usize {
+        self.features.len()
+    }
+
+    /// Cut the tile at the given XYZ position, as an uncompressed vector tile with a single
+    /// layer. Returns `None` when no feature crosses the tile.
+    pub fn get_tile(&self, layer_name: &str, z: u32, x: u32, y: u32) -> Option<Vec<u8>> {
+        if z > MAX_ZOOM || x >= 1 << z || y >= 1 << z {
+            return None;
+        }
+        let bbox = tile_bbox(z, x, y);
+        let candidates: Box<dyn Iterator<Item = &Feature>> = if z >= INDEX_ZOOM {
+            let shift = z - INDEX_ZOOM;
+            Box::new(
+                self.grid
+                    .get(&(x >> shift, y >> shift))
+                    .into_iter()
+                    .flatten()
+                    .map(|index| &self.features[*index]),
+            )
+        } else {
+            Box::new(self.features.iter())
+        };
+
+        let mut layer = Layer {
+            version: 2,
+            name: layer_name.to_string(),
+            extent: Some(EXTENT),
+            ..Default::default()
+        };
+        let mut keys: HashMap<&str, u32> = HashMap::new();
+        let mut values: HashMap<String, u32> = HashMap::new();
+        for feature in candidates {
+            let [min_x, min_y, max_x, max_y] = feature.bbox;
+            if min_x > bbox[2] || max_x < bbox[0] || min_y > bbox[3] || max_y < bbox[1] {
+                continue;
+            }
+            let geometry = match clip_geometry(&feature.geometry, bbox) {
+                Some(geometry) => geometry,
+                None => continue,
+            };
+            let (geom_type, commands) = match encode_geometry(&geometry, z, x, y) {
+                Some(encoded) => encoded,
+                None => continue,
+            };
+
+            let mut tags = Vec::new();
+            for (key, value) in &feature.properties {
+                let tile_value = match property_value(value) {
+                    Some(tile_value) => tile_value,
+                    None => continue,
+                };
+                let key_index = *keys.entry(key).or_insert_with(|| {
+                    layer.keys.push(key.clone());
+                    layer.keys.len() as u32 - 1
+                });
+                let value_index = *values.entry(format!("{tile_value:?}")).or_insert_with(|| {
+                    layer.values.push(tile_value);
+                    layer.values.len() as u32 - 1
+                });
+                tags.extend([key_index, value_index]);
+            }
+            layer.features.push(TileFeature {
+                id: feature.id,
+                tags,
+                r#type: Some(geom_type as i32),
+                geometry: commands,
+            });
+        }
+        if layer.features.is_empty() {
+            return None;
+        }
+        Some(
+            Tile {
+                layers: vec![layer],
+            }
+            .encode_to_vec(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(points: &[(f64, f64)]) -> Vec<Point> {
+        points.iter().map(|&(x, y)| [x, y, 0.0]).collect()
+    }
+
+    #[test]
+    fn clip_lines_and_rings() {
+        let clipped = clip_line(
+            &line(&[(-1.0, 0.5), (2.0, 0.5), (2.0, 1.5), (0.5, 1.5)]),
+            0,
+            0.0,
+            1.0,
+        );
+        assert_eq!(clipped.len(), 2);
+        assert_eq!((clipped[0][0][0], clipped[0][1][0]), (0.0, 1.0));
+        assert_eq!((clipped[1][0][0], clipped[1][1][0]), (1.0, 0.5));
+
+        let square = line(&[
+            (-1.0, -1.0),
+            (2.0, -1.0),
+            (2.0, 2.0),
+            (-1.0, 2.0),
+            (-1.0, -1.0),
+        ]);
+        let clipped = clip_ring(&clip_ring(&square, 0, 0.0, 1.0), 1, 0.0, 1.0);
+        let corners: Vec<(f64, f64)> = clipped.iter().map(|p| (p[0], p[1])).collect();
+        assert_eq!(corners.first(), corners.last());
+        for corner in [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)] {
+            assert!(corners.contains(&corner));
+        }
+    }
+
+    #[test]
+    fn rank_points_for_simplification() {
+        let mut points = line(&[(0.0, 0.0), (0.5, 0.001), (1.0, 0.0), (2.0, 1.0)]);
+        rank_points(&mut points);
+        assert_eq!((points[0][2], points[3][2]), (1.0, 1.0));
+        assert!(points[2][2] > points[1][2]);
+    }
+
+    #[test]
+    fn cut_tiles() {
+        let polygon = [
+            (-10.0, -10.0),
+            (10.0, -10.0),
+            (10.0, 10.0),
+            (-10.0, 10.0),
+            (-10.0, -10.0),
+        ];
+        let index = TileIndex::new(vec![
+            Feature::new(
+                Some(1),
+                Geometry::Points(vec![project(-100.0, 40.0)]),
+                vec![("name".to_string(), JSONValue::from("a"))],
+            ),
+            Feature::new(
+                None,
+                Geometry::Polygons(vec![vec![polygon
+                    .iter()
+                    .map(|&(lon, lat)| project(lon, lat))
+                    .collect()]]),
+                vec![("area".to_string(), JSONValue::from(400))],
+            ),
+        ]);
+        assert_eq!(index.geometry_type(), None);
+        assert_eq!(index.fields()["area"], "Number");
+
+        let tile = Tile::decode(&index.get_tile("layer", 0, 0, 0).unwrap()[..]).unwrap();
+        assert_eq!(tile.layers[0].features.len(), 2);
+        // The polygon is cut at the edges of the tile, with a clockwise exterior ring
+        let tile = Tile::decode(&index.get_tile("layer", 1, 1, 1).unwrap()[..]).unwrap();
+        let feature = &tile.layers[0].features[0];
+        assert_eq!(feature.r#type, Some(GeomType::Polygon as i32));
+        assert_eq!(feature.geometry.last(), Some(&command(7, 1)));
+        assert_eq!(tile.layers[0].keys, vec!["area"]);
+        assert!(index.get_tile("layer", 2, 3, 3).is_none());
+    }
+}
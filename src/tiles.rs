@@ -1,25 +1,46 @@
-use std::collections::HashMap;
-use std::ffi::OsStr;
-use std::fs::read_dir;
+use std::collections::{HashMap, HashSet};
+use std::fs::{read, remove_file, rename, write};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
 
-use log::warn;
-use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::{params, OpenFlags};
+use log::{debug, warn};
+use rusqlite::{params, Connection, OpenFlags};
 use serde::{Deserialize, Serialize};
-use serde_json::Value as JSONValue;
-use tilejson::{tilejson, Bounds, Center, TileJSON};
+use serde_json::{Map, Value as JSONValue};
+use tilejson::{tilejson, Bounds, Center, TileJSON, VectorLayer};
 
 use crate::errors::{Error, Result};
 
+use crate::cog::CogTiles;
+use crate::directory::{parse_metadata, set_tile_extents, TileDirectory, INFERRED_FIELD};
+use crate::fgb::FlatGeobufTiles;
+use crate::geojson::GeoJsonTiles;
+use crate::gpkg::GeoPackageTiles;
+use crate::mvt::prefixed_layer_name;
+use crate::pmtiles::PMTiles;
+use crate::pool::LazyPool;
 use crate::utils::{decode, get_data_format, DataFormat};
+use crate::zip::ZipTiles;
 
-type Connection = r2d2::PooledConnection<SqliteConnectionManager>;
+/// Storage the tiles of a tileset are read from
+#[derive(Clone, Debug)]
+pub enum TileSource {
+    MBTiles(Arc<LazyPool>),
+    PMTiles(Arc<PMTiles>),
+    GeoPackage(GeoPackageTiles),
+    Directory(TileDirectory),
+    Zip(Arc<ZipTiles>),
+    GeoJson(Arc<GeoJsonTiles>),
+    FlatGeobuf(Arc<FlatGeobufTiles>),
+    Cog(Arc<CogTiles>),
+}
 
 #[derive(Clone, Debug)]
 pub struct TileMeta {
-    pub connection_pool: r2d2::Pool<SqliteConnectionManager>,
+    pub source: TileSource,
+    #[allow(dead_code)]
     pub path: PathBuf,
     pub tilejson: TileJSON,
     pub id: String,
@@ -29,6 +50,66 @@ pub struct TileMeta {
     pub json: Option<JSONValue>,
 }
 
+/// Convert a TMS row to an XYZ row and back
+fn flip_y(z: u32, y: u32) -> Result<u32> {
+    match y.checked_add(1) {
+        Some(rows) if z < 32 && rows <= 1 << z => Ok((1 << z) - rows),
+        _ => Err(Error::TileNotFound),
+    }
+}
+
+impl TileMeta {
+    /// Update the TileJSON of tilesets whose metadata is derived from a file that may change
+    /// while it is served
+    pub fn refresh_tilejson(&mut self) {
+        if let TileSource::GeoJson(geojson) = &self.source {
+            geojson.update_tilejson(&mut self.tilejson);
+        }
+    }
+
+    /// Read the tile at the given position, `y` being the TMS row as stored in mbtiles.
+    /// Vector tiles are returned gzipped.
+    pub fn get_tile(&self, z: u32, x: u32, y: u32) -> Result<Vec<u8>> {
+        match &self.source {
+            TileSource::MBTiles(pool) => get_tile_data(&*pool.get()?, z, x, y),
+            TileSource::PMTiles(pmtiles) => pmtiles.get_tile(z, x, flip_y(z, y)?),
+            TileSource::GeoPackage(gpkg) => gpkg.get_tile(z, x, flip_y(z, y)?),
+            TileSource::Directory(dir) => dir.get_tile(z, x, flip_y(z, y)?),
+            TileSource::Zip(zip) => zip.get_tile(z, x, flip_y(z, y)?),
+            TileSource::GeoJson(geojson) => geojson.get_tile(z, x, flip_y(z, y)?),
+            TileSource::FlatGeobuf(fgb) => fgb.get_tile(z, x, flip_y(z, y)?),
+            TileSource::Cog(cog) => cog.get_tile(z, x, flip_y(z, y)?, self.tile_format),
+        }
+    }
+
+    /// Read a raster tile in the given image format, `y` being the TMS row. Tiles rendered on
+    /// request are encoded in that format, while stored tiles are returned as they are.
+    pub fn get_image_tile(
+        &self,
+        z: u32,
+        x: u32,
+        y: u32,
+        data_format: DataFormat,
+    ) -> Result<Vec<u8>> {
+        match &self.source {
+            TileSource::Cog(cog) if data_format.is_image() => {
+                cog.get_tile(z, x, flip_y(z, y)?, data_format)
+            }
+            _ => self.get_tile(z, x, y),
+        }
+    }
+
+    /// Read the UTFGrid at the given position, `y` being the TMS row as stored in mbtiles
+    pub fn get_grid(&self, z: u32, x: u32, y: u32) -> Result<UTFGrid> {
+        match (&self.source, self.grid_format) {
+            (TileSource::MBTiles(pool), Some(grid_format)) => {
+                get_grid_data(&*pool.get()?, grid_format, z, x, y)
+            }
+            _ => Err(Error::TileNotFound),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TileSummaryJSON {
@@ -50,6 +131,30 @@ pub struct UTFGrid {
     pub keys: Vec<String>,
 }
 
+impl UTFGrid {
+    /// Resolve the key at the given pixel offset of a 256x256 tile and return it along
+    /// with its matching data entry. Returns `None` when no feature covers the pixel.
+    pub fn lookup(&self, px: u32, py: u32) -> Option<(&str, Option<&JSONValue>)> {
+        if self.grid.is_empty() || px > 255 || py > 255 {
+            return None;
+        }
+        let resolution = (256 / self.grid.len() as u32).max(1);
+        let row = self.grid.get((py / resolution) as usize)?;
+        let mut code = row.chars().nth((px / resolution) as usize)? as u32;
+        if code >= 93 {
+            code -= 1;
+        }
+        if code >= 35 {
+            code -= 1;
+        }
+        let key = self.keys.get(code.checked_sub(32)? as usize)?;
+        if key.is_empty() {
+            return None;
+        }
+        Some((key.as_str(), self.data.get(key)))
+    }
+}
+
 pub fn get_data_format_via_query(
     tile_name: &str,
     connection: &Connection,
@@ -65,22 +170,29 @@ pub fn get_data_format_via_query(
         Err(err) => return Err(Error::DBConnection(err)),
     };
     let data_format: DataFormat = statement
-        .query_row([], |row| {
-            Ok(get_data_format(&row.get::<_, Vec<u8>>(0).unwrap()))
-        })
+        .query_row([], |row| Ok(get_data_format(&row.get::<_, Vec<u8>>(0)?)))
         .unwrap_or(DataFormat::Unknown);
     Ok(data_format)
 }
 
+/// Read the details of an mbtiles file with a connection closed once done, the pool tiles
+/// are read from being opened on first access
 pub fn get_tile_details(path: &Path, tile_name: &str) -> Result<TileMeta> {
-    let manager = SqliteConnectionManager::file(path).with_flags(OpenFlags::SQLITE_OPEN_READ_ONLY);
-    let connection_pool = match r2d2::Pool::new(manager) {
-        Ok(connection_pool) => connection_pool,
-        Err(err) => return Err(Error::Pool(err)),
-    };
-
-    let connection = connection_pool.get().unwrap();
+    let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(Error::DBConnection)?;
+    let mut metadata = read_tile_details(&connection, path, tile_name, LazyPool::new(path))?;
+    infer_metadata(path, &connection, &mut metadata, CACHED_SCAN_SIZE);
+    Ok(metadata)
+}
 
+/// Read the details of the mbtiles file `connection` is open to, whose tiles are read with the
+/// connections of `pool`
+pub fn read_tile_details(
+    connection: &Connection,
+    path: &Path,
+    tile_name: &str,
+    pool: Arc<LazyPool>,
+) -> Result<TileMeta> {
     // 'tiles', 'metadata' tables or views must be present
     let query = r#"SELECT count(*) FROM sqlite_master WHERE name IN ('tiles', 'metadata')"#;
     let mut statement = match connection.prepare(query) {
@@ -96,7 +208,7 @@ pub fn get_tile_details(path: &Path, tile_name: &str) -> Result<TileMeta> {
         Err(err) => return Err(Error::DBConnection(err)),
     };
 
-    let tile_format = match get_data_format_via_query(tile_name, &connection, "tile") {
+    let tile_format = match get_data_format_via_query(tile_name, connection, "tile") {
         Ok(tile_format) => match tile_format {
             DataFormat::Unknown => return Err(Error::UnknownTileFormat(tile_name.to_string())),
             DataFormat::Gzip => DataFormat::Pbf, // GZIP masks PBF format too
@@ -106,80 +218,390 @@ pub fn get_tile_details(path: &Path, tile_name: &str) -> Result<TileMeta> {
     };
 
     let mut metadata = TileMeta {
-        connection_pool,
+        source: TileSource::MBTiles(pool),
         path: PathBuf::from(path),
         tilejson: tilejson! {
-            tilejson: "2.1.0".to_string(),
+            tilejson: "3.0.0".to_string(),
             tiles: vec!["".to_string()],
         },
         id: tile_name.to_string(),
         tile_format,
-        grid_format: get_grid_info(tile_name, &connection),
+        grid_format: get_grid_info(tile_name, connection),
         layer_type: None,
         json: None,
     };
 
     let mut statement = connection
         .prepare(r#"SELECT name, value FROM metadata WHERE value IS NOT ''"#)
-        .unwrap();
-    let mut metadata_rows = statement.query([]).unwrap();
-
-    while let Some(row) = metadata_rows.next().unwrap() {
-        let label: String = row.get(0).unwrap();
-        let value: String = row.get(1).unwrap();
-        match label.as_ref() {
-            "name" => metadata.tilejson.name = Some(value),
-            "version" => metadata.tilejson.version = Some(value),
-            "bounds" => metadata.tilejson.bounds = Some(Bounds::from_str(value.as_str()).unwrap()),
-            "center" => metadata.tilejson.center = Some(Center::from_str(value.as_str()).unwrap()),
-            "minzoom" => metadata.tilejson.minzoom = Some(value.parse().unwrap()),
-            "maxzoom" => metadata.tilejson.maxzoom = Some(value.parse().unwrap()),
-            "description" => metadata.tilejson.description = Some(value),
-            "attribution" => metadata.tilejson.attribution = Some(value),
-            "type" => metadata.layer_type = Some(value),
-            "legend" => metadata.tilejson.legend = Some(value),
-            "template" => metadata.tilejson.template = Some(value),
-            "json" => metadata.json = Some(serde_json::from_str(&value).unwrap()),
-            _ => (),
-        };
+        .map_err(Error::DBConnection)?;
+    let mut metadata_rows = statement.query([]).map_err(Error::DBConnection)?;
+
+    while let Some(row) = metadata_rows.next().map_err(Error::DBConnection)? {
+        match (row.get::<_, String>(0), row.get::<_, String>(1)) {
+            (Ok(label), Ok(value)) => set_metadata(&mut metadata, &label, value),
+            (Ok(label), Err(_)) => warn!("Invalid {label} in {tile_name}: expected text"),
+            (Err(_), _) => warn!("Invalid metadata name in {tile_name}: expected text"),
+        }
     }
+    promote_json_metadata(&mut metadata);
 
     Ok(metadata)
 }
 
-/// Walk through the given path and its subfolders, find all valid mbtiles and create
-/// and return a map of mbtiles file names to their absolute path
-pub fn discover_tilesets(parent_dir: String, path: &PathBuf) -> HashMap<String, TileMeta> {
-    let mut tiles = HashMap::new();
-    for p in read_dir(path).unwrap() {
-        let p = p.unwrap().path();
-        if p.is_dir() {
-            let dir_name = p.file_stem().unwrap().to_str().unwrap();
-            let mut parent_dir_cloned = parent_dir.clone();
-            parent_dir_cloned.push_str(dir_name);
-            parent_dir_cloned.push('/');
-            tiles.extend(discover_tilesets(parent_dir_cloned, &p));
-        } else if p.extension().and_then(OsStr::to_str) == Some("mbtiles") {
-            let file_name = p.file_stem().and_then(OsStr::to_str).unwrap();
-            let mut parent_dir_cloned = parent_dir.clone();
-            parent_dir_cloned.push_str(file_name);
-            match get_tile_details(&p, file_name) {
-                Ok(tile_meta) => tiles.insert(parent_dir_cloned, tile_meta),
-                Err(err) => {
-                    warn!("{err}");
-                    None
+/// Size of the mbtiles files from which on the extents of their tiles are cached
+const CACHED_SCAN_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Extents of the tiles of an mbtiles file, cached next to it along with the size and
+/// modification time of the file they were scanned from
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct TileExtents {
+    size: u64,
+    modified: u64,
+    minzoom: u32,
+    maxzoom: u32,
+    /// Columns and TMS rows of the corner tiles of the highest zoom level
+    min_column: u32,
+    min_row: u32,
+    max_column: u32,
+    max_row: u32,
+}
+
+/// Write the extents to a temporary file renamed to `cache_path`, so that concurrent readers
+/// never see a partial file
+fn write_extents(cache_path: &Path, extents: &TileExtents) -> std::io::Result<()> {
+    let data = serde_json::to_vec(extents)?;
+    let partial_path = sidecar_path(cache_path, ".partial");
+    write(&partial_path, data)?;
+    rename(&partial_path, cache_path).inspect_err(|_| {
+        remove_file(&partial_path).ok();
+    })
+}
+
+/// Complete missing zoom levels, bounds and center from the `tiles` table. Scanning the tiles of
+/// files of at least `cached_size` bytes takes a while, so their extents are cached in a
+/// `<file>.inferred.json` file.
+fn infer_metadata(path: &Path, connection: &Connection, metadata: &mut TileMeta, cached_size: u64) {
+    let tilejson = &metadata.tilejson;
+    if tilejson.minzoom.is_some()
+        && tilejson.maxzoom.is_some()
+        && tilejson.bounds.is_some()
+        && tilejson.center.is_some()
+    {
+        return;
+    }
+    let (size, modified) = match path.metadata() {
+        Ok(file) => (
+            file.len(),
+            file.modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |modified| modified.as_millis() as u64),
+        ),
+        Err(_) => return,
+    };
+    let cache_path = Some(sidecar_path(path, ".inferred.json")).filter(|_| size >= cached_size);
+    let cached = cache_path
+        .as_ref()
+        .and_then(|cache_path| read(cache_path).ok())
+        .and_then(|data| serde_json::from_slice::<TileExtents>(&data).ok())
+        .filter(|extents| extents.size == size && extents.modified == modified);
+    let extents = match cached {
+        Some(extents) => extents,
+        None => match scan_extents(connection, size, modified) {
+            Ok(Some(extents)) => {
+                if let Some(cache_path) = cache_path {
+                    if let Err(err) = write_extents(&cache_path, &extents) {
+                        debug!("Cannot cache the extents of {}: {err}", metadata.id);
+                    }
+                }
+                extents
+            }
+            Ok(None) => return,
+            Err(err) => {
+                warn!("Cannot infer the metadata of {}: {err}", metadata.id);
+                return;
+            }
+        },
+    };
+    set_tile_extents(
+        metadata,
+        true,
+        extents.minzoom,
+        extents.maxzoom,
+        [
+            (extents.min_column, extents.min_row),
+            (extents.max_column, extents.max_row),
+        ],
+    );
+}
+
+/// Read the zoom levels of the `tiles` table, and the extents of its highest zoom level
+fn scan_extents(connection: &Connection, size: u64, modified: u64) -> Result<Option<TileExtents>> {
+    let zoom_levels: (Option<u32>, Option<u32>) = connection
+        .query_row(
+            r#"SELECT MIN(zoom_level), MAX(zoom_level) FROM tiles WHERE zoom_level BETWEEN 0 AND 31"#,
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .map_err(Error::DBConnection)?;
+    let (minzoom, maxzoom) = match zoom_levels {
+        (Some(minzoom), Some(maxzoom)) => (minzoom, maxzoom),
+        _ => return Ok(None),
+    };
+    connection
+        .query_row(
+            r#"SELECT MIN(tile_column), MIN(tile_row), MAX(tile_column), MAX(tile_row)
+                 FROM tiles
+                WHERE zoom_level = ?1"#,
+            params![maxzoom],
+            |row| {
+                Ok(TileExtents {
+                    size,
+                    modified,
+                    minzoom,
+                    maxzoom,
+                    min_column: row.get(0)?,
+                    min_row: row.get(1)?,
+                    max_column: row.get(2)?,
+                    max_row: row.get(3)?,
+                })
+            },
+        )
+        .map(Some)
+        .map_err(Error::DBConnection)
+}
+
+/// Set the tileset details matching an entry of the mbtiles `metadata` table, ignoring invalid
+/// values with a warning
+pub fn set_metadata(metadata: &mut TileMeta, label: &str, value: String) {
+    match label {
+        "name" => metadata.tilejson.name = Some(value),
+        "version" => metadata.tilejson.version = Some(value),
+        "bounds" => match Bounds::from_str(value.as_str()) {
+            Ok(bounds) => metadata.tilejson.bounds = Some(bounds),
+            Err(_) => warn!("Invalid bounds in {}: {value}", metadata.id),
+        },
+        "center" => match Center::from_str(value.as_str()) {
+            Ok(center) => metadata.tilejson.center = Some(center),
+            Err(_) => warn!("Invalid center in {}: {value}", metadata.id),
+        },
+        "minzoom" => match value.parse() {
+            Ok(minzoom) => metadata.tilejson.minzoom = Some(minzoom),
+            Err(_) => warn!("Invalid minzoom in {}: {value}", metadata.id),
+        },
+        "maxzoom" => match value.parse() {
+            Ok(maxzoom) => metadata.tilejson.maxzoom = Some(maxzoom),
+            Err(_) => warn!("Invalid maxzoom in {}: {value}", metadata.id),
+        },
+        "fillzoom" => match value.parse() {
+            Ok(fillzoom) => metadata.tilejson.fillzoom = Some(fillzoom),
+            Err(_) => warn!("Invalid fillzoom in {}: {value}", metadata.id),
+        },
+        "description" => metadata.tilejson.description = Some(value),
+        "attribution" => metadata.tilejson.attribution = Some(value),
+        "type" => metadata.layer_type = Some(value),
+        "legend" => metadata.tilejson.legend = Some(value),
+        "template" => metadata.tilejson.template = Some(value),
+        "json" => match serde_json::from_str(&value) {
+            Ok(json) => metadata.json = Some(json),
+            Err(err) => warn!("Invalid json in {}: {err}", metadata.id),
+        },
+        _ => (),
+    };
+}
+
+/// Override the TileJSON fields of a tileset, ignoring invalid values with a warning. A `null`
+/// value removes the field.
+pub fn override_tilejson(metadata: &mut TileMeta, overrides: Map<String, JSONValue>) {
+    for (field, value) in overrides {
+        let mut tilejson = match serde_json::to_value(&metadata.tilejson) {
+            Ok(JSONValue::Object(tilejson)) => tilejson,
+            _ => return,
+        };
+        match value {
+            JSONValue::Null => tilejson.remove(&field),
+            value => tilejson.insert(field.clone(), value),
+        };
+        match serde_json::from_value(JSONValue::Object(tilejson)) {
+            Ok(tilejson) => metadata.tilejson = tilejson,
+            Err(err) => {
+                warn!("Invalid {field} in the metadata of {}: {err}", metadata.id);
+                continue;
+            }
+        }
+        // The field is no longer inferred from the tiles
+        remove_inferred(&mut metadata.tilejson, &field);
+        // An inferred center follows the bounds it was computed from
+        let tilejson = &mut metadata.tilejson;
+        if field == "bounds" && is_inferred(tilejson, "center") {
+            match (tilejson.bounds, tilejson.center) {
+                (Some(bounds), Some(center)) => {
+                    tilejson.center = Some(Center::new(
+                        (bounds.left + bounds.right) / 2.0,
+                        (bounds.bottom + bounds.top) / 2.0,
+                        center.zoom,
+                    ));
                 }
-            };
+                _ => {
+                    tilejson.center = None;
+                    remove_inferred(tilejson, "center");
+                }
+            }
         }
     }
-    tiles
+}
+
+fn is_inferred(tilejson: &TileJSON, field: &str) -> bool {
+    match tilejson.other.get(INFERRED_FIELD) {
+        Some(JSONValue::Array(inferred)) => inferred.iter().any(|inferred| inferred == field),
+        _ => false,
+    }
+}
+
+fn remove_inferred(tilejson: &mut TileJSON, field: &str) {
+    let other = &mut tilejson.other;
+    if let Some(JSONValue::Array(inferred)) = other.get_mut(INFERRED_FIELD) {
+        inferred.retain(|inferred_field| inferred_field != field);
+        if inferred.is_empty() {
+            other.remove(INFERRED_FIELD);
+        }
+    }
+}
+
+/// Path of a file next to a tileset file, named after it with a suffix
+pub fn sidecar_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(suffix);
+    PathBuf::from(sidecar)
+}
+
+/// Read the TileJSON fields of the `<file>.json` sidecar file of a tileset file, if any
+pub fn read_sidecar(path: &Path) -> Result<Option<Map<String, JSONValue>>> {
+    let sidecar = sidecar_path(path, ".json");
+    if !sidecar.is_file() {
+        return Ok(None);
+    }
+    let data = read(&sidecar).map_err(Error::IO)?;
+    parse_metadata(&data, &sidecar.display().to_string()).map(Some)
+}
+
+/// Move `vector_layers` and `tilestats` out of the `json` metadata into TileJSON
+pub fn promote_json_metadata(metadata: &mut TileMeta) {
+    if let Some(JSONValue::Object(json)) = metadata.json.as_mut() {
+        if let Some(layers) = json.remove("vector_layers") {
+            metadata.tilejson.vector_layers = Some(parse_vector_layers(&metadata.id, layers));
+        }
+        if let Some(tilestats) = json.remove("tilestats") {
+            metadata
+                .tilejson
+                .other
+                .insert("tilestats".to_string(), tilestats);
+        }
+    }
+    if metadata.tile_format == DataFormat::Pbf && metadata.tilejson.vector_layers.is_none() {
+        warn!("No vector_layers found in the metadata of {}", metadata.id);
+    }
+}
+
+/// Return the valid entries of a `vector_layers` metadata value
+pub fn parse_vector_layers(tile_name: &str, layers: JSONValue) -> Vec<VectorLayer> {
+    let layers = match layers {
+        JSONValue::Array(layers) => layers,
+        _ => {
+            warn!("Invalid vector_layers in {tile_name}: expected an array");
+            return Vec::new();
+        }
+    };
+    layers
+        .into_iter()
+        .filter_map(|layer| match serde_json::from_value(layer) {
+            Ok(layer) => Some(layer),
+            Err(err) => {
+                warn!("Invalid vector layer in {tile_name}: {err}");
+                None
+            }
+        })
+        .collect()
+}
+
+/// Build the TileJSON of tilesets served together as a single source.
+///
+/// Tilesets are given in drawing order. Alongside the TileJSON, returns for each tileset
+/// the names of its layers that collide with layers of earlier tilesets; these are
+/// renamed with [`prefixed_layer_name`] both in `vector_layers` and in the merged tiles.
+pub fn get_composite_details(members: &[(&str, &TileMeta)]) -> (TileJSON, Vec<HashSet<String>>) {
+    let mut tilejson = tilejson! {
+        tilejson: "3.0.0".to_string(),
+        tiles: vec!["".to_string()],
+    };
+    let mut names = HashSet::new();
+    let mut collisions = Vec::new();
+    let mut vector_layers = Vec::new();
+    let mut attributions: Vec<String> = Vec::new();
+
+    for (tile_name, tile_meta) in members {
+        let mut tileset_collisions = HashSet::new();
+        let layers = tile_meta.tilejson.vector_layers.iter().flatten().cloned();
+        for mut layer in layers {
+            if names.contains(&layer.id) {
+                tileset_collisions.insert(layer.id.clone());
+                layer.id = prefixed_layer_name(tile_name, &layer.id);
+            }
+            names.insert(layer.id.clone());
+            vector_layers.push(layer);
+        }
+        collisions.push(tileset_collisions);
+
+        let details = &tile_meta.tilejson;
+        if let Some(attribution) = &details.attribution {
+            if !attributions.contains(attribution) {
+                attributions.push(attribution.clone());
+            }
+        }
+        if let Some(bounds) = details.bounds {
+            tilejson.bounds = Some(match tilejson.bounds {
+                Some(b) => Bounds::new(
+                    b.left.min(bounds.left),
+                    b.bottom.min(bounds.bottom),
+                    b.right.max(bounds.right),
+                    b.top.max(bounds.top),
+                ),
+                None => bounds,
+            });
+        }
+        if let Some(minzoom) = details.minzoom {
+            tilejson.minzoom = Some(tilejson.minzoom.map_or(minzoom, |z| z.min(minzoom)));
+        }
+        if let Some(maxzoom) = details.maxzoom {
+            tilejson.maxzoom = Some(tilejson.maxzoom.map_or(maxzoom, |z| z.max(maxzoom)));
+        }
+        if tilejson.center.is_none() {
+            tilejson.center = details.center;
+        }
+    }
+
+    let names: Vec<&str> = members
+        .iter()
+        .map(|(tile_name, tile_meta)| tile_meta.tilejson.name.as_deref().unwrap_or(tile_name))
+        .collect();
+    tilejson.name = Some(names.join(", "));
+    if !attributions.is_empty() {
+        tilejson.attribution = Some(attributions.join(" | "));
+    }
+    if members
+        .iter()
+        .all(|(_, tile_meta)| tile_meta.tile_format == DataFormat::Pbf)
+    {
+        tilejson.vector_layers = Some(vector_layers);
+    }
+
+    (tilejson, collisions)
 }
 
 fn get_grid_info(tile_name: &str, connection: &Connection) -> Option<DataFormat> {
-    let mut statement = connection.prepare(r#"SELECT count(*) FROM sqlite_master WHERE name IN ('grids', 'grid_data', 'grid_utfgrid', 'keymap', 'grid_key')"#).unwrap();
-    let count: u8 = statement
-        .query_row([], |row| Ok(row.get(0).unwrap()))
-        .unwrap();
+    let count: u8 = connection
+        .query_row(r#"SELECT count(*) FROM sqlite_master WHERE name IN ('grids', 'grid_data', 'grid_utfgrid', 'keymap', 'grid_key')"#, [], |row| row.get(0))
+        .unwrap_or(0);
     if count == 5 {
         return match get_data_format_via_query(tile_name, connection, "grid") {
             Ok(grid_format) => Some(grid_format),
@@ -192,7 +614,7 @@ fn get_grid_info(tile_name: &str, connection: &Connection) -> Option<DataFormat>
     None
 }
 
-pub fn get_grid_data(
+fn get_grid_data(
     connection: &Connection,
     data_format: DataFormat,
     z: u32,
@@ -250,7 +672,7 @@ pub fn get_grid_data(
     Ok(grid_data)
 }
 
-pub fn get_tile_data(connection: &Connection, z: u32, x: u32, y: u32) -> Result<Vec<u8>> {
+fn get_tile_data(connection: &Connection, z: u32, x: u32, y: u32) -> Result<Vec<u8>> {
     let mut statement = connection
         .prepare(
             r#"SELECT tile_data
@@ -270,15 +692,46 @@ pub fn get_tile_data(connection: &Connection, z: u32, x: u32, y: u32) -> Result<
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs::{copy, write};
+    use tempdir::TempDir;
 
     #[test]
-    fn get_list_of_valid_tilesets() {
-        let tilesets = discover_tilesets(String::new(), &PathBuf::from("./tiles"));
-        // 2 out of 7 tilesets in ./tiles directory are invalid
-        assert_eq!(tilesets.len(), 5);
+    fn lookup_utfgrid_pixel() {
+        let mut grid = UTFGrid {
+            data: HashMap::new(),
+            grid: vec![" !".to_string(), " !".to_string()],
+            keys: vec!["".to_string(), "1".to_string()],
+        };
+        grid.data
+            .insert("1".to_string(), serde_json::json!({"name": "one"}));
+        assert_eq!(grid.lookup(0, 0), None);
+        assert_eq!(grid.lookup(255, 255).unwrap().0, "1");
+        assert_eq!(
+            grid.lookup(200, 10).unwrap().1,
+            Some(&serde_json::json!({"name": "one"}))
+        );
+    }
 
-        assert!(!tilesets.contains_key("invalid"));
-        assert!(!tilesets.contains_key("invalid-tile-format"));
+    #[test]
+    fn get_composite_tileset_metadata() {
+        let cities = get_tile_details(
+            &PathBuf::from("./tiles/world_cities.mbtiles"),
+            "world_cities",
+        )
+        .unwrap();
+        let (tilejson, collisions) =
+            get_composite_details(&[("world_cities", &cities), ("copy", &cities)]);
+        let layer_ids: Vec<String> = tilejson
+            .vector_layers
+            .unwrap()
+            .into_iter()
+            .map(|layer| layer.id)
+            .collect();
+        assert_eq!(layer_ids, vec!["cities", "copy:cities"]);
+        assert!(collisions[0].is_empty());
+        assert!(collisions[1].contains("cities"));
+        assert_eq!(tilejson.minzoom, Some(0));
+        assert_eq!(tilejson.maxzoom, Some(6));
     }
 
     #[test]
@@ -302,6 +755,11 @@ mod tests {
             Center::new(0.0, 20.0, 0)
         );
         assert_eq!(tileset_details.tile_format, DataFormat::Png);
+        // Connections are only opened to read tiles
+        match &tileset_details.source {
+            TileSource::MBTiles(pool) => assert!(!pool.is_open()),
+            _ => panic!("not an mbtiles source"),
+        }
 
         let tileset_details = get_tile_details(
             &PathBuf::from("./tiles/world_cities.mbtiles"),
@@ -325,5 +783,173 @@ mod tests {
             Center::new(-75.937500, 38.788894, 6)
         );
         assert_eq!(tileset_details.tile_format, DataFormat::Pbf);
+        assert_eq!(tileset_details.tilejson.tilejson, "3.0.0");
+        let vector_layers = tileset_details.tilejson.vector_layers.unwrap();
+        assert_eq!(vector_layers.len(), 1);
+        assert_eq!(vector_layers[0].id, "cities");
+        assert!(tileset_details.tilejson.other.contains_key("tilestats"));
+        let json = tileset_details.json.unwrap();
+        assert!(json.get("vector_layers").is_none());
+    }
+
+    #[test]
+    fn skip_invalid_metadata() {
+        let dir = TempDir::new("tiles").unwrap();
+        let path = dir.path().join("cities.mbtiles");
+        copy("./tiles/world_cities.mbtiles", &path).unwrap();
+        let connection = Connection::open(&path).unwrap();
+        for (name, value) in [
+            ("bounds", "-180,-85"),
+            ("center", "west"),
+            ("minzoom", "-1"),
+            ("json", "{"),
+        ] {
+            connection
+                .execute(
+                    "UPDATE metadata SET value = ?2 WHERE name = ?1",
+                    params![name, value],
+                )
+                .unwrap();
+        }
+        connection
+            .execute(
+                "INSERT INTO metadata (name, value) VALUES ('attribution', X'01')",
+                [],
+            )
+            .unwrap();
+
+        let tileset_details = get_tile_details(&path, "cities").unwrap();
+        // The invalid values are ignored, then inferred from the tiles
+        assert_eq!(
+            tileset_details.tilejson.other[INFERRED_FIELD],
+            serde_json::json!(["minzoom", "bounds", "center"])
+        );
+        assert_eq!(tileset_details.tilejson.minzoom, Some(0));
+        assert_eq!(tileset_details.tilejson.maxzoom, Some(6));
+        assert_eq!(tileset_details.tilejson.attribution, None);
+        assert!(tileset_details.json.is_none());
+    }
+
+    #[test]
+    fn override_metadata_with_sidecar() {
+        let dir = TempDir::new("tiles").unwrap();
+        let path = dir.path().join("cities.mbtiles");
+        copy("./tiles/world_cities.mbtiles", &path).unwrap();
+        assert!(read_sidecar(&path).unwrap().is_none());
+        write(
+            dir.path().join("cities.mbtiles.json"),
+            serde_json::to_vec(&serde_json::json!({
+                "name": "Cities",
+                "attribution": "Natural Earth",
+                "bounds": [-10, -10, 10, 10],
+                "minzoom": "zero",
+                "version": null,
+                "format": null,
+                "vector_layers": [{"id": "places", "fields": {"name": "String"}}],
+            }))
+            .unwrap(),
+        )
+        .unwrap();
+
+        let mut tileset_details = get_tile_details(&path, "cities").unwrap();
+        override_tilejson(&mut tileset_details, read_sidecar(&path).unwrap().unwrap());
+        let tilejson = tileset_details.tilejson;
+        assert_eq!(tilejson.name.unwrap(), "Cities");
+        assert_eq!(tilejson.attribution.unwrap(), "Natural Earth");
+        assert_eq!(
+            tilejson.bounds.unwrap(),
+            Bounds::new(-10.0, -10.0, 10.0, 10.0)
+        );
+        assert_eq!(tilejson.minzoom, Some(0));
+        assert_eq!(tilejson.version, None);
+        assert!(!tilejson.other.contains_key("format"));
+        assert_eq!(tilejson.vector_layers.unwrap()[0].id, "places");
+    }
+
+    #[test]
+    fn infer_missing_metadata() {
+        let path = PathBuf::from("./tiles/geography-class-png-no-bounds.mbtiles");
+        let tileset_details = get_tile_details(&path, "geography-class-png-no-bounds").unwrap();
+        let tilejson = tileset_details.tilejson;
+        assert_eq!(tilejson.minzoom, Some(0));
+        assert_eq!(tilejson.maxzoom, Some(1));
+        let bounds = tilejson.bounds.unwrap();
+        assert_eq!((bounds.left, bounds.right), (-180.0, 180.0));
+        assert!((bounds.top - 85.0511).abs() < 1e-4);
+        assert_eq!(tilejson.center.unwrap(), Center::new(0.0, 0.0, 0));
+        assert_eq!(
+            tilejson.other[INFERRED_FIELD],
+            serde_json::json!(["bounds", "center"])
+        );
+        // Small files are scanned again rather than cached
+        assert!(!sidecar_path(&path, ".inferred.json").exists());
+
+        let mut tileset_details = get_tile_details(
+            &PathBuf::from("./tiles/geography-class-png.mbtiles"),
+            "geography-class-png",
+        )
+        .unwrap();
+        assert!(!tileset_details.tilejson.other.contains_key(INFERRED_FIELD));
+
+        let overrides = serde_json::json!({"center": [0, 0, 0], "bounds": [-180, -85, 180, 85]});
+        let mut no_bounds = get_tile_details(&path, "geography-class-png-no-bounds").unwrap();
+        override_tilejson(&mut no_bounds, overrides.as_object().unwrap().clone());
+        assert!(!no_bounds.tilejson.other.contains_key(INFERRED_FIELD));
+
+        // The inferred center moves with overridden bounds
+        let overrides = serde_json::json!({"bounds": [0, 0, 10, 20]});
+        let mut no_bounds = get_tile_details(&path, "geography-class-png-no-bounds").unwrap();
+        override_tilejson(&mut no_bounds, overrides.as_object().unwrap().clone());
+        assert_eq!(no_bounds.tilejson.center, Some(Center::new(5.0, 10.0, 0)));
+        assert_eq!(
+            no_bounds.tilejson.other[INFERRED_FIELD],
+            serde_json::json!(["center"])
+        );
+        let overrides = serde_json::json!({"bounds": null});
+        override_tilejson(&mut no_bounds, overrides.as_object().unwrap().clone());
+        assert_eq!(no_bounds.tilejson.center, None);
+        assert!(!no_bounds.tilejson.other.contains_key(INFERRED_FIELD));
+
+        tileset_details.tilejson.bounds = None;
+        tileset_details.tilejson.maxzoom = None;
+        let connection = Connection::open(&path).unwrap();
+        infer_metadata(&path, &connection, &mut tileset_details, u64::MAX);
+        assert_eq!(tileset_details.tilejson.maxzoom, Some(1));
+        assert_eq!(
+            tileset_details.tilejson.other[INFERRED_FIELD],
+            serde_json::json!(["maxzoom", "bounds"])
+        );
+    }
+
+    #[test]
+    fn cache_inferred_metadata() {
+        let dir = TempDir::new("tiles").unwrap();
+        let path = dir.path().join("no-bounds.mbtiles");
+        copy("./tiles/geography-class-png-no-bounds.mbtiles", &path).unwrap();
+        let cache_path = dir.path().join("no-bounds.mbtiles.inferred.json");
+        let mut tileset_details = get_tile_details(&path, "no-bounds").unwrap();
+        let connection = Connection::open(&path).unwrap();
+
+        tileset_details.tilejson.bounds = None;
+        infer_metadata(&path, &connection, &mut tileset_details, 0);
+        let extents: TileExtents = serde_json::from_slice(&read(&cache_path).unwrap()).unwrap();
+        assert!(!sidecar_path(&cache_path, ".partial").exists());
+        assert_eq!((extents.minzoom, extents.maxzoom), (0, 1));
+        assert_eq!((extents.max_column, extents.max_row), (1, 1));
+
+        // The cached extents are used as long as the file is unchanged
+        write(
+            &cache_path,
+            serde_json::to_vec(&TileExtents {
+                max_column: 0,
+                max_row: 0,
+                ..extents
+            })
+            .unwrap(),
+        )
+        .unwrap();
+        tileset_details.tilejson.bounds = None;
+        infer_metadata(&path, &connection, &mut tileset_details, 0);
+        assert_eq!(tileset_details.tilejson.bounds.unwrap().right, 0.0);
     }
 }
@@ -0,0 +1,125 @@
+//! OSGeo Tile Map Service 1.0.0 documents, see
+//! <https://wiki.osgeo.org/wiki/Tile_Map_Service_Specification>
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::tiles::TileMeta;
+use crate::utils::{escape_xml, MAX_LATITUDE, WEB_MERCATOR_EXTENT};
+
+/// Highest zoom level served by the TMS tile route
+pub const MAX_ZOOM: u8 = 30;
+
+/// Web Mercator meters per pixel at zoom 0 with 256px tiles
+const RESOLUTION: f64 = 156_543.033_928_041;
+
+fn lonlat_to_meters(lon: f64, lat: f64) -> (f64, f64) {
+    let lat = lat.clamp(-MAX_LATITUDE, MAX_LATITUDE);
+    let x = lon * WEB_MERCATOR_EXTENT / 180.0;
+    let y = ((90.0 + lat) * std::f64::consts::PI / 360.0).tan().ln() * WEB_MERCATOR_EXTENT
+        / std::f64::consts::PI;
+    (x, y)
+}
+
+/// Build the TileMapService document listing a TileMap per tileset
+pub fn get_tile_map_service(tilesets: &HashMap<String, TileMeta>, root_url: &str) -> String {
+    let mut xml = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8"?><TileMapService version="1.0.0" services="">"#,
+    );
+    xml.push_str("<Title>mbtileserver</Title><Abstract></Abstract><TileMaps>");
+    let mut tile_names: Vec<&String> = tilesets.keys().collect();
+    tile_names.sort();
+    for tile_name in tile_names {
+        let tilejson = &tilesets[tile_name].tilejson;
+        let _ = write!(
+            xml,
+            r#"<TileMap title="{}" srs="EPSG:3857" profile="global-mercator" href="{}/tms/1.0.0/{}"/>"#,
+            escape_xml(tilejson.name.as_deref().unwrap_or(tile_name)),
+            escape_xml(root_url),
+            escape_xml(tile_name)
+        );
+    }
+    xml.push_str("</TileMaps></TileMapService>");
+    xml
+}
+
+/// Build the TileMap document of a tileset with one TileSet per zoom level
+pub fn get_tile_map(tile_name: &str, tile_meta: &TileMeta, root_url: &str) -> String {
+    let tilejson = &tile_meta.tilejson;
+    let bounds = tilejson.bounds.unwrap_or_default();
+    let (min_x, min_y) = lonlat_to_meters(bounds.left, bounds.bottom);
+    let (max_x, max_y) = lonlat_to_meters(bounds.right, bounds.top);
+    let url = format!(
+        "{}/tms/1.0.0/{}",
+        escape_xml(root_url),
+        escape_xml(tile_name)
+    );
+
+    let mut xml = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?><TileMap version="1.0.0" tilemapservice="{}/tms/1.0.0/">"#,
+        escape_xml(root_url)
+    );
+    let _ = write!(
+        xml,
+        "<Title>{}</Title><Abstract>{}</Abstract><SRS>EPSG:3857</SRS>",
+        escape_xml(tilejson.name.as_deref().unwrap_or(tile_name)),
+        escape_xml(tilejson.description.as_deref().unwrap_or(""))
+    );
+    let _ = write!(
+        xml,
+        r#"<BoundingBox minx="{min_x}" miny="{min_y}" maxx="{max_x}" maxy="{max_y}"/><Origin x="-{WEB_MERCATOR_EXTENT}" y="-{WEB_MERCATOR_EXTENT}"/>"#
+    );
+    let _ = write!(
+        xml,
+        r#"<TileFormat width="256" height="256" mime-type="{}" extension="{}"/><TileSets profile="global-mercator">"#,
+        tile_meta.tile_format.content_type(),
+        tile_meta.tile_format.format()
+    );
+    let maxzoom = tilejson.maxzoom.unwrap_or(22).min(MAX_ZOOM);
+    for z in tilejson.minzoom.unwrap_or(0)..=maxzoom {
+        let _ = write!(
+            xml,
+            r#"<TileSet href="{url}/{z}" units-per-pixel="{}" order="{z}"/>"#,
+            RESOLUTION / f64::from(z).exp2()
+        );
+    }
+    xml.push_str("</TileSets></TileMap>");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tiles::get_tile_details;
+    use std::path::PathBuf;
+
+    #[test]
+    fn get_tms_tile_map() {
+        let mut tile_meta = get_tile_details(
+            &PathBuf::from("./tiles/geography-class-png.mbtiles"),
+            "geography-class-png",
+        )
+        .unwrap();
+        let xml = get_tile_map("geography-class-png", &tile_meta, "http://localhost");
+        assert!(xml.contains("<Title>Geography Class</Title>"));
+        assert!(xml.contains(r#"mime-type="image/png" extension="png""#));
+        assert!(xml.contains(
+            r#"<TileSet href="http://localhost/tms/1.0.0/geography-class-png/1" units-per-pixel="78271.5169640205" order="1"/>"#
+        ));
+        assert!(!xml.contains(r#"order="2""#));
+
+        tile_meta.tilejson.maxzoom = Some(40);
+        let xml = get_tile_map("geography-class-png", &tile_meta, "http://localhost");
+        assert!(xml.contains(r#"order="30""#));
+        assert!(!xml.contains(r#"order="31""#));
+    }
+
+    #[test]
+    fn meters_from_lonlat() {
+        let (x, y) = lonlat_to_meters(180.0, MAX_LATITUDE);
+        assert!((x - WEB_MERCATOR_EXTENT).abs() < 1e-6);
+        assert!((y - WEB_MERCATOR_EXTENT).abs() < 1e-3);
+        let (x, y) = lonlat_to_meters(0.0, 0.0);
+        assert!(x.abs() < 1e-6 && y.abs() < 1e-6);
+    }
+}
@@ -4,6 +4,8 @@ use flate2::read::{GzDecoder, ZlibDecoder};
 use flate2::write::GzEncoder;
 use flate2::Compression;
 use serde::{Deserialize, Serialize};
+use serde_json::Value as JSONValue;
+use tilejson::Bounds;
 
 use crate::errors::{Error, Result};
 
@@ -47,6 +49,10 @@ impl DataFormat {
         }
     }
 
+    pub fn is_image(&self) -> bool {
+        matches!(*self, DataFormat::Png | DataFormat::Jpg | DataFormat::Webp)
+    }
+
     pub fn content_type(&self) -> &str {
         match *self {
             DataFormat::Png => "image/png",
@@ -79,6 +85,20 @@ pub fn decode(data: Vec<u8>, data_type: DataFormat) -> Result<String> {
     }
 }
 
+/// Decompress gzip or zlib compressed data, returning any other data unchanged
+pub fn decompress(data: Vec<u8>) -> Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    let result = match get_data_format(&data) {
+        DataFormat::Gzip => GzDecoder::new(&data[..]).read_to_end(&mut decompressed),
+        DataFormat::Zlib => ZlibDecoder::new(&data[..]).read_to_end(&mut decompressed),
+        _ => return Ok(data),
+    };
+    match result {
+        Ok(_) => Ok(decompressed),
+        Err(err) => Err(Error::InvalidDataFormat(err.to_string())),
+    }
+}
+
 pub fn encode(data: &[u8]) -> Vec<u8> {
     let mut e = GzEncoder::new(Vec::new(), Compression::default());
     e.write_all(data).unwrap();
@@ -87,11 +107,11 @@ pub fn encode(data: &[u8]) -> Vec<u8> {
 
 pub fn get_data_format(data: &[u8]) -> DataFormat {
     match data {
-        v if &v[0..2] == b"\x1f\x8b" => DataFormat::Gzip,
-        v if &v[0..2] == b"\x78\x9c" => DataFormat::Zlib,
-        v if &v[0..8] == b"\x89\x50\x4E\x47\x0D\x0A\x1A\x0A" => DataFormat::Png,
-        v if &v[0..3] == b"\xFF\xD8\xFF" => DataFormat::Jpg,
-        v if &v[0..4] == b"RIFF" && &v[8..12] == b"WEBP" => DataFormat::Webp,
+        v if v.starts_with(b"\x1f\x8b") => DataFormat::Gzip,
+        v if v.starts_with(b"\x78\x9c") => DataFormat::Zlib,
+        v if v.starts_with(b"\x89\x50\x4E\x47\x0D\x0A\x1A\x0A") => DataFormat::Png,
+        v if v.starts_with(b"\xFF\xD8\xFF") => DataFormat::Jpg,
+        v if v.starts_with(b"RIFF") && v.get(8..12) == Some(&b"WEBP"[..]) => DataFormat::Webp,
         _ => DataFormat::Unknown,
     }
 }
@@ -101,6 +121,251 @@ pub fn get_blank_image() -> Vec<u8> {
     image.to_vec()
 }
 
+/// Return the percent-decoded value of a query string parameter
+pub fn get_query_param(query: Option<&str>, name: &str) -> Option<String> {
+    find_query_param(query, |key| key == name)
+}
+
+/// Return the percent-decoded value of a query string parameter whose name is matched
+/// case-insensitively, as required for OGC key-value pair requests
+pub fn get_query_param_ignore_case(query: Option<&str>, name: &str) -> Option<String> {
+    find_query_param(query, |key| key.eq_ignore_ascii_case(name))
+}
+
+fn find_query_param<F: Fn(&str) -> bool>(query: Option<&str>, matches: F) -> Option<String> {
+    query?.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        if matches(&percent_decode_query(key)) {
+            Some(percent_decode_query(value))
+        } else {
+            None
+        }
+    })
+}
+
+/// Percent-decode a URL path segment, leaving `+` as is
+pub fn percent_decode_path(value: &str) -> String {
+    percent_decode(value, false)
+}
+
+/// Percent-decode a query string key or value, where `+` stands for a space
+pub fn percent_decode_query(value: &str) -> String {
+    percent_decode(value, true)
+}
+
+fn percent_decode(value: &str, plus_as_space: bool) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' if plus_as_space => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+                match u8::from_str_radix(hex, 16) {
+                    Ok(byte) => {
+                        decoded.push(byte);
+                        i += 2;
+                    }
+                    Err(_) => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Latitude limit of the Web Mercator projection
+pub const MAX_LATITUDE: f64 = 85.0511287798066;
+
+/// Project a WGS84 longitude/latitude to Web Mercator pixel coordinates of the whole world
+/// at the given zoom, with 256px tiles and the origin at the top-left corner
+pub fn lonlat_to_pixel(lon: f64, lat: f64, z: f64) -> (f64, f64) {
+    let size = 256.0 * z.exp2();
+    let lat = lat.clamp(-MAX_LATITUDE, MAX_LATITUDE).to_radians();
+    let x = (lon + 180.0) / 360.0;
+    let y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / std::f64::consts::PI) / 2.0;
+    (x * size, y * size)
+}
+
+/// Half the width of the world in Web Mercator meters
+pub const WEB_MERCATOR_EXTENT: f64 = 20037508.342789244;
+
+/// Convert Web Mercator meters to a WGS84 longitude/latitude
+pub fn meters_to_lonlat(x: f64, y: f64) -> (f64, f64) {
+    let lon = x / WEB_MERCATOR_EXTENT * 180.0;
+    let lat = (y / WEB_MERCATOR_EXTENT * std::f64::consts::PI)
+        .sinh()
+        .atan()
+        .to_degrees();
+    (lon, lat)
+}
+
+/// WGS84 longitude/latitude of the top-left corner of an XYZ tile
+pub fn tile_to_lonlat(z: u32, x: u32, y: u32) -> (f64, f64) {
+    let n = f64::from(z).exp2();
+    let lon = f64::from(x) / n * 360.0 - 180.0;
+    let lat = (std::f64::consts::PI * (1.0 - 2.0 * f64::from(y) / n))
+        .sinh()
+        .atan()
+        .to_degrees();
+    (lon, lat)
+}
+
+/// Convert a WGS84 longitude/latitude to the XYZ tile containing it at the given zoom,
+/// along with the pixel offset of the point inside that 256x256 tile
+pub fn lonlat_to_tile(lon: f64, lat: f64, z: u32) -> Option<(u32, u32, u32, u32)> {
+    if !(-180.0..=180.0).contains(&lon) || !(-MAX_LATITUDE..=MAX_LATITUDE).contains(&lat) {
+        return None;
+    }
+    let size = 256u64 << z;
+    let (x, y) = lonlat_to_pixel(lon, lat, f64::from(z));
+    let gx = (x.max(0.0) as u64).min(size - 1);
+    let gy = (y.max(0.0) as u64).min(size - 1);
+    Some((
+        (gx / 256) as u32,
+        (gy / 256) as u32,
+        (gx % 256) as u32,
+        (gy % 256) as u32,
+    ))
+}
+
+/// Range of XYZ tile columns and rows covering the given bounds at zoom `z`
+pub fn get_tile_limits(bounds: &Bounds, z: u32) -> (u32, u32, u32, u32) {
+    let clamp = |lon: f64, lat: f64| {
+        lonlat_to_tile(
+            lon.clamp(-180.0, 180.0),
+            lat.clamp(-MAX_LATITUDE, MAX_LATITUDE),
+            z,
+        )
+    };
+    match (
+        clamp(bounds.left, bounds.top),
+        clamp(bounds.right, bounds.bottom),
+    ) {
+        (Some((min_col, min_row, _, _)), Some((max_col, max_row, _, _))) => {
+            (min_col, min_row, max_col, max_row)
+        }
+        // Bounds that are not numbers cover the whole world
+        _ => {
+            let max = 1u64
+                .checked_shl(z)
+                .map_or(u32::MAX, |size| u32::try_from(size - 1).unwrap_or(u32::MAX));
+            (0, 0, max, max)
+        }
+    }
+}
+
+pub fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn template_value(data: &JSONValue, name: &str) -> String {
+    match data.get(name) {
+        Some(JSONValue::String(s)) => s.clone(),
+        Some(JSONValue::Null) | None => String::new(),
+        Some(v) => v.to_string(),
+    }
+}
+
+fn is_truthy(value: Option<&JSONValue>) -> bool {
+    match value {
+        None | Some(JSONValue::Null) | Some(JSONValue::Bool(false)) => false,
+        Some(JSONValue::String(s)) => !s.is_empty(),
+        Some(JSONValue::Array(a)) => !a.is_empty(),
+        _ => true,
+    }
+}
+
+/// Render a UTFGrid mustache template with the given feature data.
+///
+/// Only the subset of mustache used by TileMill templates is supported: escaped (`{{name}}`)
+/// and raw (`{{{name}}}`, `{{&name}}`) variables, comments and (inverted) sections.
+/// `__location__`, `__teaser__` and `__full__` sections are kept only when they match `mode`.
+pub fn render_template(template: &str, data: &JSONValue, mode: &str) -> String {
+    let mut output = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        rest = &rest[start + 2..];
+        let (tag, raw) = if let Some(stripped) = rest.strip_prefix('{') {
+            match stripped.find("}}}") {
+                Some(end) => {
+                    let tag = &stripped[..end];
+                    rest = &stripped[end + 3..];
+                    (tag, true)
+                }
+                None => break,
+            }
+        } else {
+            match rest.find("}}") {
+                Some(end) => {
+                    let tag = &rest[..end];
+                    rest = &rest[end + 2..];
+                    (tag, false)
+                }
+                None => break,
+            }
+        };
+        let tag = tag.trim();
+        if raw {
+            output.push_str(&template_value(data, tag));
+            continue;
+        }
+        match tag.chars().next() {
+            Some('!') | None => (),
+            Some('&') => output.push_str(&template_value(data, tag[1..].trim())),
+            Some(c @ ('#' | '^')) => {
+                let name = tag[1..].trim();
+                let (inner, remainder) = split_section(rest, name);
+                rest = remainder;
+                let visible = if name.starts_with("__") && name.ends_with("__") {
+                    name.trim_matches('_') == mode
+                } else {
+                    is_truthy(data.get(name))
+                };
+                if visible == (c == '#') {
+                    output.push_str(&render_template(inner, data, mode));
+                }
+            }
+            Some('/') => (),
+            _ => output.push_str(&escape_xml(&template_value(data, tag))),
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+/// Split the text following an opening section tag into the section body and the text
+/// after its matching closing tag
+fn split_section<'a>(text: &'a str, name: &str) -> (&'a str, &'a str) {
+    let open_tags = [format!("{{{{#{name}}}}}"), format!("{{{{^{name}}}}}")];
+    let close_tag = format!("{{{{/{name}}}}}");
+    let mut depth = 0;
+    let mut offset = 0;
+    while let Some(pos) = text[offset..].find("{{") {
+        let pos = offset + pos;
+        let remaining = &text[pos..];
+        if open_tags.iter().any(|t| remaining.starts_with(t.as_str())) {
+            depth += 1;
+        } else if remaining.starts_with(close_tag.as_str()) {
+            if depth == 0 {
+                return (&text[..pos], &text[pos + close_tag.len()..]);
+            }
+            depth -= 1;
+        }
+        offset = pos + 2;
+    }
+    (text, "")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -129,4 +394,78 @@ mod tests {
             DataFormat::Webp
         );
     }
+
+    #[test]
+    fn test_get_query_param() {
+        let query = Some("opacity=1%2C0.5&name=a+b&empty");
+        assert_eq!(get_query_param(query, "opacity").unwrap(), "1,0.5");
+        assert_eq!(get_query_param(query, "name").unwrap(), "a b");
+        assert_eq!(get_query_param(query, "empty").unwrap(), "");
+        assert_eq!(get_query_param(query, "missing"), None);
+        assert_eq!(get_query_param(None, "opacity"), None);
+        assert_eq!(get_query_param(query, "OPACITY"), None);
+        assert_eq!(
+            get_query_param_ignore_case(query, "OPACITY").unwrap(),
+            "1,0.5"
+        );
+    }
+
+    #[test]
+    fn test_lonlat_to_tile() {
+        assert_eq!(lonlat_to_tile(0.0, 0.0, 0), Some((0, 0, 128, 128)));
+        assert_eq!(lonlat_to_tile(-77.03, 38.89, 1), Some((0, 0, 146, 195)));
+        assert_eq!(lonlat_to_tile(180.0, -85.05, 2), Some((3, 3, 255, 255)));
+        assert_eq!(lonlat_to_tile(0.0, 89.0, 0), None);
+    }
+
+    #[test]
+    fn test_meters_to_lonlat() {
+        let (lon, lat) = meters_to_lonlat(WEB_MERCATOR_EXTENT, WEB_MERCATOR_EXTENT);
+        assert!((lon - 180.0).abs() < 1e-9);
+        assert!((lat - MAX_LATITUDE).abs() < 1e-9);
+        assert_eq!(meters_to_lonlat(0.0, 0.0), (0.0, 0.0));
+    }
+
+    #[test]
+    fn test_tile_to_lonlat() {
+        assert_eq!(tile_to_lonlat(0, 0, 0), (-180.0, MAX_LATITUDE));
+        let (lon, lat) = tile_to_lonlat(1, 1, 1);
+        assert_eq!(lon, 0.0);
+        assert!(lat.abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_get_tile_limits() {
+        assert_eq!(get_tile_limits(&Bounds::default(), 2), (0, 0, 3, 3));
+        assert_eq!(
+            get_tile_limits(&Bounds::new(-77.2, 38.8, -76.9, 39.0), 10),
+            (292, 391, 293, 392)
+        );
+        assert_eq!(
+            get_tile_limits(&Bounds::new(f64::NAN, -85.0, 180.0, 85.0), 2),
+            (0, 0, 3, 3)
+        );
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode_path("Open+Sans%20Bold"), "Open+Sans Bold");
+        assert_eq!(percent_decode_query("Open+Sans%20Bold"), "Open Sans Bold");
+        assert_eq!(
+            get_query_param(Some("q=a+b%2Bc&x=1"), "q"),
+            Some("a b+c".to_string())
+        );
+    }
+
+    #[test]
+    fn test_render_template() {
+        let data = serde_json::json!({"admin": "<Angola>", "flag": "AO"});
+        let template = "{{#__location__}}loc{{/__location__}}{{#__teaser__}}<b>{{admin}}</b>{{{flag}}}{{/__teaser__}}{{^missing}}!{{/missing}}";
+        assert_eq!(
+            render_template(template, &data, "teaser"),
+            "<b>&lt;Angola&gt;</b>AO!"
+        );
+        assert_eq!(render_template(template, &data, "location"), "loc!");
+        assert_eq!(render_template(template, &data, "full"), "!");
+    }
 }
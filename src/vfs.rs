@@ -0,0 +1,283 @@
+//! A read-only SQLite VFS reading databases with range reads of the `Location` they are stored
+//! at, so that the details of mbtiles files in a bucket are read without downloading them
+
+use std::collections::HashMap;
+use std::ffi::{c_char, c_int, c_void, CStr};
+use std::ptr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, Once};
+
+use lazy_static::lazy_static;
+use libsqlite3_sys as ffi;
+use log::warn;
+use rusqlite::{Connection, OpenFlags};
+
+use crate::errors::{Error, Result};
+use crate::s3::Location;
+
+const VFS_NAME: &str = "mbtileserver-ranges";
+/// Bytes read at once, spanning several pages of a database
+const BLOCK_SIZE: u64 = 64 * 1024;
+const MAX_PATHNAME: c_int = 1024;
+
+lazy_static! {
+    /// Databases about to be opened, by the name they are opened with
+    static ref PENDING: Mutex<HashMap<String, (Location, u64)>> = Mutex::new(HashMap::new());
+}
+static REGISTER: Once = Once::new();
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Open a read-only connection to the database of `size` bytes stored at `location`
+pub fn open(location: Location, size: u64) -> Result<Connection> {
+    REGISTER.call_once(register);
+    // Unique names keep concurrent connections to the same database apart
+    let name = format!("{location}#{}", NEXT_ID.fetch_add(1, Ordering::Relaxed));
+    PENDING
+        .lock()
+        .unwrap()
+        .insert(name.clone(), (location, size));
+    let connection =
+        Connection::open_with_flags_and_vfs(&name, OpenFlags::SQLITE_OPEN_READ_ONLY, VFS_NAME);
+    PENDING.lock().unwrap().remove(&name);
+    connection.map_err(Error::DBConnection)
+}
+
+struct Database {
+    location: Location,
+    size: u64,
+    blocks: HashMap<u64, Vec<u8>>,
+}
+
+impl Database {
+    fn block(&mut self, index: u64) -> Result<&[u8]> {
+        if !self.blocks.contains_key(&index) {
+            let offset = index * BLOCK_SIZE;
+            let length = BLOCK_SIZE.min(self.size.saturating_sub(offset));
+            let data = self.location.read(offset, length)?;
+            self.blocks.insert(index, data);
+        }
+        Ok(&self.blocks[&index])
+    }
+
+    /// Fill `buffer` from `offset`, returning whether the database was long enough
+    fn read(&mut self, buffer: &mut [u8], offset: u64) -> Result<bool> {
+        let available = self.size.saturating_sub(offset).min(buffer.len() as u64) as usize;
+        let mut position = 0;
+        while position < available {
+            let start = offset + position as u64;
+            let block = self.block(start / BLOCK_SIZE)?;
+            let block_offset = (start % BLOCK_SIZE) as usize;
+            let length = (available - position).min(block.len() - block_offset);
+            buffer[position..position + length]
+                .copy_from_slice(&block[block_offset..block_offset + length]);
+            position += length;
+        }
+        buffer[available..].fill(0);
+        Ok(available == buffer.len())
+    }
+}
+
+/// The file handle SQLite allocates for each open database, `szOsFile` bytes long
+#[repr(C)]
+struct DatabaseFile {
+    base: ffi::sqlite3_file,
+    database: *mut Database,
+}
+
+fn register() {
+    let io_methods = Box::leak(Box::new(ffi::sqlite3_io_methods {
+        iVersion: 1,
+        xClose: Some(close),
+        xRead: Some(read),
+        xWrite: Some(write),
+        xTruncate: Some(truncate),
+        xSync: Some(sync),
+        xFileSize: Some(file_size),
+        xLock: Some(lock),
+        xUnlock: Some(lock),
+        xCheckReservedLock: Some(check_reserved_lock),
+        xFileControl: Some(file_control),
+        xSectorSize: Some(sector_size),
+        xDeviceCharacteristics: Some(device_characteristics),
+    }));
+    let name = Box::leak(format!("{VFS_NAME}\0").into_boxed_str());
+    unsafe {
+        // Randomness, sleep and time are left to the default VFS
+        let default = ffi::sqlite3_vfs_find(ptr::null());
+        let vfs = Box::leak(Box::new(ffi::sqlite3_vfs {
+            iVersion: 1,
+            szOsFile: std::mem::size_of::<DatabaseFile>() as c_int,
+            mxPathname: MAX_PATHNAME,
+            pNext: ptr::null_mut(),
+            zName: name.as_ptr().cast(),
+            pAppData: (io_methods as *mut ffi::sqlite3_io_methods).cast(),
+            xOpen: Some(open_file),
+            xDelete: Some(delete),
+            xAccess: Some(access),
+            xFullPathname: Some(full_pathname),
+            xDlOpen: None,
+            xDlError: None,
+            xDlSym: None,
+            xDlClose: None,
+            xRandomness: (*default).xRandomness,
+            xSleep: (*default).xSleep,
+            xCurrentTime: (*default).xCurrentTime,
+            xGetLastError: (*default).xGetLastError,
+        }));
+        ffi::sqlite3_vfs_register(vfs, 0);
+    }
+}
+
+unsafe extern "C" fn open_file(
+    vfs: *mut ffi::sqlite3_vfs,
+    name: *const c_char,
+    file: *mut ffi::sqlite3_file,
+    flags: c_int,
+    out_flags: *mut c_int,
+) -> c_int {
+    let file = file.cast::<DatabaseFile>();
+    (*file).base.pMethods = ptr::null();
+    if name.is_null() || flags & ffi::SQLITE_OPEN_MAIN_DB == 0 {
+        return ffi::SQLITE_CANTOPEN;
+    }
+    let pending = CStr::from_ptr(name)
+        .to_str()
+        .ok()
+        .and_then(|name| PENDING.lock().unwrap().remove(name));
+    let (location, size) = match pending {
+        Some(pending) => pending,
+        None => return ffi::SQLITE_CANTOPEN,
+    };
+    (*file).database = Box::into_raw(Box::new(Database {
+        location,
+        size,
+        blocks: HashMap::new(),
+    }));
+    (*file).base.pMethods = (*vfs).pAppData.cast();
+    if !out_flags.is_null() {
+        *out_flags = ffi::SQLITE_OPEN_READONLY | ffi::SQLITE_OPEN_MAIN_DB;
+    }
+    ffi::SQLITE_OK
+}
+
+unsafe extern "C" fn delete(_: *mut ffi::sqlite3_vfs, _: *const c_char, _: c_int) -> c_int {
+    ffi::SQLITE_IOERR_DELETE
+}
+
+unsafe extern "C" fn access(
+    _: *mut ffi::sqlite3_vfs,
+    _: *const c_char,
+    _: c_int,
+    result: *mut c_int,
+) -> c_int {
+    // There are no journals next to the databases
+    *result = 0;
+    ffi::SQLITE_OK
+}
+
+unsafe extern "C" fn full_pathname(
+    _: *mut ffi::sqlite3_vfs,
+    name: *const c_char,
+    size: c_int,
+    output: *mut c_char,
+) -> c_int {
+    let name = CStr::from_ptr(name).to_bytes_with_nul();
+    if name.len() > size as usize {
+        return ffi::SQLITE_CANTOPEN;
+    }
+    ptr::copy_nonoverlapping(name.as_ptr().cast(), output, name.len());
+    ffi::SQLITE_OK
+}
+
+unsafe extern "C" fn close(file: *mut ffi::sqlite3_file) -> c_int {
+    let file = file.cast::<DatabaseFile>();
+    drop(Box::from_raw((*file).database));
+    ffi::SQLITE_OK
+}
+
+unsafe extern "C" fn read(
+    file: *mut ffi::sqlite3_file,
+    buffer: *mut c_void,
+    length: c_int,
+    offset: ffi::sqlite3_int64,
+) -> c_int {
+    let database = &mut *(*file.cast::<DatabaseFile>()).database;
+    let buffer = std::slice::from_raw_parts_mut(buffer.cast::<u8>(), length as usize);
+    match database.read(buffer, offset as u64) {
+        Ok(true) => ffi::SQLITE_OK,
+        Ok(false) => ffi::SQLITE_IOERR_SHORT_READ,
+        Err(err) => {
+            warn!("{}: {err}", database.location);
+            ffi::SQLITE_IOERR_READ
+        }
+    }
+}
+
+unsafe extern "C" fn write(
+    _: *mut ffi::sqlite3_file,
+    _: *const c_void,
+    _: c_int,
+    _: ffi::sqlite3_int64,
+) -> c_int {
+    ffi::SQLITE_READONLY
+}
+
+unsafe extern "C" fn truncate(_: *mut ffi::sqlite3_file, _: ffi::sqlite3_int64) -> c_int {
+    ffi::SQLITE_READONLY
+}
+
+unsafe extern "C" fn sync(_: *mut ffi::sqlite3_file, _: c_int) -> c_int {
+    ffi::SQLITE_OK
+}
+
+unsafe extern "C" fn file_size(
+    file: *mut ffi::sqlite3_file,
+    size: *mut ffi::sqlite3_int64,
+) -> c_int {
+    *size = (*(*file.cast::<DatabaseFile>()).database).size as ffi::sqlite3_int64;
+    ffi::SQLITE_OK
+}
+
+unsafe extern "C" fn lock(_: *mut ffi::sqlite3_file, _: c_int) -> c_int {
+    ffi::SQLITE_OK
+}
+
+unsafe extern "C" fn check_reserved_lock(_: *mut ffi::sqlite3_file, result: *mut c_int) -> c_int {
+    *result = 0;
+    ffi::SQLITE_OK
+}
+
+unsafe extern "C" fn file_control(_: *mut ffi::sqlite3_file, _: c_int, _: *mut c_void) -> c_int {
+    ffi::SQLITE_NOTFOUND
+}
+
+unsafe extern "C" fn sector_size(_: *mut ffi::sqlite3_file) -> c_int {
+    0
+}
+
+unsafe extern "C" fn device_characteristics(_: *mut ffi::sqlite3_file) -> c_int {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn read_database_by_ranges() {
+        let path = PathBuf::from("./tiles/world_cities.mbtiles");
+        let size = path.metadata().unwrap().len();
+        let connection = open(Location::File(path), size).unwrap();
+        let name: String = connection
+            .query_row(
+                "SELECT value FROM metadata WHERE name = 'name'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(!name.is_empty());
+        assert!(PENDING.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,152 @@
+//! OGC Web Map Tile Service 1.0.0 capabilities, see <https://www.ogc.org/standards/wmts>
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::tiles::TileMeta;
+use crate::utils::{escape_xml, get_tile_limits, WEB_MERCATOR_EXTENT};
+
+/// Identifier of the only tile matrix set offered, see the OGC WMTS 1.0.0 specification annex E.4
+pub const TILE_MATRIX_SET: &str = "GoogleMapsCompatible";
+
+/// Highest tile matrix of the `GoogleMapsCompatible` tile matrix set
+const MAX_TILE_MATRIX: u8 = 22;
+
+/// Scale denominator of the `GoogleMapsCompatible` tile matrix at zoom 0
+const SCALE_DENOMINATOR: f64 = 559_082_264.028_717_8;
+
+fn write_layer(xml: &mut String, tile_name: &str, tile_meta: &TileMeta, root_url: &str) {
+    let tilejson = &tile_meta.tilejson;
+    let bounds = tilejson.bounds.unwrap_or_default();
+    let minzoom = tilejson.minzoom.unwrap_or(0).min(MAX_TILE_MATRIX);
+    let maxzoom = tilejson
+        .maxzoom
+        .unwrap_or(MAX_TILE_MATRIX)
+        .min(MAX_TILE_MATRIX);
+    let format = tile_meta.tile_format.format();
+    let content_type = tile_meta.tile_format.content_type();
+
+    xml.push_str("<Layer>");
+    let _ = write!(
+        xml,
+        "<ows:Title>{}</ows:Title>",
+        escape_xml(tilejson.name.as_deref().unwrap_or(tile_name))
+    );
+    if let Some(description) = &tilejson.description {
+        let _ = write!(
+            xml,
+            "<ows:Abstract>{}</ows:Abstract>",
+            escape_xml(description)
+        );
+    }
+    let _ = write!(
+        xml,
+        "<ows:WGS84BoundingBox><ows:LowerCorner>{} {}</ows:LowerCorner><ows:UpperCorner>{} {}</ows:UpperCorner></ows:WGS84BoundingBox>",
+        bounds.left, bounds.bottom, bounds.right, bounds.top
+    );
+    let _ = write!(
+        xml,
+        "<ows:Identifier>{}</ows:Identifier>",
+        escape_xml(tile_name)
+    );
+    xml.push_str("<Style isDefault=\"true\"><ows:Identifier>default</ows:Identifier></Style>");
+    let _ = write!(xml, "<Format>{content_type}</Format>");
+    let _ = write!(
+        xml,
+        "<TileMatrixSetLink><TileMatrixSet>{TILE_MATRIX_SET}</TileMatrixSet><TileMatrixSetLimits>"
+    );
+    for z in minzoom..=maxzoom {
+        let (min_col, min_row, max_col, max_row) = get_tile_limits(&bounds, u32::from(z));
+        let _ = write!(
+            xml,
+            "<TileMatrixLimits><TileMatrix>{z}</TileMatrix><MinTileRow>{min_row}</MinTileRow><MaxTileRow>{max_row}</MaxTileRow><MinTileCol>{min_col}</MinTileCol><MaxTileCol>{max_col}</MaxTileCol></TileMatrixLimits>"
+        );
+    }
+    xml.push_str("</TileMatrixSetLimits></TileMatrixSetLink>");
+    let _ = write!(
+        xml,
+        "<ResourceURL format=\"{content_type}\" resourceType=\"tile\" template=\"{}/wmts/1.0.0/{}/{{Style}}/{{TileMatrixSet}}/{{TileMatrix}}/{{TileRow}}/{{TileCol}}.{format}\"/>",
+        escape_xml(root_url),
+        escape_xml(tile_name)
+    );
+    xml.push_str("</Layer>");
+}
+
+fn write_tile_matrix_set(xml: &mut String) {
+    let _ = write!(
+        xml,
+        "<TileMatrixSet><ows:Identifier>{TILE_MATRIX_SET}</ows:Identifier><ows:SupportedCRS>urn:ogc:def:crs:EPSG::3857</ows:SupportedCRS><WellKnownScaleSet>urn:ogc:def:wkss:OGC:1.0:GoogleMapsCompatible</WellKnownScaleSet>"
+    );
+    for z in 0..=MAX_TILE_MATRIX {
+        let size = 1u32 << z;
+        let _ = write!(
+            xml,
+            "<TileMatrix><ows:Identifier>{z}</ows:Identifier><ScaleDenominator>{}</ScaleDenominator><TopLeftCorner>-{WEB_MERCATOR_EXTENT} {WEB_MERCATOR_EXTENT}</TopLeftCorner><TileWidth>256</TileWidth><TileHeight>256</TileHeight><MatrixWidth>{size}</MatrixWidth><MatrixHeight>{size}</MatrixHeight></TileMatrix>",
+            SCALE_DENOMINATOR / f64::from(size)
+        );
+    }
+    xml.push_str("</TileMatrixSet>");
+}
+
+fn write_operation(xml: &mut String, name: &str, root_url: &str) {
+    let root_url = escape_xml(root_url);
+    let _ = write!(
+        xml,
+        "<ows:Operation name=\"{name}\"><ows:DCP><ows:HTTP><ows:Get xlink:href=\"{root_url}/wmts/1.0.0/\"><ows:Constraint name=\"GetEncoding\"><ows:AllowedValues><ows:Value>RESTful</ows:Value></ows:AllowedValues></ows:Constraint></ows:Get><ows:Get xlink:href=\"{root_url}/wmts?\"><ows:Constraint name=\"GetEncoding\"><ows:AllowedValues><ows:Value>KVP</ows:Value></ows:AllowedValues></ows:Constraint></ows:Get></ows:HTTP></ows:DCP></ows:Operation>"
+    );
+}
+
+/// Build the WMTS capabilities document with one layer per tileset
+pub fn get_capabilities(tilesets: &HashMap<String, TileMeta>, root_url: &str) -> String {
+    let mut xml = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8"?><Capabilities xmlns="http://www.opengis.net/wmts/1.0" xmlns:ows="http://www.opengis.net/ows/1.1" xmlns:xlink="http://www.w3.org/1999/xlink" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xmlns:gml="http://www.opengis.net/gml" xsi:schemaLocation="http://www.opengis.net/wmts/1.0 http://schemas.opengis.net/wmts/1.0/wmtsGetCapabilities_response.xsd" version="1.0.0">"#,
+    );
+    xml.push_str("<ows:ServiceIdentification><ows:Title>mbtileserver</ows:Title><ows:ServiceType>OGC WMTS</ows:ServiceType><ows:ServiceTypeVersion>1.0.0</ows:ServiceTypeVersion></ows:ServiceIdentification>");
+    xml.push_str("<ows:OperationsMetadata>");
+    write_operation(&mut xml, "GetCapabilities", root_url);
+    write_operation(&mut xml, "GetTile", root_url);
+    xml.push_str("</ows:OperationsMetadata><Contents>");
+
+    let mut tile_names: Vec<&String> = tilesets.keys().collect();
+    tile_names.sort();
+    for tile_name in tile_names {
+        write_layer(&mut xml, tile_name, &tilesets[tile_name], root_url);
+    }
+    write_tile_matrix_set(&mut xml);
+
+    let _ = write!(
+        xml,
+        "</Contents><ServiceMetadataURL xlink:href=\"{}/wmts/1.0.0/WMTSCapabilities.xml\"/></Capabilities>",
+        escape_xml(root_url)
+    );
+    xml
+}
+
+/// Build an OWS exception report, see OGC WMTS 1.0.0 section 8.2.4
+pub fn exception_report(code: &str, locator: &str, text: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?><ExceptionReport xmlns="http://www.opengis.net/ows/1.1" version="1.1.0" xml:lang="en"><Exception exceptionCode="{code}" locator="{}"><ExceptionText>{}</ExceptionText></Exception></ExceptionReport>"#,
+        escape_xml(locator),
+        escape_xml(text)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discovery::discover_tilesets;
+    use std::path::PathBuf;
+
+    #[test]
+    fn get_wmts_capabilities() {
+        let tilesets = discover_tilesets(&PathBuf::from("./tiles")).tilesets;
+        let xml = get_capabilities(&tilesets, "http://localhost");
+        assert!(xml.contains("<ows:Identifier>geography-class-png</ows:Identifier>"));
+        assert!(xml.contains("<ows:Identifier>openstreetmap/open-streets-dc</ows:Identifier>"));
+        assert!(xml.contains(
+            "template=\"http://localhost/wmts/1.0.0/geography-class-jpg/{Style}/{TileMatrixSet}/{TileMatrix}/{TileRow}/{TileCol}.jpg\""
+        ));
+        assert!(xml.contains("<ows:LowerCorner>-180 -85.0511</ows:LowerCorner>"));
+        assert_eq!(xml.matches("<TileMatrix><ows:Identifier>").count(), 23);
+    }
+}
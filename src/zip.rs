@@ -0,0 +1,390 @@
+//! Tilesets stored as `{z}/{x}/{y}.<ext>` entries of a zip archive, as described by
+//! <https://pkware.cachefly.net/webdocs/casestudies/APPNOTE.TXT>
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use flate2::read::DeflateDecoder;
+use serde_json::Map;
+use tilejson::tilejson;
+
+use crate::directory::{
+    compress_vector_tile, get_tile_format, parse_metadata, set_json_metadata, set_tile_extents,
+    take_scheme,
+};
+use crate::errors::{Error, Result};
+use crate::tiles::{TileMeta, TileSource};
+
+const METADATA_ENTRY: &str = "metadata.json";
+
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+const END_OF_CENTRAL_DIRECTORY_LENGTH: u64 = 22;
+const ZIP64_END_OF_CENTRAL_DIRECTORY: u32 = 0x06064b50;
+const ZIP64_LOCATOR: u32 = 0x07064b50;
+const ZIP64_LOCATOR_LENGTH: u64 = 20;
+const CENTRAL_DIRECTORY_HEADER: u32 = 0x02014b50;
+const CENTRAL_DIRECTORY_HEADER_LENGTH: usize = 46;
+const LOCAL_FILE_HEADER: u32 = 0x04034b50;
+const LOCAL_FILE_HEADER_LENGTH: u64 = 30;
+const ZIP64_EXTRA_FIELD: u16 = 0x0001;
+const MAX_COMMENT_LENGTH: u64 = 0xffff;
+
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+/// Location of a file in a zip archive, as listed by the central directory
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Entry {
+    /// Offset of the local file header
+    offset: u64,
+    compressed_size: u64,
+    size: u64,
+    method: u16,
+}
+
+fn invalid(path: &Path, message: &str) -> Error {
+    Error::InvalidDataFormat(format!("{}: {message}", path.display()))
+}
+
+fn read_u16(data: &[u8], position: usize) -> u16 {
+    u16::from_le_bytes([data[position], data[position + 1]])
+}
+
+fn read_u32(data: &[u8], position: usize) -> u32 {
+    u32::from_le_bytes(data[position..position + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], position: usize) -> u64 {
+    u64::from_le_bytes(data[position..position + 8].try_into().unwrap())
+}
+
+/// Read `length` bytes at `offset` of a file
+fn read_at(path: &Path, offset: u64, length: u64) -> Result<Vec<u8>> {
+    let mut file = File::open(path).map_err(Error::IO)?;
+    let mut data = Vec::new();
+    file.seek(SeekFrom::Start(offset))
+        .and_then(|_| file.take(length).read_to_end(&mut data))
+        .map_err(Error::IO)?;
+    if data.len() as u64 != length {
+        return Err(invalid(path, "unexpected end of file"));
+    }
+    Ok(data)
+}
+
+/// Return the offset and length of the central directory, looking for the end of central
+/// directory record at the end of the archive
+fn find_central_directory(path: &Path) -> Result<(u64, u64)> {
+    let file_length = path.metadata().map_err(Error::IO)?.len();
+    if file_length < END_OF_CENTRAL_DIRECTORY_LENGTH {
+        return Err(invalid(path, "not a zip archive"));
+    }
+    let tail_length = file_length.min(END_OF_CENTRAL_DIRECTORY_LENGTH + MAX_COMMENT_LENGTH);
+    let tail_offset = file_length - tail_length;
+    let tail = read_at(path, tail_offset, tail_length)?;
+    let end = (0..=tail.len() - END_OF_CENTRAL_DIRECTORY_LENGTH as usize)
+        .rev()
+        .find(|&position| read_u32(&tail, position) == END_OF_CENTRAL_DIRECTORY)
+        .ok_or_else(|| invalid(path, "not a zip archive"))?;
+
+    let length = read_u32(&tail, end + 12);
+    let offset = read_u32(&tail, end + 16);
+    if length != u32::MAX && offset != u32::MAX && read_u16(&tail, end + 10) != u16::MAX {
+        return Ok((u64::from(offset), u64::from(length)));
+    }
+
+    // Archives of more than 65535 entries or 4GB keep their central directory location in a
+    // Zip64 end of central directory record, pointed to by a locator right before the end of
+    // central directory record
+    let locator_offset = (tail_offset + end as u64)
+        .checked_sub(ZIP64_LOCATOR_LENGTH)
+        .ok_or_else(|| invalid(path, "missing Zip64 locator"))?;
+    let locator = read_at(path, locator_offset, ZIP64_LOCATOR_LENGTH)?;
+    if read_u32(&locator, 0) != ZIP64_LOCATOR {
+        return Err(invalid(path, "missing Zip64 locator"));
+    }
+    let record = read_at(path, read_u64(&locator, 8), 56)?;
+    if read_u32(&record, 0) != ZIP64_END_OF_CENTRAL_DIRECTORY {
+        return Err(invalid(path, "invalid Zip64 end of central directory"));
+    }
+    Ok((read_u64(&record, 48), read_u64(&record, 40)))
+}
+
+/// Replace the sizes and offset saturated in a central directory header by their value in the
+/// Zip64 extra field
+fn apply_zip64_extra_field(extra: &[u8], entry: &mut Entry) {
+    let mut position = 0;
+    while position + 4 <= extra.len() {
+        let id = read_u16(extra, position);
+        let length = read_u16(extra, position + 2) as usize;
+        let data = &extra[position + 4..(position + 4 + length).min(extra.len())];
+        position += 4 + length;
+        if id != ZIP64_EXTRA_FIELD {
+            continue;
+        }
+        let mut values = data.chunks_exact(8).map(|value| read_u64(value, 0));
+        for field in [
+            &mut entry.size,
+            &mut entry.compressed_size,
+            &mut entry.offset,
+        ] {
+            if *field == u64::from(u32::MAX) {
+                match values.next() {
+                    Some(value) => *field = value,
+                    None => return,
+                }
+            }
+        }
+    }
+}
+
+/// Decode the central directory into the entries of the archive, keyed by name. Directories
+/// and encrypted files are left out.
+fn parse_central_directory(data: &[u8]) -> Option<HashMap<String, Entry>> {
+    let mut entries = HashMap::new();
+    let mut position = 0;
+    while position + CENTRAL_DIRECTORY_HEADER_LENGTH <= data.len() {
+        let header = &data[position..];
+        if read_u32(header, 0) != CENTRAL_DIRECTORY_HEADER {
+            return None;
+        }
+        let flags = read_u16(header, 8);
+        let name_length = read_u16(header, 28) as usize;
+        let extra_length = read_u16(header, 30) as usize;
+        let comment_length = read_u16(header, 32) as usize;
+        let name_start = CENTRAL_DIRECTORY_HEADER_LENGTH;
+        let extra_start = name_start + name_length;
+        let next = extra_start + extra_length + comment_length;
+        if header.len() < next {
+            return None;
+        }
+        position += next;
+
+        let name = String::from_utf8_lossy(&header[name_start..extra_start]);
+        let encrypted = flags & 1 != 0;
+        if name.ends_with('/') || encrypted {
+            continue;
+        }
+        let mut entry = Entry {
+            offset: u64::from(read_u32(header, 42)),
+            compressed_size: u64::from(read_u32(header, 20)),
+            size: u64::from(read_u32(header, 24)),
+            method: read_u16(header, 10),
+        };
+        apply_zip64_extra_field(&header[extra_start..extra_start + extra_length], &mut entry);
+        entries.insert(name.into_owned(), entry);
+    }
+    Some(entries)
+}
+
+/// Split the name of a zip entry into its directory prefix, tile position and extension
+fn parse_tile_name(name: &str) -> Option<(&str, (u32, u32, u32), &str)> {
+    let mut parts = name.rsplitn(4, '/');
+    let (file_name, x, z) = (parts.next()?, parts.next()?, parts.next()?);
+    let prefix = &name[..name.len() - file_name.len() - x.len() - z.len() - 2];
+    let (y, extension) = file_name.split_once('.')?;
+    let z: u32 = z.parse().ok()?;
+    if z > 31 {
+        return None;
+    }
+    Some((prefix, (z, x.parse().ok()?, y.parse().ok()?), extension))
+}
+
+/// A zip archive of tile files, whose central directory is kept in memory. Tiles are named
+/// after their XYZ position or, when `tms` is set, their TMS position.
+#[derive(Debug)]
+pub struct ZipTiles {
+    path: PathBuf,
+    tiles: HashMap<(u32, u32, u32), Entry>,
+    tms: bool,
+}
+
+impl ZipTiles {
+    fn read_entry(&self, entry: &Entry) -> Result<Vec<u8>> {
+        let header = read_at(&self.path, entry.offset, LOCAL_FILE_HEADER_LENGTH)?;
+        if read_u32(&header, 0) != LOCAL_FILE_HEADER {
+            return Err(invalid(&self.path, "invalid local file header"));
+        }
+        let data_offset = entry.offset
+            + LOCAL_FILE_HEADER_LENGTH
+            + u64::from(read_u16(&header, 26))
+            + u64::from(read_u16(&header, 28));
+        let data = read_at(&self.path, data_offset, entry.compressed_size)?;
+        match entry.method {
+            STORED => Ok(data),
+            DEFLATED => {
+                let mut decompressed = Vec::with_capacity(entry.size.min(1 << 24) as usize);
+                DeflateDecoder::new(&data[..])
+                    .read_to_end(&mut decompressed)
+                    .map_err(|err| Error::InvalidDataFormat(err.to_string()))?;
+                Ok(decompressed)
+            }
+            method => Err(invalid(
+                &self.path,
+                &format!("unsupported compression method {method}"),
+            )),
+        }
+    }
+
+    /// Read the tile at the given XYZ position. Vector tiles are returned gzipped, as they
+    /// are stored in mbtiles.
+    pub fn get_tile(&self, z: u32, x: u32, y: u32) -> Result<Vec<u8>> {
+        if z > 31 || y >= 1 << z {
+            return Err(Error::TileNotFound);
+        }
+        let y = if self.tms { (1 << z) - 1 - y } else { y };
+        let entry = self.tiles.get(&(z, x, y)).ok_or(Error::TileNotFound)?;
+        self.read_entry(entry).map(compress_vector_tile)
+    }
+}
+
+/// Index the tiles of a zip archive and build its details from its `metadata.json` entry,
+/// completed by the tiles found. Tiles may sit in a directory of the archive, next to the
+/// `metadata.json` entry.
+pub fn get_tile_details(path: &Path, tile_name: &str) -> Result<TileMeta> {
+    let (offset, length) = find_central_directory(path)?;
+    let mut entries = parse_central_directory(&read_at(path, offset, length)?)
+        .ok_or_else(|| invalid(path, "invalid central directory"))?;
+
+    let metadata_name = entries
+        .keys()
+        .filter(|name| *name == METADATA_ENTRY || name.ends_with("/metadata.json"))
+        .min_by_key(|name| name.len())
+        .cloned();
+    let prefix = match &metadata_name {
+        Some(name) => name[..name.len() - METADATA_ENTRY.len()].to_string(),
+        None => entries
+            .keys()
+            .filter_map(|name| parse_tile_name(name))
+            .map(|(prefix, _, _)| prefix)
+            .min_by_key(|prefix| prefix.len())
+            .unwrap_or_default()
+            .to_string(),
+    };
+
+    let mut zip = ZipTiles {
+        path: path.to_path_buf(),
+        tiles: HashMap::new(),
+        tms: false,
+    };
+    let mut metadata_entries = match metadata_name.and_then(|name| entries.remove(&name)) {
+        Some(entry) => parse_metadata(&zip.read_entry(&entry)?, &path.display().to_string())?,
+        None => Map::new(),
+    };
+    zip.tms = take_scheme(&mut metadata_entries, tile_name);
+
+    let mut extension = String::new();
+    for (name, entry) in entries {
+        if let Some((tile_prefix, position, tile_extension)) = parse_tile_name(&name) {
+            if tile_prefix == prefix {
+                if extension.is_empty() {
+                    extension = tile_extension.to_string();
+                }
+                zip.tiles.insert(position, entry);
+            }
+        }
+    }
+    let (minzoom, maxzoom) = match (
+        zip.tiles.keys().map(|(z, _, _)| *z).min(),
+        zip.tiles.keys().map(|(z, _, _)| *z).max(),
+    ) {
+        (Some(minzoom), Some(maxzoom)) => (minzoom, maxzoom),
+        _ => return Err(Error::MissingTable(format!("{}: no tiles", path.display()))),
+    };
+    let first_tile = zip
+        .tiles
+        .iter()
+        .filter(|((z, _, _), _)| *z == minzoom)
+        .min_by_key(|(position, _)| *position)
+        .map(|(_, entry)| *entry)
+        .unwrap();
+    let tile_format = get_tile_format(&zip.read_entry(&first_tile)?, &extension, tile_name)?;
+    let tms = zip.tms;
+    let tiles: Vec<(u32, u32)> = zip
+        .tiles
+        .keys()
+        .filter(|(z, _, _)| *z == maxzoom)
+        .map(|(_, x, y)| (*x, *y))
+        .collect();
+
+    let mut metadata = TileMeta {
+        source: TileSource::Zip(Arc::new(zip)),
+        path: PathBuf::from(path),
+        tilejson: tilejson! {
+            tilejson: "3.0.0".to_string(),
+            tiles: vec!["".to_string()],
+        },
+        id: tile_name.to_string(),
+        tile_format,
+        grid_format: None,
+        layer_type: None,
+        json: None,
+    };
+    metadata.tilejson.name = Some(tile_name.to_string());
+    set_json_metadata(&mut metadata, metadata_entries);
+    set_tile_extents(&mut metadata, tms, minzoom, maxzoom, tiles);
+
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read;
+
+    use super::*;
+    use crate::utils::DataFormat;
+
+    #[test]
+    fn tile_names() {
+        assert_eq!(parse_tile_name("3/2/1.png"), Some(("", (3, 2, 1), "png")));
+        assert_eq!(
+            parse_tile_name("tiles/3/2/1.pbf"),
+            Some(("tiles/", (3, 2, 1), "pbf"))
+        );
+        assert_eq!(parse_tile_name("metadata.json"), None);
+        assert_eq!(parse_tile_name("tiles/a/2/1.png"), None);
+    }
+
+    #[test]
+    fn zip64_extra_field() {
+        let mut entry = Entry {
+            offset: u64::from(u32::MAX),
+            compressed_size: 10,
+            size: 10,
+            method: STORED,
+        };
+        let mut extra = vec![0x01, 0x00, 0x08, 0x00];
+        extra.extend((1_u64 << 33).to_le_bytes());
+        apply_zip64_extra_field(&extra, &mut entry);
+        assert_eq!(entry.offset, 1 << 33);
+        assert_eq!(entry.size, 10);
+    }
+
+    #[test]
+    fn get_zip_details() {
+        let tile_meta =
+            get_tile_details(&PathBuf::from("./tiles/geography-class-zip.zip"), "zip").unwrap();
+        assert_eq!(tile_meta.tile_format, DataFormat::Png);
+        assert_eq!(tile_meta.tilejson.name.as_deref(), Some("Geography Class"));
+        assert_eq!(tile_meta.tilejson.minzoom, Some(0));
+        assert_eq!(tile_meta.tilejson.maxzoom, Some(1));
+
+        let source = match &tile_meta.source {
+            TileSource::Zip(source) => source,
+            _ => unreachable!(),
+        };
+        let directory = PathBuf::from("./tiles/geography-class-tms");
+        // Zoom 0 is stored and zoom 1 deflated
+        assert_eq!(
+            source.get_tile(0, 0, 0).unwrap(),
+            read(directory.join("0/0/0.png")).unwrap()
+        );
+        assert_eq!(
+            source.get_tile(1, 1, 0).unwrap(),
+            read(directory.join("1/1/1.png")).unwrap()
+        );
+        assert!(matches!(source.get_tile(2, 0, 0), Err(Error::TileNotFound)));
+    }
+}